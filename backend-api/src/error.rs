@@ -7,6 +7,12 @@ pub enum APIError {
     Json(serde_json::Error),
     IO(std::io::Error),
     Backend(backend::Error),
+    BadUpload(String),
+    UploadTooLarge,
+    // every worker is busy and the queue for them is full.
+    Busy,
+    // the worker running the request stopped before it was done.
+    WorkerFailed,
 }
 
 impl std::error::Error for APIError {
@@ -17,6 +23,21 @@ impl std::error::Error for APIError {
             APIError::Json(e) => e.description(),
             APIError::IO(e) => e.description(),
             APIError::Backend(_) => "backend error",
+            APIError::BadUpload(_) => "bad upload",
+            APIError::UploadTooLarge => "upload too large",
+            APIError::Busy => "server busy",
+            APIError::WorkerFailed => "worker failed",
+        }
+    }
+
+    fn cause(&self) -> Option<&std::error::Error> {
+        match self {
+            APIError::Hyper(e) => Some(e),
+            APIError::FromUtf8(e) => Some(e),
+            APIError::Json(e) => Some(e),
+            APIError::IO(e) => Some(e),
+            APIError::Backend(e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -30,7 +51,105 @@ impl From<backend::Error> for APIError {
 
 impl std::fmt::Display for APIError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f,"Error")
+        match self {
+            APIError::Hyper(e) => write!(f, "couldn't read the request: {}", e),
+            APIError::FromUtf8(_) => write!(f, "the request body isn't valid utf-8"),
+            APIError::Json(e) => write!(f, "bad json: {}", e),
+            APIError::IO(e) => write!(f, "io error: {}", e),
+            APIError::Backend(e) => write!(f, "{}", e),
+            APIError::BadUpload(reason) => write!(f, "bad upload: {}", reason),
+            APIError::UploadTooLarge => write!(f, "the upload is too large"),
+            APIError::Busy => write!(f, "the server is too busy to take the request, try again in a moment"),
+            APIError::WorkerFailed => write!(f, "the request failed while it was being worked on"),
+        }
+    }
+}
+
+// the http status and the machine readable code of a backend error.
+fn get_backend_error_info(e: &backend::Error) -> (StatusCode, &'static str) {
+    use backend::Error as E;
+
+    match e {
+        E::ImageNotFound => (StatusCode::NotFound, "image_not_found"),
+        E::RegionNotFound => (StatusCode::NotFound, "region_not_found"),
+        E::SavedSearchNotFound(_) => (StatusCode::NotFound, "saved_search_not_found"),
+        E::SnapshotNotFound(_) => (StatusCode::NotFound, "snapshot_not_found"),
+        E::RootNotFound(_) => (StatusCode::NotFound, "root_not_found"),
+        E::GestureSessionNotFound(_) => (StatusCode::NotFound, "gesture_session_not_found"),
+        E::TagNotFound(_) => (StatusCode::NotFound, "tag_not_found"),
+        E::NoMatchingImages => (StatusCode::NotFound, "no_matching_images"),
+
+        E::BadTag => (StatusCode::BadRequest, "bad_tag"),
+        E::BadRegion => (StatusCode::BadRequest, "bad_region"),
+        E::BadQuery(_) => (StatusCode::BadRequest, "bad_query"),
+        E::BadSavedSearchName => (StatusCode::BadRequest, "bad_saved_search_name"),
+        E::UnsupportedExportVersion(_) => (StatusCode::BadRequest, "unsupported_export_version"),
+        E::BadCaptionOption(_) => (StatusCode::BadRequest, "bad_caption_option"),
+        E::BadSnapshot(_) => (StatusCode::BadRequest, "bad_snapshot"),
+        E::BadRelink(_) => (StatusCode::BadRequest, "bad_relink"),
+        E::BadFileName(_) => (StatusCode::BadRequest, "bad_file_name"),
+        E::NotAnImage => (StatusCode::BadRequest, "not_an_image"),
+        E::BadSchedule(_) => (StatusCode::BadRequest, "bad_schedule"),
+
+        E::ImageExists => (StatusCode::Conflict, "image_exists"),
+        E::TagExists => (StatusCode::Conflict, "tag_exists"),
+        E::FileExists(_) => (StatusCode::Conflict, "file_exists"),
+        E::NoChangesOccured => (StatusCode::Conflict, "no_changes"),
+        E::NothingToUndo => (StatusCode::Conflict, "nothing_to_undo"),
+        E::NothingToRedo => (StatusCode::Conflict, "nothing_to_redo"),
+        E::ImageNotInTrash => (StatusCode::Conflict, "image_not_in_trash"),
+        E::TooManyMissingImages(_, _) => (StatusCode::Conflict, "too_many_missing_images"),
+
+        E::SQLite(_) => (StatusCode::InternalServerError, "database_error"),
+        E::IO(_) => (StatusCode::InternalServerError, "io_error"),
+        E::Notify(_) => (StatusCode::InternalServerError, "watcher_error"),
+        E::CorruptJournal => (StatusCode::InternalServerError, "corrupt_journal"),
+    }
+}
+
+impl APIError {
+    // the http status and the machine readable code of the error. problems with the request are
+    // 400, things that aren't there 404, requests that clash with the library's state 409 and
+    // requests turned away because the server is busy 503.
+    pub fn get_info(&self) -> (StatusCode, &'static str) {
+        match self {
+            APIError::Hyper(hyper::Error::Io(_)) => (StatusCode::InternalServerError, "io_error"),
+            APIError::Hyper(_) => (StatusCode::BadRequest, "bad_request"),
+            APIError::FromUtf8(_) => (StatusCode::BadRequest, "bad_utf8"),
+            APIError::Json(_) => (StatusCode::BadRequest, "bad_json"),
+            APIError::IO(_) => (StatusCode::InternalServerError, "io_error"),
+            APIError::Backend(e) => get_backend_error_info(e),
+            APIError::BadUpload(_) => (StatusCode::BadRequest, "bad_upload"),
+            APIError::UploadTooLarge => (StatusCode::PayloadTooLarge, "upload_too_large"),
+            APIError::Busy => (StatusCode::ServiceUnavailable, "server_busy"),
+            APIError::WorkerFailed => (StatusCode::InternalServerError, "internal_error"),
+        }
+    }
+
+    pub fn get_status(&self) -> StatusCode { self.get_info().0 }
+    pub fn get_code(&self) -> &'static str { self.get_info().1 }
+
+    pub fn get_message(&self) -> String {
+        match self {
+            APIError::BadUpload(reason) => reason.clone(),
+            e => e.to_string(),
+        }
+    }
+
+    pub fn to_error_response(&self) -> models::ErrorResponse {
+        models::ErrorResponse::new(self.get_status().as_u16() as u32, self.get_code().to_string(), self.get_message())
+    }
+
+    pub fn to_response(&self) -> hyper::Response {
+        let payload = match serde_json::to_string(&self.to_error_response()) {
+            Ok(v) => v,
+            Err(_) => format!("{{\"status\": {}}}", self.get_status().as_u16()),
+        };
+
+        hyper::Response::new()
+            .with_status(self.get_status())
+            .with_header(ContentLength(payload.len() as u64))
+            .with_body(payload)
     }
 }
 
@@ -46,3 +165,41 @@ impl From<serde_json::Error> for APIError {
     fn from(e: serde_json::Error) -> Self { APIError::Json(e) }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_statuses() {
+        let bad_json = APIError::from(serde_json::from_str::<models::AddTagsRequest>("{").err().unwrap());
+        assert_eq!(bad_json.get_info(), (StatusCode::BadRequest, "bad_json"));
+
+        let not_found = APIError::from(backend::Error::ImageNotFound);
+        assert_eq!(not_found.get_info(), (StatusCode::NotFound, "image_not_found"));
+
+        let bad_tag = APIError::from(backend::Error::BadTag);
+        assert_eq!(bad_tag.get_info(), (StatusCode::BadRequest, "bad_tag"));
+
+        let exists = APIError::from(backend::Error::FileExists("a.png".to_string()));
+        assert_eq!(exists.get_info(), (StatusCode::Conflict, "file_exists"));
+
+        let io = APIError::from(backend::Error::IO(std::io::Error::new(std::io::ErrorKind::Other, "disk")));
+        assert_eq!(io.get_info(), (StatusCode::InternalServerError, "io_error"));
+
+        assert_eq!(APIError::UploadTooLarge.get_status(), StatusCode::PayloadTooLarge);
+    }
+
+    #[test]
+    fn error_responses() {
+        let e = APIError::BadUpload("no image in the upload".to_string());
+
+        let body: serde_json::Value = serde_json::from_str(&serde_json::to_string(&e.to_error_response()).unwrap()).unwrap();
+        assert_eq!(body["status"], 400);
+        assert_eq!(body["code"], "bad_upload");
+        assert_eq!(body["message"], "no image in the upload");
+
+        let not_found = APIError::from(backend::Error::TagNotFound(3));
+        assert_eq!(not_found.get_message(), "tag 3 doesn't exist");
+        assert_eq!(not_found.to_response().status(), StatusCode::NotFound);
+    }
+}
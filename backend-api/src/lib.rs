@@ -6,7 +6,7 @@ pub extern crate serde;
 pub extern crate models;
 pub extern crate hyper_staticfile;
 
-use futures::future::{FutureResult, ok, err};
+use futures::future::{ok, err};
 use futures::{Future, Stream};
 
 use hyper::header::{AccessControlAllowOrigin, AccessControlAllowMethods, ContentLength};
@@ -16,10 +16,15 @@ use std::error::Error;
 
 pub mod error;
 use self::error::*;
+pub mod upload;
+pub mod workers;
+use self::workers::WorkerPool;
 use std::sync::{Arc, RwLock, Condvar, Mutex};
 
 pub struct Search {
     config: Arc<RwLock<backend::TifariConfig>>,
+    pool: backend::DbPool,
+    workers: WorkerPool,
     staticfile: Arc<RwLock<hyper_staticfile::Static>>,
     scan: Arc<backend::ScanData>,
     scan_signal: Arc<(Mutex<bool>, Condvar)>,
@@ -27,6 +32,10 @@ pub struct Search {
 
 pub struct APINewService {
     config: Arc<RwLock<backend::TifariConfig>>,
+    // the database connections the requests share, so they aren't opened for each request.
+    pool: backend::DbPool,
+    // the threads the requests' database work runs on, off of the event loop.
+    workers: WorkerPool,
     staticfile: Arc<RwLock<hyper_staticfile::Static>>,
     scan: Arc<backend::ScanData>,
     scan_signal: Arc<(Mutex<bool>, Condvar)>,
@@ -41,6 +50,8 @@ impl hyper::server::NewService for APINewService {
     fn new_service(&self) -> Result<Self::Instance, std::io::Error> {
         Ok(Search {
                 config: self.config.clone(), 
+                pool: self.pool.clone(),
+                workers: self.workers.clone(),
                 staticfile: self.staticfile.clone(),
                 scan: self.scan.clone(),
                 scan_signal: self.scan_signal.clone(),
@@ -52,15 +63,29 @@ impl hyper::server::NewService for APINewService {
 impl APINewService {
     pub fn new(
         config: Arc<RwLock<backend::TifariConfig>>, 
+        pool: backend::DbPool,
+        workers: WorkerPool,
         staticfile: Arc<RwLock<hyper_staticfile::Static>>,
         scan: Arc<backend::ScanData>,
         scan_signal: Arc<(Mutex<bool>, Condvar)>,
         ) -> Self {
-        APINewService { config, staticfile, scan, scan_signal }
+        APINewService { config, pool, workers, staticfile, scan, scan_signal }
     }
 }
 
-#[derive(Serialize)]
+// the most images /api/v1/random gives out at once.
+const MAX_RANDOM_COUNT: usize = 100;
+
+// how many drawings /api/v1/practice_log gives out when it's not asked for a number.
+const DEFAULT_PRACTICE_LOG_LIMIT: usize = 100;
+
+// how many related tags are given out when it's not asked for a number.
+const DEFAULT_RELATED_TAGS_LIMIT: usize = 20;
+
+// how many tags are suggested for an image when it's not asked for a number.
+const DEFAULT_SUGGESTED_TAGS_LIMIT: usize = 10;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
 pub enum APIStatusEnum {
     Valid = 0,
     InvalidImageFolder = 1,
@@ -68,6 +93,53 @@ pub enum APIStatusEnum {
     Scanning = 3,
 }
 
+#[derive(Serialize)]
+struct APIRootStatus {
+    name: String,
+    status: i64,
+}
+
+#[derive(Serialize)]
+struct APIStatus {
+    status: i64,
+    // the status of every root. only the default root failing fails the status as a whole,
+    // the images of another root that can't be read are just left alone by the scan.
+    roots: Vec<APIRootStatus>,
+    scan_total: usize,
+    scan_current: usize,
+    // images the last scan would've marked missing, when it was refused for it.
+    refused_missing: usize,
+}
+
+fn get_root_status(root: &backend::ImageRoot) -> APIStatusEnum {
+    match std::fs::metadata(root.get_path()) {
+        Ok(ref metadata) if metadata.is_dir() => APIStatusEnum::Valid,
+        Ok(_) => APIStatusEnum::ImageFolderIsNotAFolder,
+        Err(_) => APIStatusEnum::InvalidImageFolder,
+    }
+}
+
+fn get_status(cfg: &backend::TifariConfig, scan: &backend::ScanData) -> APIStatus {
+    let roots: Vec<(String, APIStatusEnum)> = cfg.get_roots().iter()
+        .map(|root| (root.get_name().clone(), get_root_status(root)))
+        .collect();
+
+    // without a default root, there's nothing to fail on as long as any other root is there.
+    let status =
+        if scan.is_scanning() { APIStatusEnum::Scanning }
+        else if let Some(&(_, status)) = roots.iter().find(|&&(ref name, _)| name == backend::DEFAULT_ROOT_NAME) { status }
+        else if roots.iter().any(|&(_, status)| status == APIStatusEnum::Valid) { APIStatusEnum::Valid }
+        else { APIStatusEnum::InvalidImageFolder };
+
+    APIStatus {
+        status: status as i64,
+        roots: roots.into_iter().map(|(name, status)| APIRootStatus { name, status: status as i64 }).collect(),
+        scan_total: scan.get_scan_total(),
+        scan_current: scan.get_scan_current(),
+        refused_missing: scan.get_refused_missing(),
+    }
+}
+
 #[macro_use]
 extern crate serde_derive;
 
@@ -106,6 +178,62 @@ fn get_resp_with_payload(payload: String) -> hyper::Response {
         .with_body(payload)
 }
 
+// parses the id out of paths like "/api/v1/image/12", given the parts that come before and after the id.
+fn parse_path_id(path: &str, prefix: &str, suffix: &str) -> Option<i64> {
+    if !path.starts_with(prefix) || !path.ends_with(suffix) || path.len() < prefix.len() + suffix.len() {
+        return None;
+    }
+
+    path[prefix.len()..path.len() - suffix.len()].parse().ok()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = ((bytes[i + 1] as char).to_digit(16), (bytes[i + 2] as char).to_digit(16)) {
+                decoded.push((high * 16 + low) as u8);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// the "key=value&..." pairs of a query string.
+fn parse_query_string(query: &str) -> std::collections::HashMap<String, String> {
+    query.split('&')
+        .filter(|pair| pair.len() > 0)
+        .map(|pair| match pair.find('=') {
+            Some(i) => (percent_decode(&pair[..i]), percent_decode(&pair[i + 1..])),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+// writes the xmp sidecars of the given images, if the config asks for it.
+fn sync_sidecars(cfg: &Arc<RwLock<backend::TifariConfig>>, db: &backend::TifariDb, image_ids: &Vec<i64>) {
+    let cfg = cfg.read().unwrap();
+    if !cfg.get_write_xmp_sidecars() {
+        return;
+    }
+
+    for id in image_ids {
+        match db.write_xmp_sidecar(&cfg, *id) {
+            Ok(()) => {},
+            Err(e) => println!("Failed to write xmp sidecar for image {}. Error: {:?}", id, e),
+        }
+    }
+}
+
 impl Search {
     pub fn reload_backend(&self) {
         let &(ref lock, ref condvar) = &(*self.scan_signal);
@@ -129,114 +257,541 @@ impl Service for Search {
         println!("Received request. {}", req.path());
 
         let cfg = self.config.clone();
+        let pool = self.pool.clone();
+        let workers = self.workers.clone();
         let scan = self.scan.clone();
 
         let task1: Box<Future<Item=Self::Response, Error=APIError>> = match (req.method(), req.path()) {
             (Method::Get, "/api/v1/status") => {
-
-                #[derive(Serialize)]
-                struct APIStatus { 
-                    status: i64,
-                    scan_total: usize,
-                    scan_current: usize,
-                }
-
-                let get_status = || {
-                    let is_scanning = scan.is_scanning();
-                    if is_scanning { return APIStatusEnum::Scanning; }
-
-                    let metadata = match std::fs::metadata(cfg.read().unwrap().get_root()) {
-                        Ok(v) => v,
-                        Err(_) => return APIStatusEnum::InvalidImageFolder,
-                    };
-
-                    if !metadata.is_dir() { return APIStatusEnum::ImageFolderIsNotAFolder }
-
-                    APIStatusEnum::Valid
-                };
-
-                let payload = serde_json::to_string(
-                    &APIStatus{
-                        status: get_status() as i64, 
-                        scan_total: scan.get_scan_total(),
-                        scan_current: scan.get_scan_current(),
-                    }).unwrap();
-
+                let payload = serde_json::to_string(&get_status(&cfg.read().unwrap(), &scan)).unwrap();
                 Box::new(ok(get_resp_with_payload(payload)))
             },
 
             (Method::Get, "/api/v1/tag_queue_size") => {
-                let get_response = || {
-                    let db = backend::TifariDb::new(cfg)?;
+                let get_response = move || {
+                    let db = pool.get()?;
                     let num = db.get_num_elements_in_tag_queue()?;
                     let payload = serde_json::to_string(&models::TagQueueSizeResponse::new(num))?;
                     Ok(get_resp_with_payload(payload))
                 };
 
-                Box::new(FutureResult::from(get_response()))
+                workers.spawn(get_response)
             },
             (Method::Post, "/api/v1/remove_tags") => {
+               let cfg_sidecars = cfg.clone();
                Box::new(req_to_json::<models::RemoveTagsRequest>(req)
-                    .and_then(move |query| {
-                        match backend::TifariDb::new(cfg) {
-                            Ok(db) => Ok((query, db)),
-                            Err(e) => Err(APIError::from(e)),
-                       }
-                    })
-                    .and_then(|(query, mut db)| {
-
-                        for tag in query.get_tag_ids() {
-                            for img in query.get_image_ids() {
-                                match db.remove_tag(*img, *tag) {
-                                    Ok(()) => (),
-                                    Err(e) => println!("remove tags failed: {:?}", e),
-                                };
-                            }
-                        }
-                        ok(get_default_success_response())
-                    })
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.remove_tags(query.get_image_ids(), query.get_tag_ids())?;
+                        sync_sidecars(&cfg_sidecars, &db, query.get_image_ids());
+                        Ok(get_default_success_response())
+                    }))
                 )
             },
             (Method::Get, "/api/v1/get_all_tags") => {
-                let get_response = || {
-                    let db = backend::TifariDb::new(cfg)?;
+                let get_response = move || {
+                    let db = pool.get()?;
                     let tags = db.get_all_tags()?;
                     let payload = serde_json::to_string(&tags)?;
 
                     Ok(get_resp_with_payload(payload))
                 };
 
-                Box::new(FutureResult::from(get_response()))
+                workers.spawn(get_response)
             },
             (Method::Post, "/api/v1/add_tags") => {
+                let cfg_sidecars = cfg.clone();
+
                 Box::new(req_to_json::<models::AddTagsRequest>(req)
-                    .and_then(move |query| {
-                        match backend::TifariDb::new(cfg) {
-                            Ok(db) => Ok((query, db)),
-                            Err(e) => Err(APIError::from(e)),
-                       }
+                    .and_then(move |query| workers.spawn(move || -> Result<Vec<models::Tag>, APIError> {
+                        let mut db = pool.get()?;
+                        let tags = db.give_tags(query.get_image_ids(), query.get_tags())?;
+                        sync_sidecars(&cfg_sidecars, &db, query.get_image_ids());
+                        Ok(tags)
+                    }))
+                    .and_then(|tags| {
+                        conv_result(serde_json::to_string(&tags))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            }
+            (Method::Post, "/api/v1/rename_tag") => {
+                Box::new(req_to_json::<models::RenameTagRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.rename_tag(query.get_tag_id(), query.get_name())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/merge_tags") => {
+                Box::new(req_to_json::<models::MergeTagsRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.merge_tags(query.get_source_id(), query.get_target_id())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Get, "/api/v1/snapshots") => {
+                let get_response = move || {
+                    let snapshots = backend::list_snapshots(&cfg.read().unwrap())?;
+                    let payload = serde_json::to_string(&snapshots)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/snapshot") => {
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let snapshot = db.make_snapshot(&cfg.read().unwrap())?;
+                    let payload = serde_json::to_string(&snapshot)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/restore_snapshot") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::RestoreSnapshotRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.restore_snapshot(&cfg1.read().unwrap(), request.get_name()))
+                    }))
+                    .and_then(|previous| {
+                        conv_result(serde_json::to_string(&previous))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, "/api/v1/integrity") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let report = db.check_integrity(false)?;
+                    let payload = serde_json::to_string(&report)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/repair") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let report = db.check_integrity(true)?;
+                    let payload = serde_json::to_string(&report)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Get, "/api/v1/history") => {
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let history = db.get_history(100)?;
+                    let payload = serde_json::to_string(&history)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/undo") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let entry = db.undo()?;
+                    let payload = serde_json::to_string(&entry)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/redo") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let entry = db.redo()?;
+                    let payload = serde_json::to_string(&entry)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Get, "/api/v1/export") => {
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let doc = db.export_library()?;
+                    let payload = serde_json::to_string(&doc)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/import") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::ExportDocument>(req)
+                    .and_then(move |doc| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.import_library(&cfg1.read().unwrap(), &doc))
+                    }))
+                    .and_then(|report| {
+                        conv_result(serde_json::to_string(&report))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, "/api/v1/missing") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let missing = db.get_missing_images(&cfg.read().unwrap())?;
+                    let payload = serde_json::to_string(&missing)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/relink") => {
+                Box::new(req_to_json::<models::RelinkRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.relink_image(request.get_missing_id(), request.get_new_id()))
+                    }))
+                    .and_then(|image| {
+                        conv_result(serde_json::to_string(&image))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, "/api/v1/trash") => {
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let trash = db.get_trash()?;
+                    let payload = serde_json::to_string(&trash)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/trash_image") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::TrashImageRequest>(req)
+                    .and_then(move |request| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.trash_image(&cfg1.read().unwrap(), request.get_image_id())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/restore_image") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::RestoreImageRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.restore_image(&cfg1.read().unwrap(), request.get_image_id()))
+                    }))
+                    .and_then(|image| {
+                        conv_result(serde_json::to_string(&image))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/empty_trash") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let num = db.empty_trash(&cfg.read().unwrap())?;
+                    let payload = serde_json::to_string(&models::CountResponse::new(num))?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Get, "/api/v1/random") => {
+                // GET /api/v1/random?q=figure -nsfw&count=5&seed=42&exclude_recent=10
+                let params = parse_query_string(req.query().unwrap_or(""));
+
+                let get_response = move || {
+                    let q = params.get("q").cloned().unwrap_or_default();
+                    let count = params.get("count").and_then(|v| v.parse().ok()).unwrap_or(1);
+                    let seed = params.get("seed").and_then(|v| v.parse().ok());
+                    let exclude_recent = params.get("exclude_recent").and_then(|v| v.parse().ok()).unwrap_or(0);
+
+                    let mut db = pool.get()?;
+                    let images = db.get_random_images(
+                        &q.split_whitespace().collect(), std::cmp::min(count, MAX_RANDOM_COUNT), seed, exclude_recent)?;
+
+                    let payload = serde_json::to_string(&images)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Get, "/api/v1/gesture_sessions") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let sessions = db.get_gesture_sessions()?;
+                    let payload = serde_json::to_string(&sessions)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/gesture_sessions") => {
+                Box::new(req_to_json::<models::CreateGestureSessionRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.create_gesture_session(request.get_query(), request.get_schedule(), request.get_seed()))
+                    }))
+                    .and_then(|session| {
+                        conv_result(serde_json::to_string(&session))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, path) if parse_path_id(path, "/api/v1/gesture_session/", "").is_some() => {
+                let id = parse_path_id(path, "/api/v1/gesture_session/", "").unwrap();
+
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let session = db.get_gesture_session(id)?;
+                    let payload = serde_json::to_string(&session)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/pause_gesture_session") => {
+                Box::new(req_to_json::<models::GestureSessionRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.pause_gesture_session(request.get_session_id()))
+                    }))
+                    .and_then(|session| {
+                        conv_result(serde_json::to_string(&session))
                     })
-                    .and_then(|(query, mut db)| {
-                        let mut tags = vec![];
-
-                        for tag in query.get_tags() {
-                            for id in query.get_image_ids() {
-                                match db.give_tag(*id, &tag) {
-                                    Ok(id) => tags.push(models::Tag::new(id, tag.clone())),
-                                    Err(e) => println!("add_tags req failed for tag {}. {:?}", tag,  e),
-                                };
-                            }
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/resume_gesture_session") => {
+                Box::new(req_to_json::<models::GestureSessionRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.resume_gesture_session(request.get_session_id()))
+                    }))
+                    .and_then(|session| {
+                        conv_result(serde_json::to_string(&session))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/skip_gesture_image") => {
+                Box::new(req_to_json::<models::GestureSessionRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.skip_gesture_image(request.get_session_id()))
+                    }))
+                    .and_then(|session| {
+                        conv_result(serde_json::to_string(&session))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/end_gesture_session") => {
+                Box::new(req_to_json::<models::GestureSessionRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.end_gesture_session(request.get_session_id()))
+                    }))
+                    .and_then(|session| {
+                        conv_result(serde_json::to_string(&session))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, path) if parse_path_id(path, "/api/v1/image/", "/suggested_tags").is_some() => {
+                // GET /api/v1/image/12/suggested_tags?limit=10
+                let id = parse_path_id(path, "/api/v1/image/", "/suggested_tags").unwrap();
+                let params = parse_query_string(req.query().unwrap_or(""));
+
+                let get_response = move || {
+                    let limit = params.get("limit").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_SUGGESTED_TAGS_LIMIT);
+
+                    let db = pool.get()?;
+                    let suggestions = db.get_suggested_tags(&cfg.read().unwrap(), id, limit)?;
+                    let payload = serde_json::to_string(&suggestions)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Get, path) if parse_path_id(path, "/api/v1/image/", "/stats").is_some() => {
+                let id = parse_path_id(path, "/api/v1/image/", "/stats").unwrap();
+
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let stats = db.get_image_stats(id)?;
+                    let payload = serde_json::to_string(&stats)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/record_view") => {
+                Box::new(req_to_json::<models::RecordViewRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.record_image_view(request.get_image_id()))
+                    }))
+                    .and_then(|stats| {
+                        conv_result(serde_json::to_string(&stats))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/record_drawing") => {
+                Box::new(req_to_json::<models::RecordDrawingRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.record_drawing(request.get_image_id(), request.get_seconds()))
+                    }))
+                    .and_then(|stats| {
+                        conv_result(serde_json::to_string(&stats))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, "/api/v1/practice_log") => {
+                // GET /api/v1/practice_log?limit=50
+                let params = parse_query_string(req.query().unwrap_or(""));
+
+                let get_response = move || {
+                    let limit = params.get("limit").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_PRACTICE_LOG_LIMIT);
+
+                    let db = pool.get()?;
+                    let log = db.get_practice_log(limit)?;
+                    let payload = serde_json::to_string(&log)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/images") => {
+                let cfg1 = cfg.clone();
+                let content_type = req.headers().get_raw("Content-Type")
+                    .and_then(|raw| raw.one())
+                    .map(|value| String::from_utf8_lossy(value).to_string());
+                let query = req.query().map(|q| q.to_string());
+
+                Box::new(req.body()
+                    .map_err(APIError::Hyper)
+                    .fold(vec![], |mut body, chunk| {
+                        if body.len() + chunk.len() > upload::MAX_UPLOAD_SIZE {
+                            return Err(APIError::UploadTooLarge);
                         }
 
-                        ok(tags)
+                        body.extend_from_slice(&chunk);
+                        Ok(body)
                     })
-                    .and_then(|tags| {
-                        conv_result(serde_json::to_string(&tags))
+                    .and_then(move |body| {
+                        upload::parse_upload(content_type.as_ref().map(|t| &t[..]), query.as_ref().map(|q| &q[..]), body)
+                    })
+                    .and_then(move |upload| workers.spawn(move || -> Result<models::Image, APIError> {
+                        let mut db = pool.get()?;
+                        let image = db.add_image_file(
+                            &cfg1.read().unwrap(), &upload.root, &upload.file_name, &upload.data, &upload.tags)?;
+
+                        if upload.tags.len() > 0 {
+                            sync_sidecars(&cfg1, &db, &vec![image.get_id()]);
+                        }
+                        Ok(image)
+                    }))
+                    .and_then(|image| {
+                        conv_result(serde_json::to_string(&image))
                     })
                     .and_then(|payload| {
                         ok(get_resp_with_payload(payload))
                 }))
-            }
+            },
+            (Method::Post, "/api/v1/move_image") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::MoveImageRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        conv_result(db.move_image(&cfg1.read().unwrap(), request.get_image_id(), request.get_path()))
+                    }))
+                    .and_then(|image| {
+                        conv_result(serde_json::to_string(&image))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/sync_xmp") => {
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let num = db.sync_xmp_sidecars(&cfg.read().unwrap())?;
+                    let payload = serde_json::to_string(&models::CountResponse::new(num))?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/import_keywords") => {
+                let get_response = move || {
+                    let mut db = pool.get()?;
+                    let num = db.import_all_embedded_keywords(&cfg.read().unwrap())?;
+                    let payload = serde_json::to_string(&models::CountResponse::new(num))?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/import_booru") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::BooruImportRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        let cfg = cfg1.read().unwrap();
+                        conv_result(match request.get_posts() {
+                            Some(posts) => db.import_booru_posts(&cfg, posts, request.get_dry_run()),
+                            None => db.import_booru_sidecars(&cfg, request.get_dry_run()),
+                        })
+                    }))
+                    .and_then(|report| {
+                        conv_result(serde_json::to_string(&report))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/sync_captions") => {
+                let cfg1 = cfg.clone();
+
+                Box::new(req_to_json::<models::CaptionSyncRequest>(req)
+                    .and_then(move |request| workers.spawn(move || {
+                        let options = backend::CaptionOptions::from_request(&request, &cfg1.read().unwrap())?;
+                        let mut db = pool.get()?;
+                        conv_result(db.sync_captions(&cfg1.read().unwrap(), &options))
+                    }))
+                    .and_then(|report| {
+                        conv_result(serde_json::to_string(&report))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
             (Method::Get, "/api/v1/config") => {
 
                 let cfg_lock = cfg.read().unwrap();
@@ -246,40 +801,55 @@ impl Service for Search {
                 Box::new(ok(get_resp_with_payload(payload)))
             },
             (Method::Post, "/api/v1/config") => {
-                let cfg1 = cfg.clone();
-                let cfg2 = cfg.clone();
-                let cfg3 = cfg.clone();
                 let staticfile = self.staticfile.clone();
 
-                let res = req_to_json::<std::collections::HashMap<String, String>>(req)
-                     .and_then(move |patch| {
+                let res = req_to_json::<std::collections::HashMap<String, serde_json::Value>>(req)
+                     .and_then(move |patch| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                         // the config only deals with strings, so other json values are passed on in their text form.
+                         let patch = patch.into_iter()
+                             .map(|(k, v)| match v {
+                                 serde_json::Value::String(s) => (k, s),
+                                 other => (k, other.to_string()),
+                             })
+                             .collect::<std::collections::HashMap<String, String>>();
+
+                         // the roots that go away are worked out on a copy, so that the config is
+                         // left as it was if their images can't be marked missing.
+                         let old_cfg = cfg.read().unwrap().clone();
+                         let mut new_cfg = old_cfg.clone();
+                         new_cfg.update(patch.clone());
+
+                         // the default root only goes away by clearing image_root, which is more likely
+                         // a mistake than a reason to lose track of every image in it.
+                         let removed_roots = old_cfg.get_roots().into_iter()
+                             .map(|root| root.get_name().clone())
+                             .filter(|name| name != backend::DEFAULT_ROOT_NAME && new_cfg.get_root_by_name(name).is_none())
+                             .collect::<Vec<String>>();
+
+                         if !removed_roots.is_empty() {
+                             let db = pool.get()?;
+                             for name in &removed_roots {
+                                 let num = db.mark_root_missing(name)?;
+                                 println!("Removed root {}, marked {} of it's images as missing.", name, num);
+                             }
+                         }
+
                          // modify the config
-                         let mut cfg = cfg1.write().unwrap();
                          let update_static = patch.contains_key("image_root");
-                         cfg.update(patch);
+                         let payload = {
+                             let mut cfg = cfg.write().unwrap();
+                             cfg.update(patch);
+                             serde_json::to_string(&*cfg)?
+                         };
 
-                         ok(update_static)
-                     })
-                     .and_then(move |update_static| {
                          if update_static {
                             let mut staticfile = staticfile.write().unwrap();
-                            *staticfile = make_staticfile(cfg3);
+                            *staticfile = make_staticfile(cfg);
                          }
 
-                         ok(())
-                     })
-                     .and_then(move |()| {
-                         let cfg_lock = cfg2.read().unwrap();
-                         let cfg = &(*cfg_lock); // unwraps the lock
-
-                         conv_result(serde_json::to_string(&cfg))
-                     })
-                     .and_then(move |payload| {
-                        conv_result(std::fs::write("config.json", &payload))
-                     })
-                     .and_then(move |()| {
-                         ok(get_default_success_response())
-                     });
+                         std::fs::write("config.json", &payload)?;
+                         Ok(get_default_success_response())
+                     }));
 
                 Box::new(res)
             },
@@ -287,30 +857,64 @@ impl Service for Search {
                 self.reload_backend();
                 Box::new(ok(get_default_success_response()))
             },
+            (Method::Get, "/api/v1/force_reload") => {
+                scan.set_force_next(true);
+                self.reload_backend();
+                Box::new(ok(get_default_success_response()))
+            },
             
             (Method::Get, "/api/v1/tag_queue") => {
+                // GET /api/v1/tag_queue?order=oldest&root=nas
+                let params = parse_query_string(req.query().unwrap_or(""));
+
+                let get_response = move || {
+                    let order = match params.get("order") {
+                        Some(name) => match backend::TagQueueOrder::parse(name) {
+                            Some(v) => v,
+                            None => return Err(APIError::from(backend::Error::BadQuery(name.clone()))),
+                        },
+                        None => backend::TagQueueOrder::default(),
+                    };
 
-                let get_response = || {
-                    let db = backend::TifariDb::new(cfg)?;
-                    let queue = db.get_tag_queue()?;
+                    let db = pool.get()?;
+                    let queue = db.get_sorted_tag_queue(order, params.get("root").map(|r| &r[..]))?;
                     let payload = serde_json::to_string(&queue)?;
                     Ok(get_resp_with_payload(payload))
                 };
 
-                Box::new(FutureResult::from(get_response()))
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/defer_image") => {
+                Box::new(req_to_json::<models::DeferImageRequest>(req)
+                    .and_then(move |request| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.defer_image(request.get_image_id(), request.get_until())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/mark_for_review") => {
+                Box::new(req_to_json::<models::QueueImageRequest>(req)
+                    .and_then(move |request| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.mark_image_for_review(request.get_image_id())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/mark_done") => {
+                Box::new(req_to_json::<models::QueueImageRequest>(req)
+                    .and_then(move |request| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.mark_image_done(request.get_image_id())?;
+                        Ok(get_default_success_response())
+                    })))
             },
             (Method::Post, "/api/v1/search") => {
                 Box::new(req_to_json::<Vec<String>>(req)
-                    .and_then(move |query| {
-                        match backend::TifariDb::new(cfg) {
-                            Ok(db) => Ok((query, db)),
-                            Err(e) => Err(APIError::from(e)),
-                       }
-                    })
-                    .and_then(|(query, db)| {
+                    .and_then(move |query| workers.spawn(move || {
+                        let db = pool.get()?;
                         let query_str: Vec<&str> = query.iter().map(|e| &e[..]).collect();
                         conv_result(db.search(&query_str))
-                    })
+                    }))
                     .and_then(|images| {
                         conv_result(serde_json::to_string(&images))
                     })
@@ -318,6 +922,202 @@ impl Service for Search {
                         ok(get_resp_with_payload(payload))
                 }))
             },
+            (Method::Get, path) if parse_path_id(path, "/api/v1/tags/", "/related").is_some() => {
+                // GET /api/v1/tags/12/related?limit=10
+                let id = parse_path_id(path, "/api/v1/tags/", "/related").unwrap();
+                let params = parse_query_string(req.query().unwrap_or(""));
+
+                let get_response = move || {
+                    let limit = params.get("limit").and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_RELATED_TAGS_LIMIT);
+
+                    let db = pool.get()?;
+                    let related = db.get_related_tags(id, limit)?;
+                    let payload = serde_json::to_string(&related)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/search_related_tags") => {
+                Box::new(req_to_json::<Vec<String>>(req)
+                    .and_then(move |query| workers.spawn(move || {
+                        let db = pool.get()?;
+                        let query_str: Vec<&str> = query.iter().map(|e| &e[..]).collect();
+                        conv_result(db.get_search_related_tags(&query_str, DEFAULT_RELATED_TAGS_LIMIT))
+                    }))
+                    .and_then(|related| {
+                        conv_result(serde_json::to_string(&related))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Get, path) if parse_path_id(path, "/api/v1/image/", "").is_some() => {
+                let id = parse_path_id(path, "/api/v1/image/", "").unwrap();
+
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let image = db.get_image_from_db(id)?;
+                    let payload = serde_json::to_string(&image)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Get, "/api/v1/saved_searches") => {
+                let get_response = move || {
+                    let db = pool.get()?;
+                    let searches = db.get_all_saved_searches()?;
+                    let payload = serde_json::to_string(&searches)?;
+                    Ok(get_resp_with_payload(payload))
+                };
+
+                workers.spawn(get_response)
+            },
+            (Method::Post, "/api/v1/saved_searches") => {
+                Box::new(req_to_json::<models::SaveSearchRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<String, APIError> {
+                        let mut db = pool.get()?;
+                        db.save_search(query.get_name(), query.get_query(), query.get_sort_order())?;
+
+                        let search = db.get_saved_search(query.get_name())?;
+                        conv_result(serde_json::to_string(&search))
+                    }))
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/remove_saved_search") => {
+                Box::new(req_to_json::<models::RemoveSavedSearchRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.remove_saved_search(query.get_name())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/run_saved_search") => {
+                Box::new(req_to_json::<models::RunSavedSearchRequest>(req)
+                    .and_then(move |query| workers.spawn(move || {
+                        let db = pool.get()?;
+                        conv_result(db.run_saved_search(query.get_name(), query.get_offset(), query.get_limit()))
+                    }))
+                    .and_then(|page| {
+                        conv_result(serde_json::to_string(&page))
+                    })
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/set_metadata") => {
+                Box::new(req_to_json::<models::SetMetadataRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<String, APIError> {
+                        let mut db = pool.get()?;
+                        db.update_image_metadata(
+                            query.get_image_id(),
+                            query.get_sources(),
+                            query.get_artist(),
+                            query.get_license())?;
+
+                        let image = db.get_image_from_db(query.get_image_id())?;
+                        conv_result(serde_json::to_string(&image))
+                    }))
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/add_region") => {
+                Box::new(req_to_json::<models::AddRegionRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<String, APIError> {
+                        let mut db = pool.get()?;
+                        let region_id = db.add_region(
+                            query.get_image_id(),
+                            query.get_x(), query.get_y(),
+                            query.get_width(), query.get_height(),
+                            query.get_note(),
+                            query.get_tags())?;
+
+                        let region = db.get_region(region_id)?;
+                        conv_result(serde_json::to_string(&region))
+                    }))
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/update_region") => {
+                Box::new(req_to_json::<models::UpdateRegionRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.update_region(
+                            query.get_region_id(),
+                            query.get_x(), query.get_y(),
+                            query.get_width(), query.get_height(),
+                            query.get_note())?;
+
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/remove_region") => {
+                Box::new(req_to_json::<models::RemoveRegionRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        db.erase_region(query.get_region_id())?;
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Post, "/api/v1/add_region_tags") => {
+                Box::new(req_to_json::<models::AddRegionTagsRequest>(req)
+                    .and_then(move |query| workers.spawn(move || {
+                        let mut db = pool.get()?;
+                        let tags = db.give_region_tags(query.get_region_id(), query.get_tags())?;
+                        conv_result(serde_json::to_string(&tags))
+                    }))
+                    .and_then(|payload| {
+                        ok(get_resp_with_payload(payload))
+                }))
+            },
+            (Method::Post, "/api/v1/remove_region_tags") => {
+                Box::new(req_to_json::<models::RemoveRegionTagsRequest>(req)
+                    .and_then(move |query| workers.spawn(move || -> Result<hyper::Response, APIError> {
+                        let mut db = pool.get()?;
+                        for tag in query.get_tag_ids() {
+                            db.remove_region_tag(query.get_region_id(), *tag)?;
+                        }
+
+                        Ok(get_default_success_response())
+                    })))
+            },
+            (Method::Get, _) if req.path().starts_with("/roots/") => {
+                // the images of every root are served under /roots/{name}/{path}.
+                let (name, path) = {
+                    let rest = &req.path()["/roots/".len()..];
+                    match rest.find('/') {
+                        Some(i) => (rest[..i].to_string(), rest[i..].to_string()),
+                        None => (rest.to_string(), "/".to_string()),
+                    }
+                };
+
+                let root = cfg.read().unwrap().get_root_by_name(&name);
+                let uri = path.parse::<hyper::Uri>();
+
+                let result: Box<Future<Item=Self::Response, Error=APIError>> = match (root, uri) {
+                    (Some(root), Ok(uri)) => {
+                        let mut inner = Request::new(Method::Get, uri);
+                        *inner.headers_mut() = req.headers().clone();
+
+                        Box::new(hyper_staticfile::Static::new(std::path::Path::new(root.get_path()))
+                            .call(inner)
+                            .then(|result: Result<hyper::Response, hyper::Error>| {
+                                match result {
+                                    Ok(resp) => ok(resp),
+                                    Err(e) => err(APIError::from(e)),
+                                }
+                            }))
+                    },
+                    _ => Box::new(ok(Response::new().with_status(StatusCode::NotFound))),
+                };
+
+                result
+            },
             (_, _) => {
                 println!("Redirecting to staticfile.");
 
@@ -338,10 +1138,7 @@ impl Service for Search {
         let finalized = task1.then(|result: Result<Self::Response, APIError>| {
             ok(match result {
                 Ok(resp) => resp,
-                Err(e) => { println!("Error: {:?}", e); Self::Response::new()
-                    .with_status(StatusCode::InternalServerError)
-                    .with_header(ContentLength(e.description().len() as u64))
-                    .with_body(e.description().to_string()) },
+                Err(e) => { println!("Error: {:?}", e); e.to_response() },
             })
         })
             .and_then(|req| {
@@ -377,6 +1174,61 @@ fn make_staticfile(cfg: Arc<RwLock<backend::TifariConfig>>) -> hyper_staticfile:
     hyper_staticfile::Static::new(std::path::Path::new(cfg.read().unwrap().get_root()))
 }
 
+// takes a snapshot of the database and prints it's name.
+pub fn run_snapshot(config: backend::TifariConfig) -> Result<(), APIError> {
+    let cfg = Arc::new(RwLock::new(config));
+    let db = backend::TifariDb::new(cfg.clone())?;
+    let snapshot = db.make_snapshot(&cfg.read().unwrap())?;
+
+    println!("Wrote snapshot {}", snapshot.get_name());
+    Ok(())
+}
+
+// replaces the database with the given snapshot. the server shouldn't be running while doing this.
+pub fn run_restore(config: backend::TifariConfig, name: &str) -> Result<(), APIError> {
+    let cfg = Arc::new(RwLock::new(config));
+    let mut db = backend::TifariDb::new(cfg.clone())?;
+    let previous = db.restore_snapshot(&cfg.read().unwrap(), name)?;
+
+    println!("Restored snapshot {}. The previous database was saved as {}", name, previous.get_name());
+    Ok(())
+}
+
+// checks the database for inconsistencies and prints them, fixing them when repair is set.
+pub fn run_integrity_check(config: backend::TifariConfig, repair: bool) -> Result<(), APIError> {
+    let cfg = Arc::new(RwLock::new(config));
+    let mut db = backend::TifariDb::new(cfg)?;
+    let report = db.check_integrity(repair)?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if repair && !report.get_repaired() {
+        println!("SQLite's integrity check failed, so nothing was repaired. Restore a snapshot instead.");
+    }
+
+    Ok(())
+}
+
+// hashes the images the scan found, on a copy of the config so that reading the files doesn't
+// keep it locked.
+// scans the roots and hashes the new images. it works on a copy of the config, so that changing
+// the config doesn't wait for the scan to finish.
+fn scan_roots(db: &mut backend::TifariDb, cfg: &Arc<RwLock<backend::TifariConfig>>, scan: &Arc<backend::ScanData>) {
+    let cfg = cfg.read().unwrap().clone();
+    db.reload_root(&cfg, scan.clone());
+    hash_new_images(db, &cfg);
+}
+
+fn hash_new_images(db: &mut backend::TifariDb, cfg: &backend::TifariConfig) {
+    if let Err(e) = db.ensure_image_hashes(cfg) {
+        println!("Failed to hash new images. Error: {:?}", e);
+    }
+
+    if let Err(e) = db.ensure_visual_hashes(cfg) {
+        println!("Failed to take the visual hashes of new images. Error: {:?}", e);
+    }
+}
+
 pub fn run_server(config: backend::TifariConfig) {
 
     use std::thread;
@@ -395,27 +1247,203 @@ pub fn run_server(config: backend::TifariConfig) {
 
             let mut db = backend::TifariDb::new(cfg.clone()).unwrap();
             db.setup_tables().unwrap();
-            db.reload_root(cfg.read().unwrap().get_root(), scan.clone());
+            scan_roots(&mut db, &cfg, &scan);
 
             loop{
-                let &(ref lock, ref condvar) = &(*scan_signal);
-                let mut should_scan = lock.lock().unwrap();
+                {
+                    let &(ref lock, ref condvar) = &(*scan_signal);
+                    let mut should_scan = lock.lock().unwrap();
 
-                while !*should_scan {
-                    should_scan = condvar.wait(should_scan).unwrap();
-                }
+                    while !*should_scan {
+                        should_scan = condvar.wait(should_scan).unwrap();
+                    }
 
-                db.reload_root(cfg.read().unwrap().get_root(), scan.clone());
+                    // taken back before the scan and not held through it, so that asking for a
+                    // reload never waits on the scan. one asked for midway gets a scan of it's own.
+                    *should_scan = false;
+                }
 
-                *should_scan = false;
+                scan_roots(&mut db, &cfg, &scan);
             }
         });
     }
 
     let staticfile = Arc::new(RwLock::new(make_staticfile(cfg.clone())));
 
-    let service = APINewService::new(cfg, staticfile, scan, scan_signal);
+    let pool = backend::DbPool::new(cfg.clone(), backend::DEFAULT_POOL_SIZE);
+    let workers = WorkerPool::new(workers::DEFAULT_WORKER_THREADS, workers::DEFAULT_WORKER_QUEUE_SIZE);
+
+    let service = APINewService::new(cfg, pool, workers, staticfile, scan, scan_signal);
     let server = hyper::server::Http::new().bind(&addr, service).unwrap();
 
     server.run().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    fn send_request(addr: &std::net::SocketAddr, request: &str) -> std::net::TcpStream {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream
+    }
+
+    fn read_response(mut stream: std::net::TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // runs the api on a port of it's own until the returned sender is used.
+    fn start_server(cfg: Arc<RwLock<backend::TifariConfig>>, workers: WorkerPool) -> (std::net::SocketAddr, futures::sync::oneshot::Sender<()>) {
+        let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+        let (shutdown, shutdown_rx) = futures::sync::oneshot::channel::<()>();
+
+        std::thread::spawn(move || {
+            let pool = backend::DbPool::new(cfg.clone(), 2);
+            let staticfile = Arc::new(RwLock::new(make_staticfile(cfg.clone())));
+            let scan = Arc::new(backend::ScanData::default());
+            let scan_signal = Arc::new((Mutex::new(false), Condvar::new()));
+            let service = APINewService::new(cfg, pool, workers, staticfile, scan, scan_signal);
+
+            let server = hyper::server::Http::new().bind(&"127.0.0.1:0".parse().unwrap(), service).unwrap();
+            addr_tx.send(server.local_addr().unwrap()).unwrap();
+            server.run_until(shutdown_rx.then(|_| -> Result<(), ()> { Ok(()) })).unwrap();
+        });
+
+        (addr_rx.recv().unwrap(), shutdown)
+    }
+
+    // runs a request through the api without a server in between, returning the status and body.
+    fn call_api(cfg: Arc<RwLock<backend::TifariConfig>>, request: Request) -> (StatusCode, String) {
+        use hyper::server::NewService;
+
+        let staticfile = Arc::new(RwLock::new(make_staticfile(cfg.clone())));
+        let scan = Arc::new(backend::ScanData::default());
+        let scan_signal = Arc::new((Mutex::new(false), Condvar::new()));
+        let pool = backend::DbPool::new(cfg.clone(), 2);
+        let service = APINewService::new(cfg, pool, WorkerPool::new(1, 4), staticfile, scan, scan_signal)
+            .new_service().unwrap();
+
+        let response = service.call(request).wait().unwrap();
+        let status = response.status();
+        let body = response.body().concat2().wait().unwrap();
+
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    #[test]
+    fn unknown_ids_not_found() {
+        let dir = std::env::temp_dir().join("tifari_not_found_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut config = backend::TifariConfig::default();
+        let mut patch = std::collections::HashMap::new();
+        patch.insert("image_root".to_string(), dir.to_str().unwrap().to_string());
+        patch.insert("db_root".to_string(), dir.join("test.db").to_str().unwrap().to_string());
+        config.update(patch);
+        let cfg = Arc::new(RwLock::new(config));
+
+        backend::TifariDb::new(cfg.clone()).unwrap().setup_tables().unwrap();
+
+        let (status, body) = call_api(cfg.clone(), Request::new(Method::Get, "/api/v1/image/1000".parse().unwrap()));
+        assert_eq!(status, StatusCode::NotFound);
+        assert!(body.contains("\"image_not_found\""), "{}", body);
+
+        let mut request = Request::new(Method::Post, "/api/v1/rename_tag".parse().unwrap());
+        request.set_body("{ \"tag_id\": 1000, \"name\": \"hand\" }");
+        let (status, body) = call_api(cfg.clone(), request);
+        assert_eq!(status, StatusCode::NotFound);
+        assert!(body.contains("\"tag_not_found\""), "{}", body);
+
+        let mut request = Request::new(Method::Post, "/api/v1/remove_region_tags".parse().unwrap());
+        request.set_body("{ \"region_id\": 1000, \"tag_ids\": [1000] }");
+        let (status, body) = call_api(cfg, request);
+        assert_eq!(status, StatusCode::NotFound);
+        assert!(body.contains("\"tag_not_found\""), "{}", body);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn status_of_roots() {
+        let dir = std::env::temp_dir().join("tifari_status_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut config = backend::TifariConfig::default();
+        let mut patch = std::collections::HashMap::new();
+        patch.insert("image_root".to_string(), dir.to_str().unwrap().to_string());
+        patch.insert("roots".to_string(), r#"[{ "name": "usb", "path": "/nonexistent/tifari" }]"#.to_string());
+        config.update(patch);
+
+        let scan = backend::ScanData::default();
+
+        // an unplugged drive doesn't fail the status, but is reported
+        let status = get_status(&config, &scan);
+        assert_eq!(status.status, APIStatusEnum::Valid as i64);
+        assert_eq!(status.roots.len(), 2);
+        assert_eq!(status.roots[1].name, "usb");
+        assert_eq!(status.roots[1].status, APIStatusEnum::InvalidImageFolder as i64);
+
+        let mut patch = std::collections::HashMap::new();
+        patch.insert("image_root".to_string(), dir.join("gone").to_str().unwrap().to_string());
+        config.update(patch);
+        assert_eq!(get_status(&config, &scan).status, APIStatusEnum::InvalidImageFolder as i64);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn static_files_served_during_long_search() {
+        let dir = std::env::temp_dir().join("tifari_workers_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("img.png"), "not really a png").unwrap();
+
+        let mut config = backend::TifariConfig::default();
+        let mut patch = std::collections::HashMap::new();
+        patch.insert("image_root".to_string(), dir.to_str().unwrap().to_string());
+        patch.insert("db_root".to_string(), dir.join("test.db").to_str().unwrap().to_string());
+        config.update(patch);
+        let cfg = Arc::new(RwLock::new(config));
+
+        backend::TifariDb::new(cfg.clone()).unwrap().setup_tables().unwrap();
+
+        // a single worker, held up by a job that stands in for a long search.
+        let workers = WorkerPool::new(1, 4);
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let long_search = workers.spawn(move || { blocked.recv().unwrap(); Ok(()) });
+
+        let (addr, shutdown) = start_server(cfg.clone(), workers.clone());
+
+        // a search that has to wait for the worker.
+        let body = "[\"anything\"]";
+        let search = send_request(&addr, &format!(
+            "POST /api/v1/search HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(), body));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while workers.get_num_waiting() == 0 {
+            assert!(std::time::Instant::now() < deadline, "the search never reached the workers");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let image = read_response(send_request(&addr, "GET /img.png HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"));
+        assert!(image.starts_with("HTTP/1.1 200"), "{}", image);
+        assert!(image.ends_with("not really a png"));
+
+        release.send(()).unwrap();
+        long_search.wait().unwrap();
+
+        let search = read_response(search);
+        assert!(search.starts_with("HTTP/1.1 200"), "{}", search);
+
+        shutdown.send(()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
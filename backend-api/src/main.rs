@@ -1,5 +1,28 @@
 extern crate tifari_backend_api;
 
 fn main() {
-    tifari_backend_api::run_server(tifari_backend_api::get_cfg());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let cfg = tifari_backend_api::get_cfg();
+
+    let result = match args.get(0).map(|a| &a[..]) {
+        Some("snapshot") => tifari_backend_api::run_snapshot(cfg),
+        Some("check") => tifari_backend_api::run_integrity_check(cfg, false),
+        Some("repair") => tifari_backend_api::run_integrity_check(cfg, true),
+        Some("restore") => match args.get(1) {
+            Some(name) => tifari_backend_api::run_restore(cfg, name),
+            None => {
+                println!("Usage: backend_api restore <snapshot name>");
+                return;
+            }
+        },
+        _ => {
+            tifari_backend_api::run_server(cfg);
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        println!("Error: {:?}", e);
+        std::process::exit(1);
+    }
 }
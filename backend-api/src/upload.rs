@@ -0,0 +1,178 @@
+use super::*;
+
+// uploads bigger than this are refused before they're read in full.
+pub const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+// an image sent to POST /api/v1/images.
+pub struct Upload {
+    pub file_name: String,
+    pub data: Vec<u8>,
+    pub tags: Vec<String>,
+    pub root: String,
+}
+
+// a part of a multipart/form-data body.
+pub struct Part {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub data: Vec<u8>,
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.len() == 0 || haystack.len() < needle.len() {
+        return None;
+    }
+
+    (from..haystack.len() - needle.len() + 1).find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+// the value of a "key=value" parameter of a header like content-type, without quotes.
+fn get_header_param(header: &str, key: &str) -> Option<String> {
+    header.split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let param = param.trim();
+            let i = param.find('=')?;
+
+            if !param[..i].trim().eq_ignore_ascii_case(key) {
+                return None;
+            }
+
+            Some(param[i + 1..].trim().trim_matches('"').to_string())
+        })
+        .next()
+}
+
+// the parts of a multipart/form-data body, or None when it's malformed.
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Option<Vec<Part>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = vec![];
+
+    let mut pos = find(body, &delimiter, 0)? + delimiter.len();
+
+    loop {
+        // the last delimiter is followed by "--".
+        if body[pos..].starts_with(b"--") {
+            return Some(parts);
+        }
+
+        if !body[pos..].starts_with(b"\r\n") {
+            return None;
+        }
+        pos += 2;
+
+        let headers_end = find(body, b"\r\n\r\n", pos)?;
+        let headers = String::from_utf8_lossy(&body[pos..headers_end]).to_string();
+
+        let data_start = headers_end + 4;
+        let mut end_delimiter = b"\r\n".to_vec();
+        end_delimiter.extend_from_slice(&delimiter);
+        let data_end = find(body, &end_delimiter, data_start)?;
+
+        let mut name = None;
+        let mut file_name = None;
+
+        for header in headers.split("\r\n") {
+            let lower = header.to_lowercase();
+            if lower.starts_with("content-disposition:") {
+                name = get_header_param(header, "name");
+                file_name = get_header_param(header, "filename");
+            }
+        }
+
+        parts.push(Part {
+            name: name?,
+            file_name,
+            data: body[data_start..data_end].to_vec(),
+        });
+
+        pos = data_end + end_delimiter.len();
+    }
+}
+
+// reads the upload from either a multipart form, with the image in a "file" field and the
+// rest in "name", "tags" and "root" fields, or from a raw body with those in the query string.
+// tags are separated by commas. the image goes into the default root unless another one is given.
+pub fn parse_upload(content_type: Option<&str>, query: Option<&str>, body: Vec<u8>) -> Result<Upload, APIError> {
+    let mut fields = parse_query_string(query.unwrap_or(""));
+    let mut data = None;
+
+    let boundary = content_type
+        .filter(|t| t.trim().to_lowercase().starts_with("multipart/form-data"))
+        .map(|t| get_header_param(t, "boundary"));
+
+    match boundary {
+        Some(Some(boundary)) => {
+            let parts = match parse_multipart(&body, &boundary) {
+                Some(v) => v,
+                None => return Err(APIError::BadUpload(String::from("malformed multipart body"))),
+            };
+
+            for part in parts {
+                if part.name == "file" {
+                    if let Some(file_name) = part.file_name {
+                        fields.entry(String::from("name")).or_insert(file_name);
+                    }
+                    data = Some(part.data);
+                } else {
+                    fields.insert(part.name, String::from_utf8_lossy(&part.data).to_string());
+                }
+            }
+        },
+        Some(None) => return Err(APIError::BadUpload(String::from("multipart body without a boundary"))),
+        None => data = Some(body),
+    }
+
+    let data = data.unwrap_or(vec![]);
+    if data.len() <= 0 {
+        return Err(APIError::BadUpload(String::from("no image in the upload")));
+    }
+
+    let tags = fields.get("tags")
+        .map(|t| t.split(',').map(|t| t.trim().to_string()).filter(|t| t.len() > 0).collect())
+        .unwrap_or(vec![]);
+
+    Ok(Upload {
+        file_name: fields.remove("name").unwrap_or_default(),
+        data,
+        tags,
+        root: fields.remove("root").unwrap_or(backend::DEFAULT_ROOT_NAME.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multipart_upload() {
+        let body = b"--xyz\r\n\
+            Content-Disposition: form-data; name=\"tags\"\r\n\r\n\
+            hand, pose\r\n\
+            --xyz\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"ref.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            \x89PNG\r\n--xy\r\n\
+            --xyz--\r\n".to_vec();
+
+        let upload = parse_upload(Some("multipart/form-data; boundary=xyz"), None, body).unwrap();
+
+        assert_eq!(upload.file_name, "ref.png");
+        assert_eq!(upload.data, b"\x89PNG\r\n--xy".to_vec());
+        assert_eq!(upload.tags, vec!["hand".to_string(), "pose".to_string()]);
+        assert_eq!(upload.root, backend::DEFAULT_ROOT_NAME);
+
+        assert!(parse_upload(Some("multipart/form-data; boundary=xyz"), None, b"--xyz\r\ngarbage".to_vec()).is_err());
+    }
+
+    #[test]
+    fn raw_upload() {
+        let upload = parse_upload(Some("image/png"), Some("name=my%20ref.png&tags=hand,red+hair&root=nas"), b"data".to_vec()).unwrap();
+
+        assert_eq!(upload.file_name, "my ref.png");
+        assert_eq!(upload.tags, vec!["hand".to_string(), "red hair".to_string()]);
+        assert_eq!(upload.root, "nas");
+
+        assert!(parse_upload(None, None, vec![]).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use super::*;
+use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use futures::sync::oneshot;
+
+// how many threads do the database work of requests, and how many requests can wait for one of
+// them before new ones are turned away.
+pub const DEFAULT_WORKER_THREADS: usize = 4;
+pub const DEFAULT_WORKER_QUEUE_SIZE: usize = 64;
+
+type Job = Box<FnOnce() + Send + 'static>;
+
+// threads that run the blocking parts of requests, so a slow query doesn't hold up the event
+// loop and every other request with it. cloning it gives another handle to the same threads.
+#[derive(Clone)]
+pub struct WorkerPool {
+    sender: mpsc::SyncSender<Job>,
+    // jobs that are queued but haven't been picked up by a worker yet.
+    waiting: Arc<AtomicUsize>,
+}
+
+impl WorkerPool {
+    pub fn new(num_threads: usize, queue_size: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let waiting = Arc::new(AtomicUsize::new(0));
+
+        for i in 0..num_threads {
+            let receiver = receiver.clone();
+            let waiting = waiting.clone();
+
+            std::thread::Builder::new()
+                .name(format!("worker-{}", i))
+                .spawn(move || loop {
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(v) => v,
+                        // every handle to the pool is gone.
+                        Err(_) => return,
+                    };
+
+                    waiting.fetch_sub(1, Ordering::AcqRel);
+
+                    // a panicking job fails it's request, the worker carries on with the next one.
+                    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                        println!("A worker job panicked.");
+                    }
+                })
+                .unwrap();
+        }
+
+        WorkerPool { sender, waiting }
+    }
+
+    // runs f on one of the workers. the future resolves with what f returns, or fails right away
+    // with APIError::Busy if the queue is full.
+    pub fn spawn<T, F>(&self, f: F) -> Box<Future<Item=T, Error=APIError>>
+        where T: Send + 'static,
+              F: FnOnce() -> Result<T, APIError> + Send + 'static {
+
+        let (tx, rx) = oneshot::channel();
+
+        let job: Job = Box::new(move || {
+            // the request may have been dropped by now, leaving nobody to hand the result to.
+            let _ = tx.send(f());
+        });
+
+        self.waiting.fetch_add(1, Ordering::AcqRel);
+
+        match self.sender.try_send(job) {
+            Ok(()) => Box::new(rx.then(|result| match result {
+                Ok(v) => v,
+                Err(oneshot::Canceled) => Err(APIError::WorkerFailed),
+            })),
+            Err(e) => {
+                self.waiting.fetch_sub(1, Ordering::AcqRel);
+
+                match e {
+                    mpsc::TrySendError::Full(_) => Box::new(err(APIError::Busy)),
+                    mpsc::TrySendError::Disconnected(_) => Box::new(err(APIError::WorkerFailed)),
+                }
+            },
+        }
+    }
+
+    pub fn get_num_waiting(&self) -> usize {
+        self.waiting.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn worker_pool_jobs() {
+        let workers = WorkerPool::new(1, 1);
+
+        assert_eq!(workers.spawn(|| Ok(2 + 2)).wait().unwrap(), 4);
+
+        let failed = workers.spawn(|| -> Result<(), APIError> { Err(APIError::UploadTooLarge) }).wait();
+        assert_eq!(failed.unwrap_err().get_code(), "upload_too_large");
+
+        let panicked = workers.spawn(|| -> Result<(), APIError> { panic!("job panicked") }).wait();
+        assert_eq!(panicked.unwrap_err().get_code(), "internal_error");
+
+        // the worker is busy with the first job and the second one fills the queue.
+        let (release, blocked) = mpsc::channel::<()>();
+        let first = workers.spawn(move || { blocked.recv().unwrap(); Ok(1) });
+        while workers.get_num_waiting() > 0 {
+            std::thread::yield_now();
+        }
+
+        let second = workers.spawn(|| Ok(2));
+        let third = workers.spawn(|| Ok(3)).wait();
+        assert_eq!(third.unwrap_err().get_status(), StatusCode::ServiceUnavailable);
+
+        release.send(()).unwrap();
+        assert_eq!(first.wait().unwrap(), 1);
+        assert_eq!(second.wait().unwrap(), 2);
+    }
+}
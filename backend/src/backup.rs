@@ -0,0 +1,192 @@
+use super::*;
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_PREFIX: &str = "tifari-";
+const SNAPSHOT_EXTENSION: &str = ".db";
+
+// tables every tifari database has, used to tell a snapshot apart from any other sqlite file.
+const REQUIRED_TABLES: [&str; 3] = ["images", "tags", "tag_queue"];
+
+// the format of the time in a snapshot's name, "tifari-20240131-235959-000001.db".
+const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+// when the snapshot was taken, in microseconds, or None if the name isn't one we'd give a snapshot.
+fn parse_snapshot_time(name: &str) -> Option<i64> {
+    if !name.starts_with(SNAPSHOT_PREFIX) || !name.ends_with(SNAPSHOT_EXTENSION) {
+        return None;
+    }
+
+    let time = &name[SNAPSHOT_PREFIX.len()..name.len() - SNAPSHOT_EXTENSION.len()];
+
+    // "20240131-235959" followed by "-000001"
+    if time.len() != 22 || !time.is_char_boundary(15) || &time[15..16] != "-" {
+        return None;
+    }
+
+    let micros = &time[16..];
+    if !micros.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let seconds = chrono::NaiveDateTime::parse_from_str(&time[..15], SNAPSHOT_TIME_FORMAT).ok()?.timestamp();
+    Some(seconds * 1_000_000 + micros.parse::<i64>().ok()?)
+}
+
+fn is_snapshot_name(name: &str) -> bool {
+    parse_snapshot_time(name).is_some()
+}
+
+// checks that the file is an intact sqlite database with the tifari tables in it.
+pub fn validate_snapshot(path: &Path) -> Result<()> {
+    let conn = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let integrity: String = conn.query_row("PRAGMA integrity_check", &[], |row| row.get(0))
+        .map_err(|_| BackendError::BadSnapshot(path.to_string_lossy().to_string()))?;
+
+    if integrity != "ok" {
+        return Err(BackendError::BadSnapshot(path.to_string_lossy().to_string()));
+    }
+
+    for table in REQUIRED_TABLES.iter() {
+        let mut statement = conn.prepare("SELECT name FROM sqlite_master WHERE type='table' AND name=?")?;
+
+        if !statement.exists(&[table])? {
+            return Err(BackendError::BadSnapshot(path.to_string_lossy().to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+// snapshots in the backup directory, oldest first.
+pub fn list_snapshots(cfg: &TifariConfig) -> Result<Vec<models::Snapshot>> {
+    let dir = Path::new(cfg.get_backup_dir());
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    // along with the time they were taken at, in microseconds, to order them by.
+    let mut snapshots: Vec<(i64, models::Snapshot)> = vec![];
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // other files that happen to be in the backup directory are left alone.
+        let time = match parse_snapshot_time(&name) {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let data = entry.metadata()?;
+        if !data.is_file() {
+            continue;
+        }
+
+        snapshots.push((time, models::Snapshot::new(name, data.len() as i64, time / 1_000_000)));
+    }
+
+    snapshots.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(snapshots.into_iter().map(|(_, snapshot)| snapshot).collect())
+}
+
+fn get_snapshot_path(cfg: &TifariConfig, name: &str) -> Result<PathBuf> {
+    let path = Path::new(cfg.get_backup_dir()).join(name);
+
+    if !is_snapshot_name(name) || !path.is_file() {
+        return Err(BackendError::SnapshotNotFound(name.to_string()));
+    }
+
+    Ok(path)
+}
+
+// removes the oldest snapshots so that only the configured amount is left. keeping 0 keeps them all.
+fn rotate_snapshots(cfg: &TifariConfig) -> Result<()> {
+    let keep = cfg.get_backup_keep();
+    if keep == 0 {
+        return Ok(());
+    }
+
+    let snapshots = list_snapshots(cfg)?;
+    if snapshots.len() <= keep {
+        return Ok(());
+    }
+
+    for snapshot in &snapshots[..snapshots.len() - keep] {
+        std::fs::remove_file(Path::new(cfg.get_backup_dir()).join(snapshot.get_name()))?;
+    }
+
+    Ok(())
+}
+
+impl TifariDb
+{
+    // copies the database into dst with sqlite's online backup, which is safe to do while
+    // other connections are writing to it.
+    pub fn backup(&self, dst: &Path) -> Result<()> {
+        self.connection.backup(rusqlite::DatabaseName::Main, dst, None)?;
+
+        // the copy of a WAL database is in WAL mode as well, which read only connections can't
+        // always open, so it's put back into the default mode to stand on it's own.
+        let copy = rusqlite::Connection::open(dst)?;
+        copy.execute_batch("PRAGMA journal_mode = DELETE")?;
+
+        Ok(())
+    }
+
+    fn take_snapshot(&self, cfg: &TifariConfig) -> Result<models::Snapshot> {
+        let dir = Path::new(cfg.get_backup_dir());
+        std::fs::create_dir_all(dir)?;
+
+        let (name, path) = loop {
+            let now = chrono::Utc::now();
+            let name = format!("{}{}-{}{}",
+                               SNAPSHOT_PREFIX,
+                               now.format(SNAPSHOT_TIME_FORMAT),
+                               now.format("%6f"),
+                               SNAPSHOT_EXTENSION);
+
+            let path = dir.join(&name);
+            if !path.exists() {
+                break (name, path);
+            }
+        };
+
+        // backed up under a temporary name first, so a half written snapshot is never listed.
+        let partial = dir.join(format!("{}.part", name));
+
+        self.backup(&partial)?;
+        std::fs::rename(&partial, &path)?;
+
+        let size = std::fs::metadata(&path)?.len() as i64;
+        Ok(models::Snapshot::new(name, size, chrono::Utc::now().timestamp()))
+    }
+
+    // takes a timestamped snapshot in the backup directory and rotates the old ones out.
+    pub fn make_snapshot(&self, cfg: &TifariConfig) -> Result<models::Snapshot> {
+        let snapshot = self.take_snapshot(cfg)?;
+        rotate_snapshots(cfg)?;
+
+        Ok(snapshot)
+    }
+
+    // validates the snapshot and copies it over the database. the current database is snapshotted
+    // first, so the restore itself can be undone.
+    pub fn restore_snapshot(&mut self, cfg: &TifariConfig, name: &str) -> Result<models::Snapshot> {
+        let path = get_snapshot_path(cfg, name)?;
+        validate_snapshot(&path)?;
+
+        // rotated only after the restore, so the snapshot being restored isn't rotated out from under us.
+        let previous = self.take_snapshot(cfg)?;
+
+        // the restore goes through sqlite's backup api as well, so other open connections
+        // see the restored database instead of holding on to the old file.
+        self.connection.restore(rusqlite::DatabaseName::Main, &path, None::<fn(rusqlite::backup::Progress)>)?;
+
+        // snapshots taken by older versions might not have every table yet.
+        self.setup_tables()?;
+
+        rotate_snapshots(cfg)?;
+        Ok(previous)
+    }
+}
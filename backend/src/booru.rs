@@ -0,0 +1,297 @@
+use super::*;
+use std::path::Path;
+
+fn rating_name(rating: &str) -> Option<&'static str> {
+    match rating {
+        "g" | "general" => Some("general"),
+        "s" | "safe" => Some("safe"),
+        "q" | "questionable" => Some("questionable"),
+        "e" | "explicit" => Some("explicit"),
+        _ => None,
+    }
+}
+
+// the post's tags, with the character, copyright and meta tags namespaced like "character:name"
+// and the rating as "rating:safe". artist tags become "artist_name", as searching for "artist:"
+// filters on the artist metadata instead of tags.
+pub fn booru_post_tags(post: &models::BooruPost) -> Vec<String> {
+    let mut tags = vec![];
+    let mut namespaced = HashSet::new();
+
+    let categories = [
+        ("artist", post.get_tag_string_artist()),
+        ("character", post.get_tag_string_character()),
+        ("copyright", post.get_tag_string_copyright()),
+        ("meta", post.get_tag_string_meta()),
+    ];
+
+    for &(namespace, names) in categories.iter() {
+        for name in names.split_whitespace() {
+            namespaced.insert(name);
+            if query::is_filter_namespace(namespace) {
+                tags.push(format!("{}_{}", namespace, name));
+            } else {
+                tags.push(format!("{}:{}", namespace, name));
+            }
+        }
+    }
+
+    // danbooru's tag_string has every tag, gelbooru only has "tags".
+    let general =
+        if post.get_tag_string_general().len() > 0 { post.get_tag_string_general() }
+        else if post.get_tag_string().len() > 0 { post.get_tag_string() }
+        else { post.get_tags() };
+
+    for name in general.split_whitespace() {
+        if !namespaced.contains(name) {
+            tags.push(name.to_string());
+        }
+    }
+
+    if let Some(rating) = rating_name(&post.get_rating().to_lowercase()) {
+        tags.push(format!("rating:{}", rating));
+    }
+
+    let mut seen = HashSet::new();
+    tags.into_iter()
+        .filter(|t| TifariDb::is_valid_tag(t))
+        .filter(|t| seen.insert(t.clone()))
+        .collect()
+}
+
+// file names the post's image could have been saved as.
+fn post_file_names(post: &models::BooruPost) -> Vec<String> {
+    let mut names = vec![];
+
+    if post.get_image().len() > 0 {
+        names.push(post.get_image().clone());
+    }
+
+    if post.get_md5().len() > 0 && post.get_file_ext().len() > 0 {
+        names.push(format!("{}.{}", post.get_md5(), post.get_file_ext()));
+    }
+
+    names
+}
+
+// the image a sidecar was saved next to, as the id of it's root and the sidecar's name without ".json".
+type SidecarOf = Option<(i64, String)>;
+
+impl TifariDb
+{
+    // reads the posts in the .json files of every root. "image.png.json" and "image.json" are taken
+    // to describe image.png, files with a list of posts are matched by md5 and file name only.
+    fn read_booru_sidecars(&self, cfg: &TifariConfig) -> Result<Vec<(models::BooruPost, SidecarOf)>> {
+        let mut posts = vec![];
+
+        for (root_id, name) in self.get_known_roots()? {
+            let root = match cfg.get_root_by_name(&name) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let entries = match std::fs::read_dir(root.get_path()) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("Failed to read root {}. Error: {:?}", name, e);
+                    continue;
+                }
+            };
+
+            for entry in entries {
+                let path = entry?.path();
+
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some(ext) if ext.eq_ignore_ascii_case("json") => {},
+                    _ => continue,
+                }
+
+                let text = match std::fs::read_to_string(&path) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        println!("Failed to read booru metadata {:?}. Error: {:?}", path, e);
+                        continue;
+                    }
+                };
+
+                if let Ok(list) = serde_json::from_str::<Vec<models::BooruPost>>(&text) {
+                    posts.extend(list.into_iter().map(|post| (post, None)));
+                    continue;
+                }
+
+                match serde_json::from_str::<models::BooruPost>(&text) {
+                    Ok(post) => {
+                        let stem = path.file_stem().unwrap().to_string_lossy().to_string();
+                        posts.push((post, Some((root_id, stem))));
+                    },
+                    Err(e) => println!("Failed to parse booru metadata {:?}. Error: {:?}", path, e),
+                }
+            }
+        }
+
+        Ok(posts)
+    }
+
+    // finds image.png given "image".
+    fn find_image_by_stem(&self, root_id: i64, stem: &str) -> Result<Option<i64>> {
+        let prefix = format!("{}.", stem);
+        let len = prefix.chars().count() as i64;
+
+        match self.connection.query_row(
+            "SELECT id FROM images WHERE root_id=? AND substr(path, 1, ?)=? ORDER BY id ASC LIMIT 1",
+            &[&root_id, &len, &prefix],
+            |row| row.get(0))
+        {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    fn match_booru_post(&self, post: &models::BooruPost, sidecar_of: &SidecarOf) -> Result<Option<(i64, &'static str)>> {
+        if post.get_md5().len() > 0 {
+            if let Some(id) = self.find_images_by_hash(&post.get_md5().to_lowercase())?.first() {
+                return Ok(Some((*id, "md5")));
+            }
+        }
+
+        if let Some((root_id, ref stem)) = *sidecar_of {
+            if let Some(id) = self.find_image_in_root(root_id, stem)? {
+                return Ok(Some((id, "filename")));
+            }
+
+            if Path::new(stem).extension().is_none() {
+                if let Some(id) = self.find_image_by_stem(root_id, stem)? {
+                    return Ok(Some((id, "filename")));
+                }
+            }
+        }
+
+        for name in post_file_names(post) {
+            if let Some(id) = self.find_image_by_path(&name)? {
+                return Ok(Some((id, "filename")));
+            }
+        }
+
+        Ok(None)
+    }
+
+    // gives the matched images the post's tags, source and artist. a dry run makes the same changes
+    // and rolls them back, so the report is exactly what a real import would do.
+    fn import_booru(&mut self, cfg: &TifariConfig, posts: &Vec<(&models::BooruPost, SidecarOf)>, dry_run: bool) -> Result<models::BooruImportReport> {
+        self.ensure_image_hashes(cfg)?;
+
+        let mut matches = vec![];
+        let mut unmatched = vec![];
+
+        for &(post, ref sidecar_of) in posts {
+            match self.match_booru_post(post, sidecar_of)? {
+                Some((id, matched_by)) => matches.push((id, matched_by, post)),
+                None => {
+                    let name =
+                        if post.get_md5().len() > 0 { post.get_md5().clone() }
+                        else if let Some((_, ref stem)) = *sidecar_of { stem.clone() }
+                        else { post_file_names(post).into_iter().next().unwrap_or_default() };
+                    unmatched.push(name);
+                },
+            }
+        }
+
+        let tx = self.write_transaction()?;
+        let mut changes = vec![];
+        let mut entries = vec![];
+
+        for (image_id, matched_by, post) in matches {
+            let path: String = tx.query_row(
+                "SELECT path FROM images WHERE id=?",
+                &[&image_id],
+                |row| row.get(0))?;
+
+            let mut tags_added = vec![];
+            for tag in booru_post_tags(post) {
+                if let Some(tag_id) = TifariDb::get_tag_id(&tx, &tag)? {
+                    if TifariDb::image_has_tag(&tx, image_id, tag_id)? {
+                        continue;
+                    }
+                }
+
+                TifariDb::add_tag_tx(&tx, image_id, &tag)?;
+                changes.push(journal::Change::add(image_id, &tag));
+                tags_added.push(tag);
+            }
+
+            tx.execute(
+                "INSERT OR IGNORE INTO image_metadata (image_id, artist, license) VALUES (?, '', '')",
+                &[&image_id])?;
+
+            let mut sources_added = vec![];
+            let source = post.get_source().trim();
+            if source.len() > 0 {
+                let inserted = tx.execute(
+                    "INSERT OR IGNORE INTO image_sources (id, image_id, url) VALUES (null, ?, ?)",
+                    &[&image_id, &source])?;
+
+                if inserted > 0 {
+                    sources_added.push(source.to_string());
+                }
+            }
+
+            // an artist set by hand is kept.
+            let artists = post.get_tag_string_artist().split_whitespace().collect::<Vec<&str>>().join(", ");
+            if artists.len() > 0 {
+                tx.execute(
+                    "UPDATE image_metadata SET artist=? WHERE image_id=? AND artist=''",
+                    &[&artists, &image_id])?;
+            }
+
+            entries.push(models::BooruImportEntry::new(path, matched_by.to_string(), tags_added, sources_added));
+        }
+
+        TifariDb::record_journal(&tx, "booru_import", &changes)?;
+
+        if dry_run {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+        }
+
+        Ok(models::BooruImportReport::new(dry_run, entries, unmatched))
+    }
+
+    pub fn import_booru_posts(&mut self, cfg: &TifariConfig, posts: &Vec<models::BooruPost>, dry_run: bool) -> Result<models::BooruImportReport> {
+        let posts = posts.iter().map(|post| (post, None)).collect();
+        self.import_booru(cfg, &posts, dry_run)
+    }
+
+    // imports the booru metadata saved in .json files next to the images.
+    pub fn import_booru_sidecars(&mut self, cfg: &TifariConfig, dry_run: bool) -> Result<models::BooruImportReport> {
+        let posts = self.read_booru_sidecars(cfg)?;
+        let posts = posts.iter().map(|&(ref post, ref sidecar_of)| (post, sidecar_of.clone())).collect();
+        self.import_booru(cfg, &posts, dry_run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn booru_tags() {
+        let post: models::BooruPost = serde_json::from_str(r#"{
+            "md5": "abc", "rating": "s",
+            "tag_string": "1girl hand kantoku original",
+            "tag_string_artist": "kantoku",
+            "tag_string_copyright": "original"
+        }"#).unwrap();
+
+        assert_eq!(booru_post_tags(&post),
+                   vec!["artist_kantoku", "copyright:original", "1girl", "hand", "rating:safe"]);
+
+        let post: models::BooruPost = serde_json::from_str(r#"{
+            "image": "abc.jpg", "rating": "questionable", "tags": " hand  sitting "
+        }"#).unwrap();
+
+        assert_eq!(booru_post_tags(&post), vec!["hand", "sitting", "rating:questionable"]);
+        assert_eq!(post_file_names(&post), vec!["abc.jpg".to_string()]);
+    }
+}
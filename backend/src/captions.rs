@@ -0,0 +1,391 @@
+use super::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionOrder {
+    Alphabetical,
+    // most used tags first
+    Usage,
+}
+
+impl CaptionOrder {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "alphabetical" => Some(CaptionOrder::Alphabetical),
+            "usage" => Some(CaptionOrder::Usage),
+            _ => None,
+        }
+    }
+}
+
+// what to do when both the caption file and the image's tags changed since the last sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptionConflictPolicy {
+    PreferFile,
+    PreferDb,
+    // gives the image the tags from the file and then writes the union back to the file.
+    Merge,
+    Skip,
+}
+
+impl CaptionConflictPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "prefer_file" => Some(CaptionConflictPolicy::PreferFile),
+            "prefer_db" => Some(CaptionConflictPolicy::PreferDb),
+            "merge" => Some(CaptionConflictPolicy::Merge),
+            "skip" => Some(CaptionConflictPolicy::Skip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptionOptions {
+    pub separator: String,
+    pub order: CaptionOrder,
+    // write "artist:name" as just "name"
+    pub strip_namespaces: bool,
+    pub conflict_policy: CaptionConflictPolicy,
+}
+
+impl CaptionOptions {
+    pub fn default() -> Self {
+        CaptionOptions {
+            separator: String::from(","),
+            order: CaptionOrder::Alphabetical,
+            strip_namespaces: false,
+            conflict_policy: CaptionConflictPolicy::Skip,
+        }
+    }
+
+    // fills in the options given in the request, using the configured separator when none is given.
+    pub fn from_request(request: &models::CaptionSyncRequest, cfg: &TifariConfig) -> Result<Self> {
+        let mut options = CaptionOptions::default();
+        options.separator = cfg.get_caption_separator().clone();
+
+        if let Some(separator) = request.get_separator() {
+            if separator.len() <= 0 {
+                return Err(BackendError::BadCaptionOption(separator.clone()));
+            }
+            options.separator = separator.clone();
+        }
+
+        if let Some(order) = request.get_order() {
+            options.order = CaptionOrder::parse(order)
+                .ok_or_else(|| BackendError::BadCaptionOption(order.clone()))?;
+        }
+
+        if let Some(policy) = request.get_conflict_policy() {
+            options.conflict_policy = CaptionConflictPolicy::parse(policy)
+                .ok_or_else(|| BackendError::BadCaptionOption(policy.clone()))?;
+        }
+
+        if let Some(strip) = request.get_strip_namespaces() {
+            options.strip_namespaces = strip;
+        }
+
+        Ok(options)
+    }
+}
+
+// image.png -> image.txt
+pub fn caption_path(image_path: &Path) -> PathBuf {
+    image_path.with_extension("txt")
+}
+
+// whether another file next to the image has the same caption file, like image.jpg does for
+// image.png. there's no telling which of them such a caption belongs to, so it's left alone.
+pub fn caption_is_shared(image_path: &Path) -> bool {
+    let (dir, stem) = match (image_path.parent(), image_path.file_stem()) {
+        (Some(dir), Some(stem)) => (dir, stem),
+        _ => return false,
+    };
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+
+    entries.filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .any(|p| p != image_path && p.file_stem() == Some(stem) && p.is_file() && !TifariDb::is_sidecar_file(&p))
+}
+
+pub fn parse_caption(text: &str, separator: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+
+    text.split(separator)
+        .map(|t| keywords::keyword_to_tag(t))
+        .filter(|t| TifariDb::is_valid_tag(t))
+        .filter(|t| seen.insert(t.clone()))
+        .collect()
+}
+
+fn strip_namespace(tag: &str) -> &str {
+    match tag.find(':') {
+        Some(i) if i + 1 < tag.len() => &tag[i + 1..],
+        _ => tag,
+    }
+}
+
+// tags are expected to already be in the order they should be written in.
+pub fn format_caption(tags: &Vec<String>, options: &CaptionOptions) -> String {
+    let mut seen = HashSet::new();
+
+    let tags: Vec<&str> = tags.iter()
+        .map(|t| if options.strip_namespaces { strip_namespace(t) } else { &t[..] })
+        .filter(|t| seen.insert(*t))
+        .collect();
+
+    let separator = if options.separator == "," { ", " } else { &options.separator[..] };
+    tags.join(separator)
+}
+
+fn hash_text(text: &str) -> String {
+    format!("{:x}", md5::compute(text.as_bytes()))
+}
+
+// tags in the order they should be written into the caption, along with their usage.
+fn get_caption_tags(tx: &rusqlite::Transaction, image_id: i64, order: CaptionOrder) -> Result<Vec<String>> {
+    let mut statement = tx.prepare(
+        &format!("SELECT id, name FROM tags WHERE id IN (SELECT tag_id FROM tags_array_table_{})", image_id))?;
+
+    let mut tags: Vec<(String, i64)> = vec![];
+    for result in statement.query_map(&[], |row| (row.get::<usize, i64>(0), row.get::<usize, String>(1)))? {
+        let (id, name) = result?;
+
+        let usage: i64 = tx.query_row(
+            &format!("SELECT count(*) FROM image_ids_array_table_{}", id),
+            &[],
+            |row| row.get(0))?;
+
+        tags.push((name, usage));
+    }
+
+    match order {
+        CaptionOrder::Alphabetical => tags.sort_by(|a, b| a.0.cmp(&b.0)),
+        CaptionOrder::Usage => tags.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0))),
+    }
+
+    Ok(tags.into_iter().map(|t| t.0).collect())
+}
+
+// a hash of the image's tags that doesn't depend on their order.
+fn hash_tags(tags: &Vec<String>) -> String {
+    let mut sorted = tags.clone();
+    sorted.sort();
+    hash_text(&sorted.join("\n"))
+}
+
+impl TifariDb
+{
+    // gives the image the tags listed in it's caption file. returns the number of tags given.
+    pub fn import_caption(&mut self, cfg: &TifariConfig, image_id: i64) -> Result<usize> {
+        let path = self.get_image_file_path(cfg, image_id)?;
+        if caption_is_shared(&path) {
+            return Ok(0);
+        }
+
+        let text = std::fs::read_to_string(caption_path(&path))?;
+        let tags = parse_caption(&text, cfg.get_caption_separator());
+
+        self.import_tags(image_id, &tags)
+    }
+
+    // makes the image's tags match the caption's. when namespaces are stripped,
+    // "artist:name" counts as matching "name" in the caption.
+    fn apply_caption_tags(tx: &rusqlite::Transaction,
+                          image_id: i64,
+                          current: &Vec<String>,
+                          caption: &Vec<String>,
+                          options: &CaptionOptions,
+                          remove_missing: bool,
+                          changes: &mut Vec<journal::Change>) -> Result<()>
+    {
+        let matches = |tag: &str, other: &str| {
+            tag == other || (options.strip_namespaces && strip_namespace(tag) == other)
+        };
+
+        for tag in caption {
+            if current.iter().any(|c| matches(c, tag)) {
+                continue;
+            }
+
+            TifariDb::add_tag_tx(tx, image_id, tag)?;
+            changes.push(journal::Change::add(image_id, tag));
+        }
+
+        if remove_missing {
+            for tag in current {
+                if caption.iter().any(|c| matches(tag, c)) {
+                    continue;
+                }
+
+                if let Some(tag_id) = TifariDb::get_tag_id(tx, tag)? {
+                    TifariDb::remove_tag_tx(tx, image_id, tag_id)?;
+                    changes.push(journal::Change::remove(image_id, tag));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // syncs the tags of every image with it's caption file in both directions.
+    // the tag changes are journaled as a single operation, and the caption files are only written
+    // once it's committed. images whose root isn't configured are left alone, and images that
+    // share their caption file with another image are reported as conflicts.
+    pub fn sync_captions(&mut self, cfg: &TifariConfig, options: &CaptionOptions) -> Result<models::CaptionSyncReport> {
+        let images: Vec<(i64, String)> = {
+            let mut statement = self.connection.prepare("SELECT id, path FROM images ORDER BY id ASC")?;
+
+            let mut images = vec![];
+            for result in statement.query_map(&[], |row| (row.get(0), row.get(1)))? {
+                images.push(result?);
+            }
+            images
+        };
+
+        let mut files = vec![];
+        for (image_id, path) in images {
+            match self.get_image_file_path(cfg, image_id) {
+                Ok(file) => files.push((image_id, path, caption_path(&file))),
+                Err(BackendError::RootNotFound(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut num_sharing: HashMap<PathBuf, usize> = HashMap::new();
+        for &(_, _, ref file) in &files {
+            *num_sharing.entry(file.clone()).or_insert(0) += 1;
+        }
+
+        let tx = self.write_transaction()?;
+
+        let mut imported = 0;
+        let mut conflicts = vec![];
+        let mut changes = vec![];
+        let mut writes = vec![];
+
+        for (image_id, path, file) in files {
+            if num_sharing[&file] > 1 {
+                conflicts.push(path.clone());
+                continue;
+            }
+
+            let current = get_caption_tags(&tx, image_id, options.order)?;
+            let text = std::fs::read_to_string(&file).ok();
+
+            let record: Option<(String, String)> = match tx.query_row(
+                "SELECT file_hash, tags_hash FROM caption_sync WHERE image_id=?",
+                &[&image_id],
+                |row| (row.get(0), row.get(1)))
+            {
+                Ok(v) => Some(v),
+                Err(rusqlite::Error::QueryReturnedNoRows) => None,
+                Err(e) => return Err(BackendError::from(e)),
+            };
+
+            #[derive(PartialEq)]
+            enum Action { Nothing, Import, Export, Merge, Conflict }
+
+            let action = match (&text, &record) {
+                (None, _) => if current.is_empty() { Action::Nothing } else { Action::Export },
+                (Some(text), Some((file_hash, tags_hash))) => {
+                    let file_changed = hash_text(text) != *file_hash;
+                    let tags_changed = hash_tags(&current) != *tags_hash;
+
+                    match (file_changed, tags_changed) {
+                        (false, false) => Action::Nothing,
+                        (true, false) => Action::Import,
+                        (false, true) => Action::Export,
+                        (true, true) => Action::Conflict,
+                    }
+                },
+                (Some(text), None) => {
+                    let caption = parse_caption(text, &options.separator);
+
+                    if current.is_empty() { Action::Import }
+                    else if format_caption(&caption, options) == format_caption(&current, options) { Action::Nothing }
+                    else { Action::Conflict }
+                },
+            };
+
+            let action = if action == Action::Conflict {
+                match options.conflict_policy {
+                    CaptionConflictPolicy::PreferFile => Action::Import,
+                    CaptionConflictPolicy::PreferDb => Action::Export,
+                    CaptionConflictPolicy::Merge => Action::Merge,
+                    CaptionConflictPolicy::Skip => {
+                        conflicts.push(path.clone());
+                        continue;
+                    },
+                }
+            } else { action };
+
+            if action == Action::Import || action == Action::Merge {
+                let caption = parse_caption(text.as_ref().map(|t| &t[..]).unwrap_or(""), &options.separator);
+                TifariDb::apply_caption_tags(&tx, image_id, &current, &caption, options, action == Action::Import, &mut changes)?;
+                imported += 1;
+            }
+
+            let tags = get_caption_tags(&tx, image_id, options.order)?;
+
+            let text = if action == Action::Export || action == Action::Merge {
+                let text = format_caption(&tags, options);
+                writes.push((image_id, file, text.clone()));
+                Some(text)
+            } else { text };
+
+            // remember what both sides looked like, so that we can tell which one changed next time.
+            match text {
+                Some(text) => {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO caption_sync (image_id, file_hash, tags_hash) VALUES (?, ?, ?)",
+                        &[&image_id, &hash_text(&text), &hash_tags(&tags)])?;
+                },
+                None => {
+                    tx.execute("DELETE FROM caption_sync WHERE image_id=?", &[&image_id])?;
+                },
+            }
+        }
+
+        TifariDb::record_journal(&tx, "caption_sync", &changes)?;
+
+        tx.commit()?;
+
+        let mut exported = 0;
+        for (image_id, file, text) in writes {
+            match std::fs::write(&file, text) {
+                Ok(()) => exported += 1,
+                Err(e) => {
+                    // without a record, the caption is compared to the tags from scratch next time.
+                    println!("Failed to write caption {:?}. Error: {:?}", file, e);
+                    self.connection.execute("DELETE FROM caption_sync WHERE image_id=?", &[&image_id])?;
+                },
+            }
+        }
+
+        Ok(models::CaptionSyncReport::new(imported, exported, conflicts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caption_parse_and_format() {
+        let tags = parse_caption("1girl, red hair,  -bad , hand, hand", ",");
+        assert_eq!(tags, vec!["1girl".to_string(), "red_hair".to_string(), "hand".to_string()]);
+
+        let mut options = CaptionOptions::default();
+        assert_eq!(format_caption(&tags, &options), "1girl, red_hair, hand");
+
+        options.separator = String::from(" ");
+        options.strip_namespaces = true;
+        let tags = vec!["artist:someone".to_string(), "hand".to_string(), "someone".to_string()];
+        assert_eq!(format_caption(&tags, &options), "someone hand");
+    }
+}
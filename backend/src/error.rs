@@ -8,11 +8,88 @@ pub enum BackendError
 {
     SQLite(rusqlite::Error),
     IO(std::io::Error),
-    Opt(std::option::NoneError),
     Notify(notify::Error),
     ImageExists,
     NoChangesOccured,
     BadTag,
+    BadRegion,
+    ImageNotFound,
+    RegionNotFound,
+    BadQuery(String),
+    BadSavedSearchName,
+    SavedSearchNotFound(String),
+    TagExists,
+    NothingToUndo,
+    NothingToRedo,
+    CorruptJournal,
+    UnsupportedExportVersion(u32),
+    BadCaptionOption(String),
+    BadSnapshot(String),
+    SnapshotNotFound(String),
+    RootNotFound(String),
+    // the number of images a scan would've found missing, out of the number present
+    TooManyMissingImages(usize, usize),
+    BadRelink(String),
+    BadFileName(String),
+    FileExists(String),
+    ImageNotInTrash,
+    NotAnImage,
+    BadSchedule(String),
+    GestureSessionNotFound(i64),
+    NoMatchingImages,
+    TagNotFound(i64),
+}
+
+impl std::fmt::Display for BackendError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BackendError::SQLite(e) => write!(f, "database error: {}", e),
+            BackendError::IO(e) => write!(f, "io error: {}", e),
+            BackendError::Notify(e) => write!(f, "file watcher error: {}", e),
+            BackendError::ImageExists => write!(f, "the image is already in the library"),
+            BackendError::NoChangesOccured => write!(f, "nothing was changed"),
+            BackendError::BadTag => write!(f, "tags can't be empty or start with a dash or whitespace"),
+            BackendError::BadRegion => write!(f, "regions must have a size and lie within the image"),
+            BackendError::ImageNotFound => write!(f, "the image doesn't exist"),
+            BackendError::RegionNotFound => write!(f, "the region doesn't exist"),
+            BackendError::BadQuery(reason) => write!(f, "bad query: {}", reason),
+            BackendError::BadSavedSearchName => write!(f, "saved search names can't be empty or contain whitespace"),
+            BackendError::SavedSearchNotFound(name) => write!(f, "there's no saved search named \"{}\"", name),
+            BackendError::TagExists => write!(f, "the tag already exists"),
+            BackendError::NothingToUndo => write!(f, "there's nothing to undo"),
+            BackendError::NothingToRedo => write!(f, "there's nothing to redo"),
+            BackendError::CorruptJournal => write!(f, "the undo journal is corrupt"),
+            BackendError::UnsupportedExportVersion(version) => write!(f, "export version {} isn't supported", version),
+            BackendError::BadCaptionOption(option) => write!(f, "bad caption option: {}", option),
+            BackendError::BadSnapshot(name) => write!(f, "\"{}\" isn't a valid snapshot", name),
+            BackendError::SnapshotNotFound(name) => write!(f, "there's no snapshot named \"{}\"", name),
+            BackendError::RootNotFound(name) => write!(f, "there's no root named \"{}\"", name),
+            BackendError::TooManyMissingImages(missing, present) =>
+                write!(f, "the scan would mark {} of {} images as missing, is the root's folder mounted?", missing, present),
+            BackendError::BadRelink(reason) => write!(f, "can't relink: {}", reason),
+            BackendError::BadFileName(name) => write!(f, "\"{}\" isn't a valid file name", name),
+            BackendError::FileExists(name) => write!(f, "\"{}\" already exists", name),
+            BackendError::ImageNotInTrash => write!(f, "the image isn't in the trash"),
+            BackendError::NotAnImage => write!(f, "the file isn't an image"),
+            BackendError::BadSchedule(schedule) => write!(f, "bad schedule: {}", schedule),
+            BackendError::GestureSessionNotFound(id) => write!(f, "gesture session {} doesn't exist", id),
+            BackendError::NoMatchingImages => write!(f, "no images match the query"),
+            BackendError::TagNotFound(id) => write!(f, "tag {} doesn't exist", id),
+        }
+    }
+}
+
+impl std::error::Error for BackendError
+{
+    fn cause(&self) -> Option<&std::error::Error> {
+        match self {
+            BackendError::SQLite(e) => Some(e),
+            BackendError::IO(e) => Some(e),
+            BackendError::Notify(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<std::io::Error> for BackendError 
@@ -25,11 +102,6 @@ impl From<rusqlite::Error> for BackendError
     fn from(error: rusqlite::Error) -> Self { BackendError::SQLite(error) }
 }
 
-impl From<std::option::NoneError> for BackendError
-{
-    fn from(error: std::option::NoneError) -> Self { BackendError::Opt(error) }
-}
-
 impl From<notify::Error> for BackendError
 {
     fn from(error: notify::Error) -> Self { BackendError::Notify(error) }
@@ -0,0 +1,211 @@
+use super::*;
+
+pub const EXPORT_VERSION: u32 = 1;
+
+impl TifariDb
+{
+    fn get_tag_names_of_image(&self, image_id: i64) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare(
+            &format!("SELECT name
+                     FROM tags
+                     WHERE id IN (SELECT tag_id FROM tags_array_table_{})
+                     ORDER BY name ASC", image_id))?;
+
+        let mut names = vec![];
+        for result in statement.query_map(&[], |row| row.get(0))? {
+            names.push(result?);
+        }
+
+        Ok(names)
+    }
+
+    fn export_image(&self, image_id: i64) -> Result<models::ExportedImage> {
+        let (path, created_at_time): (String, i64) = self.connection.query_row(
+            "SELECT path, created_at_time FROM images WHERE id=?",
+            &[&image_id],
+            |row| (row.get(0), row.get(1)))?;
+
+        let hash = self.get_image_hash(image_id)?.unwrap_or_default();
+
+        let mut regions = vec![];
+        for region in self.get_regions_of_image(image_id)? {
+            let mut tags: Vec<String> = region.get_tags().iter().map(|t| t.get_name().clone()).collect();
+            tags.sort();
+
+            regions.push(models::ExportedRegion::new(
+                    region.get_x(), region.get_y(), region.get_width(), region.get_height(),
+                    region.get_note().clone(), tags));
+        }
+
+        Ok(models::ExportedImage::new(
+                path, hash, created_at_time,
+                self.get_tag_names_of_image(image_id)?,
+                self.get_image_metadata(image_id)?,
+                regions))
+    }
+
+    // exports every image with it's tags, regions and metadata. images the scan thread hasn't
+    // hashed yet are exported without a hash, and are matched by path when imported.
+    pub fn export_library(&self) -> Result<models::ExportDocument> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare("SELECT id FROM images ORDER BY id ASC")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        let mut images = Vec::with_capacity(ids.len());
+        for id in ids {
+            images.push(self.export_image(id)?);
+        }
+
+        Ok(models::ExportDocument::new(EXPORT_VERSION, chrono::Utc::now().timestamp(), images))
+    }
+
+    // matches an exported image to an image in the library, by hash first and path second.
+    fn match_exported_image(&self, image: &models::ExportedImage) -> Result<Option<(i64, bool)>> {
+        if image.get_hash().len() > 0 {
+            if let Some(id) = self.find_images_by_hash(image.get_hash())?.first() {
+                return Ok(Some((*id, true)));
+            }
+        }
+
+        Ok(self.find_image_by_path(image.get_path())?.map(|id| (id, false)))
+    }
+
+    // applies the tags, regions and metadata of an exported document onto the library.
+    // the tags given are journaled as a single operation, so the whole import can be undone.
+    pub fn import_library(&mut self, cfg: &TifariConfig, doc: &models::ExportDocument) -> Result<models::ImportReport> {
+        if doc.get_version() > EXPORT_VERSION {
+            return Err(BackendError::UnsupportedExportVersion(doc.get_version()));
+        }
+
+        self.ensure_image_hashes(cfg)?;
+
+        let mut matches = vec![];
+        let mut matched_by_hash = 0;
+        let mut matched_by_path = 0;
+        let mut unmatched = vec![];
+        let mut tags_added = 0;
+        let mut regions_added = 0;
+
+        for image in doc.get_images() {
+            match self.match_exported_image(image)? {
+                Some((id, by_hash)) => {
+                    if by_hash { matched_by_hash += 1; }
+                    else { matched_by_path += 1; }
+
+                    matches.push((id, image));
+                },
+                None => unmatched.push(image.get_path().clone()),
+            }
+        }
+
+        let tx = self.write_transaction()?;
+        let mut changes = vec![];
+
+        for (image_id, image) in matches {
+            for tag in image.get_tags() {
+                if !TifariDb::is_valid_tag(tag) {
+                    continue;
+                }
+
+                if let Some(tag_id) = TifariDb::get_tag_id(&tx, tag)? {
+                    if TifariDb::image_has_tag(&tx, image_id, tag_id)? {
+                        continue;
+                    }
+                }
+
+                TifariDb::add_tag_tx(&tx, image_id, tag)?;
+                changes.push(journal::Change::add(image_id, tag));
+                tags_added += 1;
+            }
+
+            TifariDb::import_metadata(&tx, image_id, image.get_metadata())?;
+
+            for region in image.get_regions() {
+                let region_id = match TifariDb::import_region(&tx, image_id, region)? {
+                    Some(id) => id,
+                    None => continue,
+                };
+
+                regions_added += 1;
+
+                for tag in region.get_tags() {
+                    if TifariDb::is_valid_tag(tag) && TifariDb::add_region_tag_tx(&tx, region_id, tag)?.is_some() {
+                        changes.push(journal::Change::region_add(region_id, tag));
+                    }
+                }
+            }
+        }
+
+        TifariDb::record_journal(&tx, "import", &changes)?;
+
+        tx.commit()?;
+        Ok(models::ImportReport::new(matched_by_hash, matched_by_path, unmatched, tags_added, regions_added))
+    }
+
+    // sources are merged, artist and license are only overwritten by non-empty values.
+    fn import_metadata(tx: &rusqlite::Transaction, image_id: i64, metadata: &models::ImageMetadata) -> Result<()> {
+        tx.execute(
+            "INSERT OR IGNORE INTO image_metadata (image_id, artist, license) VALUES (?, '', '')",
+            &[&image_id])?;
+
+        if metadata.get_artist().len() > 0 {
+            tx.execute(
+                "UPDATE image_metadata SET artist=? WHERE image_id=?",
+                &[metadata.get_artist(), &image_id])?;
+        }
+
+        if metadata.get_license().len() > 0 {
+            tx.execute(
+                "UPDATE image_metadata SET license=? WHERE image_id=?",
+                &[metadata.get_license(), &image_id])?;
+        }
+
+        for url in metadata.get_sources() {
+            tx.execute(
+                "INSERT OR IGNORE INTO image_sources (id, image_id, url) VALUES (null, ?, ?)",
+                &[&image_id, url])?;
+        }
+
+        Ok(())
+    }
+
+    // adds the region unless it's invalid or the image already has one with the same box,
+    // returning the new region's id.
+    fn import_region(tx: &rusqlite::Transaction, image_id: i64, region: &models::ExportedRegion) -> Result<Option<i64>> {
+        if !TifariDb::is_valid_region(region.get_x(), region.get_y(), region.get_width(), region.get_height()) {
+            return Ok(None);
+        }
+
+        let exists = {
+            let mut statement = tx.prepare(
+                "SELECT id FROM regions
+                WHERE image_id=? AND x=? AND y=? AND width=? AND height=?
+                LIMIT 1")?;
+
+            statement.exists(&[&image_id, &region.get_x(), &region.get_y(), &region.get_width(), &region.get_height()])?
+        };
+
+        if exists {
+            return Ok(None);
+        }
+
+        tx.execute_named(
+            "INSERT INTO regions (id, image_id, x, y, width, height, note)
+            VALUES (null, :image_id, :x, :y, :width, :height, :note)",
+            &[(":image_id", &image_id),
+              (":x", &region.get_x()),
+              (":y", &region.get_y()),
+              (":width", &region.get_width()),
+              (":height", &region.get_height()),
+              (":note", region.get_note()),
+            ])?;
+
+        Ok(Some(tx.last_insert_rowid()))
+    }
+}
@@ -0,0 +1,352 @@
+use super::*;
+use std::path::{Path, PathBuf};
+
+// every root has it's own trash folder, so trashing a file never moves it across drives.
+// the scanner only looks at the files at the top of a root, so the trash isn't scanned.
+pub const TRASH_DIR_NAME: &str = ".tifari-trash";
+
+// whether the name is a plain file name, which keeps the file at the top of it's root where the
+// scanner can see it. rules out "..", path separators and hidden files, the trash folder among them.
+pub fn is_valid_file_name(name: &str) -> bool {
+    if name.len() <= 0 || name.starts_with('.') || name.contains('/') || name.contains('\\') || name.contains('\0') {
+        return false;
+    }
+
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) => true,
+        _ => false,
+    }
+}
+
+// the extension of the image format the data is in, going by it's first few bytes.
+pub fn detect_image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") { Some("png") }
+    else if data.starts_with(b"\xff\xd8\xff") { Some("jpg") }
+    else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") { Some("gif") }
+    else if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" { Some("webp") }
+    else if data.starts_with(b"BM") { Some("bmp") }
+    else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") { Some("tiff") }
+    else { None }
+}
+
+// the stem of the uploaded file's name, for naming the file in the root. browsers can send
+// the whole path the file was picked from, so only the last part of it is used.
+fn get_upload_stem(file_name: &str) -> String {
+    let name = file_name.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+
+    let stem = match Path::new(name).file_stem() {
+        Some(v) => v.to_string_lossy().to_string(),
+        None => String::new(),
+    };
+
+    if is_valid_file_name(&stem) { stem } else { String::from("upload") }
+}
+
+fn get_trash_dir(root: &ImageRoot) -> PathBuf {
+    Path::new(root.get_path()).join(TRASH_DIR_NAME)
+}
+
+impl TifariDb
+{
+    // the name of the image's file in the trash folder, if it's in the trash.
+    fn get_trashed_file_name(&self, image_id: i64) -> Result<Option<String>> {
+        match self.connection.query_row(
+            "SELECT file_name FROM trash WHERE image_id=?",
+            &[&image_id],
+            |row| row.get(0))
+        {
+            Ok(name) => Ok(Some(name)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // deletes the image's file from the trash, for when the image itself is about to be erased
+    // or it's file has shown up in the root again.
+    pub(crate) fn delete_trashed_file(&self, cfg: &TifariConfig, image_id: i64) {
+        let file_name = match self.get_trashed_file_name(image_id) {
+            Ok(Some(v)) => v,
+            Ok(None) => return,
+            Err(e) => {
+                println!("Failed to look up trashed image {}. Error: {:?}", image_id, e);
+                return;
+            }
+        };
+
+        let root = match self.get_image_root(cfg, image_id) {
+            Ok((root, _)) => root,
+            Err(_) => return,
+        };
+
+        let path = get_trash_dir(&root).join(file_name);
+        if let Err(e) = std::fs::remove_file(&path) {
+            println!("Failed to delete trashed file {:?}. Error: {:?}", path, e);
+        }
+    }
+
+    // moves the image's file into it's root's trash folder. the image keeps it's tags and is hidden
+    // like a missing image until it's restored, or erased along with the images that have been
+    // missing for too long.
+    pub fn trash_image(&mut self, cfg: &TifariConfig, image_id: i64) -> Result<()> {
+        let (root, path) = self.get_image_root(cfg, image_id)?;
+
+        let dir = get_trash_dir(&root);
+        std::fs::create_dir_all(&dir)?;
+
+        // prefixed with the id, so trashing two files of the same name one after another works.
+        let file_name = format!("{}-{}", image_id, path);
+        let src = Path::new(root.get_path()).join(&path);
+        let dst = dir.join(&file_name);
+
+        let tx = self.write_transaction()?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO trash (image_id, file_name, trashed_at_time) VALUES (?, ?, ?)",
+            &[&image_id, &file_name, &chrono::Utc::now().timestamp()])?;
+
+        tx.execute(
+            "UPDATE images SET missing_since=? WHERE id=?",
+            &[&chrono::Utc::now().timestamp(), &image_id])?;
+
+        // the file is moved last, so that it only has to be moved back if the commit fails.
+        std::fs::rename(&src, &dst)?;
+
+        if let Err(e) = tx.commit() {
+            let _ = std::fs::rename(&dst, &src);
+            return Err(BackendError::from(e));
+        }
+
+        Ok(())
+    }
+
+    // moves the image's file out of the trash and back to where it was.
+    pub fn restore_image(&mut self, cfg: &TifariConfig, image_id: i64) -> Result<models::Image> {
+        let file_name = match self.get_trashed_file_name(image_id)? {
+            Some(v) => v,
+            None => return Err(BackendError::ImageNotInTrash),
+        };
+
+        let (root, path) = self.get_image_root(cfg, image_id)?;
+        let src = get_trash_dir(&root).join(&file_name);
+        let dst = Path::new(root.get_path()).join(&path);
+
+        if dst.exists() {
+            return Err(BackendError::FileExists(path));
+        }
+
+        {
+            let tx = self.write_transaction()?;
+
+            tx.execute("DELETE FROM trash WHERE image_id=?", &[&image_id])?;
+            tx.execute("UPDATE images SET missing_since=NULL WHERE id=?", &[&image_id])?;
+
+            std::fs::rename(&src, &dst)?;
+
+            if let Err(e) = tx.commit() {
+                let _ = std::fs::rename(&dst, &src);
+                return Err(BackendError::from(e));
+            }
+        }
+
+        self.get_image_from_db(image_id)
+    }
+
+    // the trashed images, most recently trashed first.
+    pub fn get_trash(&self) -> Result<Vec<models::Image>> {
+        let mut statement = self.connection.prepare("SELECT image_id FROM trash ORDER BY trashed_at_time DESC, image_id DESC")?;
+
+        let mut images = vec![];
+        for result in statement.query_map(&[], |row| row.get(0))? {
+            images.push(self.get_image_from_db(result?)?);
+        }
+
+        Ok(images)
+    }
+
+    // deletes the trashed files for good and erases their images. returns the number of images erased.
+    pub fn empty_trash(&mut self, cfg: &TifariConfig) -> Result<usize> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare("SELECT image_id FROM trash")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        for id in &ids {
+            self.delete_trashed_file(cfg, *id);
+            self.erase_image_by_id(*id)?;
+        }
+
+        Ok(ids.len())
+    }
+
+    // writes the uploaded image into the root and adds it to the library with the given tags.
+    // the file is named after the upload, with a number added when that name is taken, and
+    // with the extension of the format the data turned out to be in.
+    // nothing is left behind when the image can't be added with it's tags.
+    pub fn add_image_file(&mut self, cfg: &TifariConfig, root_name: &str, file_name: &str, data: &[u8], tags: &Vec<String>) -> Result<models::Image> {
+        let ext = match detect_image_type(data) {
+            Some(v) => v,
+            None => return Err(BackendError::NotAnImage),
+        };
+
+        if tags.iter().any(|t| !TifariDb::is_valid_tag(t)) {
+            return Err(BackendError::BadTag);
+        }
+
+        let root = match cfg.get_root_by_name(root_name) {
+            Some(v) => v,
+            None => return Err(BackendError::RootNotFound(root_name.to_string())),
+        };
+
+        let root_id = self.get_or_create_root_id(root_name)?;
+        let stem = get_upload_stem(file_name);
+
+        let mut num = 0;
+        let name = loop {
+            let name =
+                if num == 0 { format!("{}.{}", stem, ext) }
+                else { format!("{}-{}.{}", stem, num, ext) };
+            num += 1;
+
+            // a missing image with the name would take the file over once the root is scanned.
+            if self.find_image_in_root(root_id, &name)?.is_some() {
+                continue;
+            }
+
+            // create_new fails if the file is there, so nothing is ever overwritten.
+            let path = Path::new(root.get_path()).join(&name);
+            match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    if let Err(e) = file.write_all(data) {
+                        let _ = std::fs::remove_file(&path);
+                        return Err(BackendError::from(e));
+                    }
+                    break name;
+                },
+                Err(ref e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(BackendError::from(e)),
+            }
+        };
+
+        let path = Path::new(root.get_path()).join(&name);
+
+        // a scan that's running could've found the file first.
+        let (image_id, inserted) = match self.try_insert_image_in_root(root_id, &name) {
+            Ok(id) => (id, true),
+            Err(BackendError::ImageExists) => match self.find_image_in_root(root_id, &name)? {
+                Some(id) => (id, false),
+                None => return Err(BackendError::ImageNotFound),
+            },
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                return Err(e);
+            }
+        };
+
+        if tags.len() > 0 {
+            if let Err(e) = self.give_tags(&vec![image_id], tags) {
+                if inserted {
+                    let _ = self.erase_image_by_id(image_id);
+                    let _ = std::fs::remove_file(&path);
+                }
+                return Err(e);
+            }
+        }
+
+        if inserted {
+            self.on_new_image(cfg, image_id, &name);
+        }
+
+        self.get_image_from_db(image_id)
+    }
+
+    // renames the image's file within it's root, along with it's caption and xmp sidecars.
+    // the image keeps it's id, so it's tags and everything else stay with it.
+    pub fn move_image(&mut self, cfg: &TifariConfig, image_id: i64, new_path: &str) -> Result<models::Image> {
+        if !is_valid_file_name(new_path) {
+            return Err(BackendError::BadFileName(new_path.to_string()));
+        }
+
+        let (root, path) = self.get_image_root(cfg, image_id)?;
+        if path == new_path {
+            return self.get_image_from_db(image_id);
+        }
+
+        let (root_id, _) = self.get_image_location(image_id)?;
+        let src = Path::new(root.get_path()).join(&path);
+        let dst = Path::new(root.get_path()).join(new_path);
+
+        // a missing image with that name would get this file the next time the root is scanned.
+        if dst.exists() || self.find_image_in_root(root_id, new_path)?.is_some() {
+            return Err(BackendError::FileExists(new_path.to_string()));
+        }
+
+        {
+            let tx = self.write_transaction()?;
+
+            tx.execute("UPDATE images SET path=? WHERE id=?", &[&new_path, &image_id])?;
+
+            std::fs::rename(&src, &dst)?;
+
+            if let Err(e) = tx.commit() {
+                let _ = std::fs::rename(&dst, &src);
+                return Err(BackendError::from(e));
+            }
+        }
+
+        // a caption shared with another image stays where it is.
+        let caption_shared = caption_is_shared(&src);
+
+        for &(ref from, ref to) in [(caption_path(&src), caption_path(&dst)), (sidecar_path(&src), sidecar_path(&dst))].iter() {
+            if *from == caption_path(&src) && caption_shared {
+                continue;
+            }
+
+            if from.is_file() && !to.exists() {
+                if let Err(e) = std::fs::rename(from, to) {
+                    println!("Failed to move sidecar {:?}. Error: {:?}", from, e);
+                }
+            }
+        }
+
+        self.get_image_from_db(image_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert!(is_valid_file_name("img.png"));
+        assert!(is_valid_file_name("img two.png"));
+
+        assert!(!is_valid_file_name(""));
+        assert!(!is_valid_file_name(".."));
+        assert!(!is_valid_file_name("../img.png"));
+        assert!(!is_valid_file_name("sub/img.png"));
+        assert!(!is_valid_file_name("sub\\img.png"));
+        assert!(!is_valid_file_name("/etc/passwd"));
+        assert!(!is_valid_file_name(TRASH_DIR_NAME));
+    }
+
+    #[test]
+    fn uploads() {
+        assert_eq!(detect_image_type(b"\x89PNG\r\n\x1a\n...."), Some("png"));
+        assert_eq!(detect_image_type(b"\xff\xd8\xff\xe0...."), Some("jpg"));
+        assert_eq!(detect_image_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(detect_image_type(b"<html>"), None);
+
+        assert_eq!(get_upload_stem("C:\\fakepath\\pose.png"), "pose");
+        assert_eq!(get_upload_stem("../../pose.jpg"), "pose");
+        assert_eq!(get_upload_stem(".."), "upload");
+        assert_eq!(get_upload_stem(""), "upload");
+    }
+}
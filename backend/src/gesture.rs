@@ -0,0 +1,384 @@
+use super::*;
+
+// the most images one session can go through.
+pub const MAX_GESTURE_SLOTS: usize = 500;
+
+// parses a duration like "30s", "2m", "1h" or "45" into seconds.
+fn parse_duration(duration: &str) -> Option<i64> {
+    let duration = duration.trim();
+    let digits_end = duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len());
+
+    let num: i64 = duration[..digits_end].parse().ok()?;
+    let multiplier = match duration[digits_end..].trim() {
+        "" | "s" | "sec" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        _ => return None,
+    };
+
+    if num <= 0 {
+        return None;
+    }
+
+    num.checked_mul(multiplier)
+}
+
+// parses a schedule like "10x30s, 5x2m, 1x10m" into it's (count, seconds) stages.
+pub fn parse_schedule(schedule: &str) -> Option<Vec<(usize, i64)>> {
+    let mut stages = vec![];
+    let mut num_slots: usize = 0;
+
+    for stage in schedule.split(',') {
+        let stage = stage.trim();
+        let (i, c) = stage.char_indices().find(|&(_, c)| c == 'x' || c == 'X' || c == '×')?;
+
+        let count: usize = stage[..i].trim().parse().ok()?;
+        let seconds = parse_duration(&stage[i + c.len_utf8()..])?;
+
+        num_slots = num_slots.checked_add(count)?;
+        if count == 0 || num_slots > MAX_GESTURE_SLOTS {
+            return None;
+        }
+
+        stages.push((count, seconds));
+    }
+
+    Some(stages)
+}
+
+// the timing of a session, as it's stored.
+struct GestureSessionState {
+    // the slot that's up, or the one after the last slot once the session is finished.
+    position: i64,
+    slot_started_at: i64,
+    paused_at: Option<i64>,
+    finished_at: Option<i64>,
+}
+
+impl TifariDb
+{
+    fn get_gesture_session_state(&self, session_id: i64) -> Result<GestureSessionState> {
+        match self.connection.query_row(
+            "SELECT position, slot_started_at, paused_at, finished_at_time FROM gesture_sessions WHERE id=?",
+            &[&session_id],
+            |row| GestureSessionState {
+                position: row.get(0),
+                slot_started_at: row.get(1),
+                paused_at: row.get(2),
+                finished_at: row.get(3),
+            })
+        {
+            Ok(v) => Ok(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(BackendError::GestureSessionNotFound(session_id)),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // the (slot, duration) of the first slot at or after position. slots can have gaps in them
+    // when images are erased during a session.
+    fn get_next_gesture_slot(conn: &rusqlite::Connection, session_id: i64, position: i64) -> Result<Option<(i64, i64)>> {
+        match conn.query_row(
+            "SELECT slot, duration FROM gesture_session_images WHERE session_id=? AND slot>=? ORDER BY slot ASC LIMIT 1",
+            &[&session_id, &position],
+            |row| (row.get(0), row.get(1)))
+        {
+            Ok(v) => Ok(Some(v)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // starts a session going through random images matching the query, on the given schedule.
+    // when there are fewer matches than slots, the images are shown more than once.
+    pub fn create_gesture_session(&mut self, query_str: &str, schedule: &str, seed: Option<u64>) -> Result<models::GestureSession> {
+        let stages = match parse_schedule(schedule) {
+            Some(v) => v,
+            None => return Err(BackendError::BadSchedule(schedule.to_string())),
+        };
+
+        let num_slots: usize = stages.iter().map(|&(count, _)| count).sum();
+        let tags: Vec<&str> = query_str.split_whitespace().collect();
+        let images = self.get_random_images(&tags, num_slots, seed, 0)?;
+
+        if images.is_empty() {
+            return Err(BackendError::NoMatchingImages);
+        }
+
+        let now = chrono::Utc::now().timestamp();
+
+        let session_id = {
+            let tx = self.write_transaction()?;
+
+            tx.execute(
+                "INSERT INTO gesture_sessions (id, query, schedule, created_at_time, position, slot_started_at)
+                 VALUES (null, ?, ?, ?, 0, ?)",
+                &[&query_str, &schedule, &now, &now])?;
+
+            let session_id = tx.last_insert_rowid();
+
+            let durations = stages.iter().flat_map(|&(count, seconds)| std::iter::repeat(seconds).take(count));
+            for (slot, (image, duration)) in images.iter().cycle().zip(durations).enumerate() {
+                tx.execute(
+                    "INSERT INTO gesture_session_images (session_id, slot, image_id, duration, skipped) VALUES (?, ?, ?, ?, 0)",
+                    &[&session_id, &(slot as i64), &image.get_id(), &duration])?;
+            }
+
+            tx.commit()?;
+            session_id
+        };
+
+        self.get_gesture_session_at(session_id, now)
+    }
+
+    // moves the session past the images whose time is up. the clock keeps running between requests,
+    // so this is done before anything else touches the session.
+    fn advance_gesture_session(&mut self, session_id: i64, now: i64) -> Result<()> {
+        let state = self.get_gesture_session_state(session_id)?;
+        if state.finished_at.is_some() || state.paused_at.is_some() {
+            return Ok(());
+        }
+
+        let mut position = state.position;
+        let mut started = state.slot_started_at;
+
+        let tx = self.write_transaction()?;
+
+        loop {
+            match TifariDb::get_next_gesture_slot(&tx, session_id, position)? {
+                Some((slot, duration)) => {
+                    if now - started < duration {
+                        position = slot;
+                        break;
+                    }
+
+                    tx.execute(
+                        "UPDATE gesture_session_images SET seconds_spent=? WHERE session_id=? AND slot=?",
+                        &[&duration, &session_id, &slot])?;
+
+                    // images that were up for their whole time go into the practice log.
+                    tx.execute(
+                        "INSERT INTO drawings (id, image_id, drawn_at_time, seconds)
+                         SELECT null, image_id, ?, duration FROM gesture_session_images WHERE session_id=? AND slot=?",
+                        &[&(started + duration), &session_id, &slot])?;
+
+                    position = slot + 1;
+                    started += duration;
+                },
+                None => {
+                    // the session ended when the last image's time was up, not when it was looked at.
+                    tx.execute(
+                        "UPDATE gesture_sessions SET finished_at_time=? WHERE id=?",
+                        &[&started, &session_id])?;
+                    break;
+                },
+            }
+        }
+
+        tx.execute(
+            "UPDATE gesture_sessions SET position=?, slot_started_at=? WHERE id=?",
+            &[&position, &started, &session_id])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn get_gesture_session_at(&mut self, session_id: i64, now: i64) -> Result<models::GestureSession> {
+        self.advance_gesture_session(session_id, now)?;
+
+        let (query, schedule, created_at, finished_at, position, started, paused_at):
+            (String, String, i64, Option<i64>, i64, i64, Option<i64>) = self.connection.query_row(
+            "SELECT query, schedule, created_at_time, finished_at_time, position, slot_started_at, paused_at
+             FROM gesture_sessions WHERE id=?",
+            &[&session_id],
+            |row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5), row.get(6)))?;
+
+        let rows: Vec<(i64, i64, i64, Option<i64>, bool)> = {
+            let mut statement = self.connection.prepare(
+                "SELECT slot, image_id, duration, seconds_spent, skipped FROM gesture_session_images
+                 WHERE session_id=? ORDER BY slot ASC")?;
+
+            let mut rows = vec![];
+            for result in statement.query_map(&[&session_id],
+                |row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get::<usize, i64>(4) != 0))?
+            {
+                rows.push(result?);
+            }
+            rows
+        };
+
+        let elapsed = paused_at.unwrap_or(now) - started;
+
+        let mut index = 0;
+        let mut remaining = 0;
+        let mut slots = vec![];
+
+        for (slot, image_id, duration, seconds_spent, skipped) in rows {
+            if slot < position {
+                index += 1;
+            } else if slot == position && finished_at.is_none() {
+                remaining = std::cmp::max(duration - elapsed, 0);
+            }
+
+            slots.push(models::GestureSlot::new(self.get_image_from_db(image_id)?, duration, seconds_spent, skipped));
+        }
+
+        Ok(models::GestureSession::new(
+            session_id, query, schedule, created_at, finished_at, paused_at.is_some(), index, remaining, slots))
+    }
+
+    pub fn get_gesture_session(&mut self, session_id: i64) -> Result<models::GestureSession> {
+        self.get_gesture_session_at(session_id, chrono::Utc::now().timestamp())
+    }
+
+    // every session, newest first.
+    pub fn get_gesture_sessions(&mut self) -> Result<Vec<models::GestureSession>> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare("SELECT id FROM gesture_sessions ORDER BY id DESC")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        let now = chrono::Utc::now().timestamp();
+
+        let mut sessions = vec![];
+        for id in ids {
+            sessions.push(self.get_gesture_session_at(id, now)?);
+        }
+
+        Ok(sessions)
+    }
+
+    pub(crate) fn pause_gesture_session_at(&mut self, session_id: i64, now: i64) -> Result<()> {
+        self.advance_gesture_session(session_id, now)?;
+
+        self.connection.execute(
+            "UPDATE gesture_sessions SET paused_at=? WHERE id=? AND paused_at IS NULL AND finished_at_time IS NULL",
+            &[&now, &session_id])?;
+
+        Ok(())
+    }
+
+    pub fn pause_gesture_session(&mut self, session_id: i64) -> Result<models::GestureSession> {
+        let now = chrono::Utc::now().timestamp();
+        self.pause_gesture_session_at(session_id, now)?;
+        self.get_gesture_session_at(session_id, now)
+    }
+
+    // the current image gets back the time it had left when the session was paused.
+    pub(crate) fn resume_gesture_session_at(&mut self, session_id: i64, now: i64) -> Result<()> {
+        let state = self.get_gesture_session_state(session_id)?;
+
+        if let Some(paused_at) = state.paused_at {
+            self.connection.execute(
+                "UPDATE gesture_sessions SET slot_started_at=?, paused_at=NULL WHERE id=?",
+                &[&(state.slot_started_at + now - paused_at), &session_id])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn resume_gesture_session(&mut self, session_id: i64) -> Result<models::GestureSession> {
+        let now = chrono::Utc::now().timestamp();
+        self.resume_gesture_session_at(session_id, now)?;
+        self.get_gesture_session_at(session_id, now)
+    }
+
+    // moves on to the next image right away. a paused session stays paused.
+    pub(crate) fn skip_gesture_image_at(&mut self, session_id: i64, now: i64) -> Result<()> {
+        self.advance_gesture_session(session_id, now)?;
+
+        let state = self.get_gesture_session_state(session_id)?;
+        if state.finished_at.is_some() {
+            return Ok(());
+        }
+
+        let now = state.paused_at.unwrap_or(now);
+        let tx = self.write_transaction()?;
+
+        if let Some((slot, _)) = TifariDb::get_next_gesture_slot(&tx, session_id, state.position)? {
+            tx.execute(
+                "UPDATE gesture_session_images SET seconds_spent=?, skipped=1 WHERE session_id=? AND slot=?",
+                &[&(now - state.slot_started_at), &session_id, &slot])?;
+
+            tx.execute(
+                "UPDATE gesture_sessions SET position=?, slot_started_at=? WHERE id=?",
+                &[&(slot + 1), &now, &session_id])?;
+
+            if TifariDb::get_next_gesture_slot(&tx, session_id, slot + 1)?.is_none() {
+                tx.execute(
+                    "UPDATE gesture_sessions SET finished_at_time=?, paused_at=NULL WHERE id=?",
+                    &[&now, &session_id])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn skip_gesture_image(&mut self, session_id: i64) -> Result<models::GestureSession> {
+        let now = chrono::Utc::now().timestamp();
+        self.skip_gesture_image_at(session_id, now)?;
+        self.get_gesture_session_at(session_id, now)
+    }
+
+    // finishes the session early. the current image is kept as skipped with the time it was up for,
+    // the images after it are left without a time.
+    pub(crate) fn end_gesture_session_at(&mut self, session_id: i64, now: i64) -> Result<()> {
+        self.advance_gesture_session(session_id, now)?;
+
+        let state = self.get_gesture_session_state(session_id)?;
+        if state.finished_at.is_some() {
+            return Ok(());
+        }
+
+        let now = state.paused_at.unwrap_or(now);
+        let tx = self.write_transaction()?;
+
+        if let Some((slot, _)) = TifariDb::get_next_gesture_slot(&tx, session_id, state.position)? {
+            tx.execute(
+                "UPDATE gesture_session_images SET seconds_spent=?, skipped=1 WHERE session_id=? AND slot=?",
+                &[&(now - state.slot_started_at), &session_id, &slot])?;
+        }
+
+        tx.execute(
+            "UPDATE gesture_sessions
+             SET finished_at_time=?, paused_at=NULL,
+                 position=(SELECT COALESCE(MAX(slot), -1) + 1 FROM gesture_session_images WHERE session_id=?)
+             WHERE id=?",
+            &[&now, &session_id, &session_id])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn end_gesture_session(&mut self, session_id: i64) -> Result<models::GestureSession> {
+        let now = chrono::Utc::now().timestamp();
+        self.end_gesture_session_at(session_id, now)?;
+        self.get_gesture_session_at(session_id, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gesture_schedules() {
+        assert_eq!(parse_schedule("10x30s, 5x2m, 1x10m"), Some(vec![(10, 30), (5, 120), (1, 600)]));
+        assert_eq!(parse_schedule("3×45"), Some(vec![(3, 45)]));
+        assert_eq!(parse_schedule("2X1h"), Some(vec![(2, 3600)]));
+
+        assert_eq!(parse_schedule(""), None);
+        assert_eq!(parse_schedule("10x"), None);
+        assert_eq!(parse_schedule("0x30s"), None);
+        assert_eq!(parse_schedule("5x0s"), None);
+        assert_eq!(parse_schedule("5x30 days"), None);
+        assert_eq!(parse_schedule("1000x30s"), None);
+        assert_eq!(parse_schedule("1x99999999999999999h"), None);
+        assert_eq!(parse_schedule("1x30s, 18446744073709551615x30s"), None);
+    }
+}
@@ -0,0 +1,201 @@
+use super::*;
+
+// returns the md5 of the file's contents as a hex string, along with the file's size.
+// md5 is what booru style metadata dumps use, so we can match against those directly.
+pub fn hash_file(path: &std::path::Path) -> Result<(String, i64)> {
+    let data = std::fs::read(path)?;
+    Ok((format!("{:x}", md5::compute(&data)), data.len() as i64))
+}
+
+// the size of the grayscale thumbnail a visual hash is taken of. every one of it's 8 rows gives
+// a bit for each of the 8 pairs of neighbouring pixels in it.
+const VISUAL_HASH_WIDTH: u32 = 9;
+const VISUAL_HASH_HEIGHT: u32 = 8;
+
+// a difference hash of what the image looks like. it barely changes when the image is resized,
+// recompressed or made brighter, so images that look alike have hashes that differ in only a few
+// of their 64 bits. None when the file can't be decoded.
+pub fn visual_hash_file(path: &std::path::Path) -> Option<i64> {
+    let thumbnail = image::open(path).ok()?
+        .resize_exact(VISUAL_HASH_WIDTH, VISUAL_HASH_HEIGHT, image::FilterType::Triangle)
+        .to_luma();
+
+    let mut hash: u64 = 0;
+    for y in 0..VISUAL_HASH_HEIGHT {
+        for x in 0..VISUAL_HASH_WIDTH - 1 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y).data[0] < thumbnail.get_pixel(x + 1, y).data[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Some(hash as i64)
+}
+
+// the number of bits two visual hashes differ in.
+pub fn visual_distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+impl TifariDb
+{
+    pub(crate) fn set_image_file_info(tx: &rusqlite::Transaction, image_id: i64, hash: &str, size: i64) -> Result<()> {
+        tx.execute(
+            "INSERT OR REPLACE INTO image_files (image_id, hash, size) VALUES (?, ?, ?)",
+            &[&image_id, &hash, &size])?;
+
+        Ok(())
+    }
+
+    pub fn update_image_hash(&mut self, cfg: &TifariConfig, image_id: i64) -> Result<String> {
+        let (hash, size) = hash_file(&self.get_image_file_path(cfg, image_id)?)?;
+
+        let tx = self.write_transaction()?;
+        TifariDb::set_image_file_info(&tx, image_id, &hash, size)?;
+        tx.commit()?;
+
+        Ok(hash)
+    }
+
+    // drops the stored hashes, so the image is hashed again the next time hashes are brought up to date.
+    pub(crate) fn forget_image_hash(&self, image_id: i64) -> Result<()> {
+        self.connection.execute("DELETE FROM image_files WHERE image_id=?", &[&image_id])?;
+        self.connection.execute("DELETE FROM image_visual_hashes WHERE image_id=?", &[&image_id])?;
+        Ok(())
+    }
+
+    // a hash of None is kept for files that can't be decoded, so they aren't tried again.
+    pub(crate) fn set_visual_hash(tx: &rusqlite::Transaction, image_id: i64, hash: Option<i64>) -> Result<()> {
+        tx.execute(
+            "INSERT OR REPLACE INTO image_visual_hashes (image_id, hash) VALUES (?, ?)",
+            &[&image_id, &hash])?;
+
+        Ok(())
+    }
+
+    pub fn update_visual_hash(&mut self, cfg: &TifariConfig, image_id: i64) -> Result<Option<i64>> {
+        let hash = visual_hash_file(&self.get_image_file_path(cfg, image_id)?);
+
+        let tx = self.write_transaction()?;
+        TifariDb::set_visual_hash(&tx, image_id, hash)?;
+        tx.commit()?;
+
+        Ok(hash)
+    }
+
+    pub fn get_visual_hash(&self, image_id: i64) -> Result<Option<i64>> {
+        match self.connection.query_row(
+            "SELECT hash FROM image_visual_hashes WHERE image_id=?",
+            &[&image_id],
+            |row| row.get(0))
+        {
+            Ok(hash) => Ok(hash),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // gives every image that doesn't have a visual hash yet one. decoding takes a while, so this is
+    // left to the scan thread as well.
+    pub fn ensure_visual_hashes(&mut self, cfg: &TifariConfig) -> Result<()> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare(
+                "SELECT id FROM images WHERE id NOT IN (SELECT image_id FROM image_visual_hashes) AND missing_since IS NULL")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        for id in ids {
+            if let Err(e) = self.update_visual_hash(cfg, id) {
+                println!("Failed to take the visual hash of image {}. Error: {:?}", id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // the images whose visual hashes are at most max_distance bits away from the hash, closest first.
+    pub fn find_visually_similar_images(&self, hash: i64, max_distance: u32) -> Result<Vec<i64>> {
+        let mut statement = self.connection.prepare(
+            "SELECT image_id, hash FROM image_visual_hashes WHERE hash IS NOT NULL")?;
+
+        let mut similar = vec![];
+        for result in statement.query_map(&[], |row| (row.get::<usize, i64>(0), row.get::<usize, i64>(1)))? {
+            let (id, other) = result?;
+
+            let distance = visual_distance(hash, other);
+            if distance <= max_distance {
+                similar.push((distance, id));
+            }
+        }
+
+        similar.sort();
+        Ok(similar.into_iter().map(|(_, id)| id).collect())
+    }
+
+    pub fn get_image_hash(&self, image_id: i64) -> Result<Option<String>> {
+        match self.connection.query_row(
+            "SELECT hash FROM image_files WHERE image_id=?",
+            &[&image_id],
+            |row| row.get(0))
+        {
+            Ok(hash) => Ok(Some(hash)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // hashes every image that hasn't been hashed yet. missing images and ones whose files can't be read are skipped.
+    // scans leave new images unhashed, so that reading every new file doesn't hold them up. the
+    // scan thread calls this once a scan is done.
+    pub fn ensure_image_hashes(&mut self, cfg: &TifariConfig) -> Result<()> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare(
+                "SELECT id FROM images WHERE id NOT IN (SELECT image_id FROM image_files) AND missing_since IS NULL")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        for id in ids {
+            match self.update_image_hash(cfg, id) {
+                Ok(_) => {},
+                Err(e) => println!("Failed to hash image {}. Error: {:?}", id, e),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn find_images_by_hash(&self, hash: &str) -> Result<Vec<i64>> {
+        let mut statement = self.connection.prepare(
+            "SELECT image_id FROM image_files WHERE hash=? ORDER BY image_id ASC")?;
+
+        let mut ids = vec![];
+        for result in statement.query_map(&[&hash], |row| row.get(0))? {
+            ids.push(result?);
+        }
+
+        Ok(ids)
+    }
+
+    pub fn find_image_by_path(&self, path: &str) -> Result<Option<i64>> {
+        match self.connection.query_row(
+            "SELECT id FROM images WHERE path=? LIMIT 1",
+            &[&path],
+            |row| row.get(0))
+        {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use super::*;
+
+// ids of the per image or per tag tables whose names start with the prefix.
+fn get_array_table_ids(tx: &rusqlite::Transaction, prefix: &str) -> Result<HashSet<i64>> {
+    let mut statement = tx.prepare("SELECT name FROM sqlite_master WHERE type='table' AND substr(name, 1, ?)=?")?;
+
+    let mut ids = HashSet::new();
+    for result in statement.query_map(&[&(prefix.len() as i64), &prefix], |row| row.get::<usize, String>(0))? {
+        if let Ok(id) = result?[prefix.len()..].parse() {
+            ids.insert(id);
+        }
+    }
+
+    Ok(ids)
+}
+
+fn get_ids(tx: &rusqlite::Transaction, query: &str) -> Result<HashSet<i64>> {
+    let mut statement = tx.prepare(query)?;
+
+    let mut ids = HashSet::new();
+    for result in statement.query_map(&[], |row| row.get(0))? {
+        ids.insert(result?);
+    }
+
+    Ok(ids)
+}
+
+impl TifariDb
+{
+    // sqlite's own integrity check. returns the problems it found, which is empty when there are none.
+    fn sqlite_integrity_check(&self) -> Result<Vec<String>> {
+        let mut statement = self.connection.prepare("PRAGMA integrity_check")?;
+
+        let mut errors = vec![];
+        for result in statement.query_map(&[], |row| row.get::<usize, String>(0))? {
+            let line = result?;
+            if line != "ok" {
+                errors.push(line);
+            }
+        }
+
+        Ok(errors)
+    }
+
+    // looks for the ways the tag and image array tables can drift from the images and tags tables,
+    // along with rows left behind for deleted images. every problem is fixed inside a transaction,
+    // which is only committed when repairing, so the report is the same either way.
+    // a database that fails sqlite's own integrity check isn't repaired, a snapshot should be restored instead.
+    pub fn check_integrity(&mut self, repair: bool) -> Result<models::IntegrityReport> {
+        let sqlite_errors = self.sqlite_integrity_check()?;
+        let repair = repair && sqlite_errors.is_empty();
+
+        let tx = self.write_transaction()?;
+
+        let image_ids = get_ids(&tx, "SELECT id FROM images")?;
+        let tag_ids = get_ids(&tx, "SELECT id FROM tags")?;
+        let image_tables = get_array_table_ids(&tx, "tags_array_table_")?;
+        let tag_tables = get_array_table_ids(&tx, "image_ids_array_table_")?;
+
+        let mut orphan_tables = vec![];
+        let mut missing_tables = vec![];
+
+        for id in image_tables.difference(&image_ids) {
+            let table = format!("tags_array_table_{}", id);
+            tx.execute(&format!("DROP TABLE {}", table), &[])?;
+            orphan_tables.push(table);
+        }
+
+        for id in tag_tables.difference(&tag_ids) {
+            let table = format!("image_ids_array_table_{}", id);
+            tx.execute(&format!("DROP TABLE {}", table), &[])?;
+            orphan_tables.push(table);
+        }
+
+        for id in image_ids.difference(&image_tables) {
+            let table = format!("tags_array_table_{}", id);
+            tx.execute(&format!("CREATE TABLE {} (tag_id INTEGER NOT NULL UNIQUE)", table), &[])?;
+            missing_tables.push(table);
+        }
+
+        for id in tag_ids.difference(&tag_tables) {
+            let table = format!("image_ids_array_table_{}", id);
+            tx.execute(&format!("CREATE TABLE {} (image_id INTEGER NOT NULL UNIQUE)", table), &[])?;
+            missing_tables.push(table);
+        }
+
+        // every (image, tag) pair should be in both the image's and the tag's table.
+        // pairs that refer to a deleted image or tag are dropped, pairs only on one side are copied over.
+        let mut dangling_memberships = 0;
+        let mut image_side = HashSet::new();
+        let mut tag_side = HashSet::new();
+
+        for image_id in &image_ids {
+            for tag_id in get_ids(&tx, &format!("SELECT tag_id FROM tags_array_table_{}", image_id))? {
+                if tag_ids.contains(&tag_id) {
+                    image_side.insert((*image_id, tag_id));
+                } else {
+                    tx.execute(&format!("DELETE FROM tags_array_table_{} WHERE tag_id=?", image_id), &[&tag_id])?;
+                    dangling_memberships += 1;
+                }
+            }
+        }
+
+        for tag_id in &tag_ids {
+            for image_id in get_ids(&tx, &format!("SELECT image_id FROM image_ids_array_table_{}", tag_id))? {
+                if image_ids.contains(&image_id) {
+                    tag_side.insert((image_id, *tag_id));
+                } else {
+                    tx.execute(&format!("DELETE FROM image_ids_array_table_{} WHERE image_id=?", tag_id), &[&image_id])?;
+                    dangling_memberships += 1;
+                }
+            }
+        }
+
+        let mut one_sided_memberships = 0;
+
+        for &(image_id, tag_id) in image_side.difference(&tag_side) {
+            tx.execute(&format!("INSERT INTO image_ids_array_table_{} (image_id) VALUES (?)", tag_id), &[&image_id])?;
+            one_sided_memberships += 1;
+        }
+
+        for &(image_id, tag_id) in tag_side.difference(&image_side) {
+            tx.execute(&format!("INSERT INTO tags_array_table_{} (tag_id) VALUES (?)", image_id), &[&tag_id])?;
+            one_sided_memberships += 1;
+        }
+
+        let orphan_queue_rows = tx.execute("DELETE FROM tag_queue WHERE image_id NOT IN (SELECT id FROM images)", &[])? as usize;
+
+        let mut orphan_rows = 0;
+        for query in [
+            "DELETE FROM regions WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM region_tags WHERE region_id NOT IN (SELECT id FROM regions) OR tag_id NOT IN (SELECT id FROM tags)",
+            "DELETE FROM image_metadata WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM image_sources WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM image_files WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM image_visual_hashes WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM caption_sync WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM trash WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM random_history WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM gesture_session_images WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM image_views WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM drawings WHERE image_id NOT IN (SELECT id FROM images)",
+            "DELETE FROM tag_queue_states WHERE image_id NOT IN (SELECT id FROM images)",
+        ].iter() {
+            orphan_rows += tx.execute(query, &[])? as usize;
+        }
+
+        // tags that nothing uses anymore would've been erased if the tables were in sync.
+        let used_tag_ids: HashSet<i64> = image_side.union(&tag_side).map(|&(_, tag_id)| tag_id).collect();
+        let mut unused_tags = vec![];
+
+        for tag_id in &tag_ids {
+            let used = used_tag_ids.contains(tag_id);
+            let used_by_regions: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM region_tags WHERE tag_id=?",
+                &[tag_id],
+                |row| row.get(0))?;
+
+            if !used && used_by_regions <= 0 {
+                unused_tags.push(TifariDb::get_tag_name(&tx, *tag_id)?);
+                TifariDb::erase_tag_if_not_used(&tx, *tag_id)?;
+            }
+        }
+
+        // the pair counts of related tags follow the memberships, so they're counted again from the fixed ones.
+        TifariDb::rebuild_tag_pairs(&tx)?;
+
+        if repair {
+            tx.commit()?;
+        } else {
+            tx.rollback()?;
+        }
+
+        orphan_tables.sort();
+        missing_tables.sort();
+        unused_tags.sort();
+
+        Ok(models::IntegrityReport::new(
+                sqlite_errors, orphan_tables, missing_tables,
+                dangling_memberships, one_sided_memberships,
+                unused_tags, orphan_queue_rows, orphan_rows, repair))
+    }
+}
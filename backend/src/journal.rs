@@ -0,0 +1,296 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChangeKind {
+    Add,
+    Remove,
+    Rename,
+    RegionAdd,
+    RegionRemove,
+}
+
+impl ChangeKind {
+    fn get_name(&self) -> &'static str {
+        match self {
+            ChangeKind::Add => "add",
+            ChangeKind::Remove => "remove",
+            ChangeKind::Rename => "rename",
+            ChangeKind::RegionAdd => "region_add",
+            ChangeKind::RegionRemove => "region_remove",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "add" => Some(ChangeKind::Add),
+            "remove" => Some(ChangeKind::Remove),
+            "rename" => Some(ChangeKind::Rename),
+            "region_add" => Some(ChangeKind::RegionAdd),
+            "region_remove" => Some(ChangeKind::RegionRemove),
+            _ => None,
+        }
+    }
+}
+
+// a single change made by a journaled operation.
+// tags are referred to by name, since tag ids don't survive a tag being erased and recreated.
+#[derive(Debug, Clone)]
+pub(crate) struct Change {
+    kind: ChangeKind,
+    // image id for add/remove, region id for region_add/region_remove, unused for rename.
+    target_id: i64,
+    tag_name: String,
+    // only used by rename.
+    new_tag_name: String,
+}
+
+impl Change {
+    pub fn add(image_id: i64, tag: &str) -> Self {
+        Change { kind: ChangeKind::Add, target_id: image_id, tag_name: tag.to_string(), new_tag_name: String::new() }
+    }
+
+    pub fn remove(image_id: i64, tag: &str) -> Self {
+        Change { kind: ChangeKind::Remove, target_id: image_id, tag_name: tag.to_string(), new_tag_name: String::new() }
+    }
+
+    pub fn rename(old_name: &str, new_name: &str) -> Self {
+        Change { kind: ChangeKind::Rename, target_id: 0, tag_name: old_name.to_string(), new_tag_name: new_name.to_string() }
+    }
+
+    pub fn region_add(region_id: i64, tag: &str) -> Self {
+        Change { kind: ChangeKind::RegionAdd, target_id: region_id, tag_name: tag.to_string(), new_tag_name: String::new() }
+    }
+
+    pub fn region_remove(region_id: i64, tag: &str) -> Self {
+        Change { kind: ChangeKind::RegionRemove, target_id: region_id, tag_name: tag.to_string(), new_tag_name: String::new() }
+    }
+
+    fn inverse(&self) -> Self {
+        match self.kind {
+            ChangeKind::Add => Change::remove(self.target_id, &self.tag_name),
+            ChangeKind::Remove => Change::add(self.target_id, &self.tag_name),
+            ChangeKind::Rename => Change::rename(&self.new_tag_name, &self.tag_name),
+            ChangeKind::RegionAdd => Change::region_remove(self.target_id, &self.tag_name),
+            ChangeKind::RegionRemove => Change::region_add(self.target_id, &self.tag_name),
+        }
+    }
+}
+
+impl TifariDb
+{
+    // records a journaled operation. recording a new operation drops everything that was undone,
+    // so there's nothing left to redo afterwards.
+    pub(crate) fn record_journal(tx: &rusqlite::Transaction, operation: &str, changes: &Vec<Change>) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        tx.execute(
+            "DELETE FROM journal_changes WHERE journal_id IN (SELECT id FROM journal WHERE undone=1)",
+            &[])?;
+        tx.execute("DELETE FROM journal WHERE undone=1", &[])?;
+
+        tx.execute_named(
+            "INSERT INTO journal (id, created_at_time, operation, undone)
+            VALUES (null, :time, :operation, 0)",
+            &[(":time", &chrono::Utc::now().timestamp()),
+              (":operation", &operation),
+            ])?;
+
+        let journal_id = tx.last_insert_rowid();
+
+        for change in changes {
+            tx.execute_named(
+                "INSERT INTO journal_changes (id, journal_id, kind, target_id, tag_name, new_tag_name)
+                VALUES (null, :journal_id, :kind, :target_id, :tag_name, :new_tag_name)",
+                &[(":journal_id", &journal_id),
+                  (":kind", &change.kind.get_name()),
+                  (":target_id", &change.target_id),
+                  (":tag_name", &change.tag_name),
+                  (":new_tag_name", &change.new_tag_name),
+                ])?;
+        }
+
+        Ok(())
+    }
+
+    fn get_journal_changes(conn: &rusqlite::Connection, journal_id: i64) -> Result<Vec<Change>> {
+        let mut statement = conn.prepare(
+            "SELECT kind, target_id, tag_name, new_tag_name
+            FROM journal_changes
+            WHERE journal_id=?
+            ORDER BY id ASC")?;
+
+        let mut changes = vec![];
+        for result in statement.query_map(&[&journal_id],
+            |row| (row.get::<usize, String>(0), row.get(1), row.get(2), row.get(3)))?
+        {
+            let (kind, target_id, tag_name, new_tag_name) = result?;
+
+            // the journal is only written to by us, so an unknown kind means the db is damaged.
+            let kind = match ChangeKind::parse(&kind) {
+                Some(kind) => kind,
+                None => return Err(BackendError::CorruptJournal),
+            };
+
+            changes.push(Change { kind, target_id, tag_name, new_tag_name });
+        }
+
+        Ok(changes)
+    }
+
+    fn make_journal_entry(conn: &rusqlite::Connection, journal_id: i64) -> Result<models::JournalEntry> {
+        let (created_at_time, operation, undone): (i64, String, i64) = conn.query_row(
+            "SELECT created_at_time, operation, undone FROM journal WHERE id=?",
+            &[&journal_id],
+            |row| (row.get(0), row.get(1), row.get(2)))?;
+
+        let mut image_ids = vec![];
+        let mut region_ids = vec![];
+        let mut tag_names = vec![];
+
+        for change in TifariDb::get_journal_changes(conn, journal_id)? {
+            match change.kind {
+                ChangeKind::Add | ChangeKind::Remove => image_ids.push(change.target_id),
+                ChangeKind::RegionAdd | ChangeKind::RegionRemove => region_ids.push(change.target_id),
+                ChangeKind::Rename => tag_names.push(change.new_tag_name.clone()),
+            }
+
+            tag_names.push(change.tag_name);
+        }
+
+        image_ids.sort();
+        image_ids.dedup();
+        region_ids.sort();
+        region_ids.dedup();
+        tag_names.sort();
+        tag_names.dedup();
+
+        Ok(models::JournalEntry::new(journal_id, created_at_time, operation, undone != 0, image_ids, region_ids, tag_names))
+    }
+
+    // applies a single change, skipping it if it no longer makes sense,
+    // (e.g. the image has since been erased or already has the tag).
+    fn apply_change(tx: &rusqlite::Transaction, change: &Change) -> Result<()> {
+        match change.kind {
+            ChangeKind::Add => {
+                if !TifariDb::image_exists(tx, change.target_id)? {
+                    return Ok(());
+                }
+
+                if let Some(tag_id) = TifariDb::get_tag_id(tx, &change.tag_name)? {
+                    if TifariDb::image_has_tag(tx, change.target_id, tag_id)? {
+                        return Ok(());
+                    }
+                }
+
+                TifariDb::add_tag_tx(tx, change.target_id, &change.tag_name)?;
+            },
+            ChangeKind::Remove => {
+                if !TifariDb::image_exists(tx, change.target_id)? {
+                    return Ok(());
+                }
+
+                if let Some(tag_id) = TifariDb::get_tag_id(tx, &change.tag_name)? {
+                    if TifariDb::image_has_tag(tx, change.target_id, tag_id)? {
+                        TifariDb::remove_tag_tx(tx, change.target_id, tag_id)?;
+                    }
+                }
+            },
+            ChangeKind::Rename => {
+                let tag_id = match TifariDb::get_tag_id(tx, &change.tag_name)? {
+                    Some(tag_id) => tag_id,
+                    None => return Ok(()),
+                };
+
+                if TifariDb::get_tag_id(tx, &change.new_tag_name)?.is_some() {
+                    return Err(BackendError::TagExists);
+                }
+
+                tx.execute("UPDATE tags SET name=? WHERE id=?", &[&change.new_tag_name, &tag_id])?;
+            },
+            ChangeKind::RegionAdd => {
+                if TifariDb::region_exists(tx, change.target_id)? {
+                    TifariDb::add_region_tag_tx(tx, change.target_id, &change.tag_name)?;
+                }
+            },
+            ChangeKind::RegionRemove => {
+                if let Some(tag_id) = TifariDb::get_tag_id(tx, &change.tag_name)? {
+                    TifariDb::remove_region_tag_tx(tx, change.target_id, tag_id)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    // reverts the latest operation that hasn't been undone yet, as a whole.
+    pub fn undo(&mut self) -> Result<models::JournalEntry> {
+        let tx = self.write_transaction()?;
+
+        let journal_id: i64 = match tx.query_row(
+            "SELECT id FROM journal WHERE undone=0 ORDER BY id DESC LIMIT 1",
+            &[],
+            |row| row.get(0))
+        {
+            Ok(id) => id,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(BackendError::NothingToUndo),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        for change in TifariDb::get_journal_changes(&tx, journal_id)?.iter().rev() {
+            TifariDb::apply_change(&tx, &change.inverse())?;
+        }
+
+        tx.execute("UPDATE journal SET undone=1 WHERE id=?", &[&journal_id])?;
+
+        let entry = TifariDb::make_journal_entry(&tx, journal_id)?;
+
+        tx.commit()?;
+        Ok(entry)
+    }
+
+    // re-applies the earliest undone operation, as a whole.
+    pub fn redo(&mut self) -> Result<models::JournalEntry> {
+        let tx = self.write_transaction()?;
+
+        let journal_id: i64 = match tx.query_row(
+            "SELECT id FROM journal WHERE undone=1 ORDER BY id ASC LIMIT 1",
+            &[],
+            |row| row.get(0))
+        {
+            Ok(id) => id,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(BackendError::NothingToRedo),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        for change in TifariDb::get_journal_changes(&tx, journal_id)? {
+            TifariDb::apply_change(&tx, &change)?;
+        }
+
+        tx.execute("UPDATE journal SET undone=0 WHERE id=?", &[&journal_id])?;
+
+        let entry = TifariDb::make_journal_entry(&tx, journal_id)?;
+
+        tx.commit()?;
+        Ok(entry)
+    }
+
+    // returns the latest journal entries, newest first.
+    pub fn get_history(&self, limit: usize) -> Result<Vec<models::JournalEntry>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id FROM journal ORDER BY id DESC LIMIT ?")?;
+
+        let mut ids: Vec<i64> = vec![];
+        for result in statement.query_map(&[&(limit as i64)], |row| row.get(0))? {
+            ids.push(result?);
+        }
+
+        let mut entries = Vec::with_capacity(ids.len());
+        for id in ids {
+            entries.push(TifariDb::make_journal_entry(&self.connection, id)?);
+        }
+
+        Ok(entries)
+    }
+}
@@ -0,0 +1,390 @@
+use super::*;
+use std::path::{Path, PathBuf};
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+
+    (0..haystack.len() - needle.len() + 1).find(|i| &haystack[*i..*i + needle.len()] == needle)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// keywords can contain spaces, which we can't have in tags, so those become underscores.
+pub fn keyword_to_tag(keyword: &str) -> String {
+    keyword.trim().split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+// returns the keywords listed in the dc:subject of an xmp packet.
+pub fn parse_xmp_subjects(xmp: &str) -> Vec<String> {
+    let mut keywords = vec![];
+
+    let start = match xmp.find("<dc:subject") {
+        Some(v) => v,
+        None => return keywords,
+    };
+
+    let end = match xmp[start..].find("</dc:subject>") {
+        Some(v) => start + v,
+        None => return keywords,
+    };
+
+    let mut rest = &xmp[start..end];
+
+    while let Some(li) = rest.find("<rdf:li") {
+        rest = &rest[li..];
+
+        let open_end = match rest.find('>') {
+            Some(v) => v + 1,
+            None => break,
+        };
+
+        let close = match rest.find("</rdf:li>") {
+            Some(v) => v,
+            None => break,
+        };
+
+        if open_end <= close {
+            let keyword = unescape_xml(rest[open_end..close].trim());
+            if keyword.len() > 0 {
+                keywords.push(keyword);
+            }
+        }
+
+        rest = &rest[close + "</rdf:li>".len()..];
+    }
+
+    keywords
+}
+
+// returns the keywords (dataset 2:25) of an iptc iim block.
+pub fn parse_iptc_keywords(iptc: &[u8]) -> Vec<String> {
+    let mut keywords = vec![];
+    let mut i = 0;
+
+    while i + 5 <= iptc.len() {
+        if iptc[i] != 0x1C {
+            i += 1;
+            continue;
+        }
+
+        let record = iptc[i + 1];
+        let dataset = iptc[i + 2];
+        let len = ((iptc[i + 3] as usize) << 8) | iptc[i + 4] as usize;
+
+        // extended datasets have the high bit of the length set. keywords are never that long.
+        if len & 0x8000 != 0 {
+            break;
+        }
+
+        let data_start = i + 5;
+        let data_end = data_start + len;
+        if data_end > iptc.len() {
+            break;
+        }
+
+        if record == 2 && dataset == 25 {
+            let keyword = String::from_utf8_lossy(&iptc[data_start..data_end]).trim().to_string();
+            if keyword.len() > 0 {
+                keywords.push(keyword);
+            }
+        }
+
+        i = data_end;
+    }
+
+    keywords
+}
+
+// walks the jpeg segments and returns the iptc block stored in the photoshop APP13 segment, if any.
+fn find_jpeg_iptc(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+        return None;
+    }
+
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xFF {
+            return None;
+        }
+
+        let marker = data[i + 1];
+
+        // start of scan, no more metadata after this
+        if marker == 0xDA {
+            return None;
+        }
+
+        // the length counts it's own two bytes, so anything shorter is a broken file.
+        let len = ((data[i + 2] as usize) << 8) | data[i + 3] as usize;
+        if len < 2 {
+            return None;
+        }
+
+        let segment_end = std::cmp::min(i + 2 + len, data.len());
+        let segment = &data[i + 4..segment_end];
+
+        if marker == 0xED && segment.starts_with(b"Photoshop 3.0\0") {
+            let mut j = "Photoshop 3.0\0".len();
+
+            // 8BIM resources: signature, id, padded pascal string name, size, padded data
+            while j + 12 <= segment.len() && &segment[j..j + 4] == b"8BIM" {
+                let id = ((segment[j + 4] as u16) << 8) | segment[j + 5] as u16;
+
+                let name_len = segment[j + 6] as usize;
+                let mut k = j + 7 + name_len;
+                if (name_len + 1) % 2 != 0 { k += 1; }
+
+                if k + 4 > segment.len() {
+                    break;
+                }
+
+                let size = ((segment[k] as usize) << 24) | ((segment[k + 1] as usize) << 16) |
+                           ((segment[k + 2] as usize) << 8) | segment[k + 3] as usize;
+
+                let res_start = k + 4;
+                let res_end = std::cmp::min(res_start + size, segment.len());
+
+                if id == 0x0404 {
+                    return Some(&segment[res_start..res_end]);
+                }
+
+                j = res_end + (size % 2);
+            }
+        }
+
+        i = segment_end;
+    }
+
+    None
+}
+
+fn find_xmp_packet(data: &[u8]) -> Option<String> {
+    let start = find_bytes(data, b"<x:xmpmeta")?;
+    let end = find_bytes(&data[start..], b"</x:xmpmeta>")? + start;
+
+    Some(String::from_utf8_lossy(&data[start..end]).to_string())
+}
+
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    let mut name = image_path.as_os_str().to_os_string();
+    name.push(".xmp");
+    PathBuf::from(name)
+}
+
+// reads the keywords from the image's embedded xmp and iptc metadata, and from it's xmp sidecar
+// if there is one. duplicates are removed.
+pub fn read_embedded_keywords(image_path: &Path) -> Result<Vec<String>> {
+    let data = std::fs::read(image_path)?;
+
+    let mut keywords = vec![];
+
+    if let Some(xmp) = find_xmp_packet(&data) {
+        keywords.extend(parse_xmp_subjects(&xmp));
+    }
+
+    if let Some(iptc) = find_jpeg_iptc(&data) {
+        keywords.extend(parse_iptc_keywords(iptc));
+    }
+
+    if let Ok(sidecar) = std::fs::read_to_string(sidecar_path(image_path)) {
+        keywords.extend(parse_xmp_subjects(&sidecar));
+    }
+
+    let mut seen = HashSet::new();
+    keywords.retain(|k| seen.insert(k.clone()));
+
+    Ok(keywords)
+}
+
+fn make_subject_block(keywords: &Vec<String>) -> String {
+    let mut block = String::from("<dc:subject>\n    <rdf:Bag>\n");
+    for keyword in keywords {
+        block.push_str(&format!("     <rdf:li>{}</rdf:li>\n", escape_xml(keyword)));
+    }
+    block.push_str("    </rdf:Bag>\n   </dc:subject>");
+    block
+}
+
+pub fn make_xmp_sidecar(keywords: &Vec<String>) -> String {
+    format!(
+"<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">
+ <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">
+  <rdf:Description rdf:about=\"\"
+    xmlns:dc=\"http://purl.org/dc/elements/1.1/\">
+   {}
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end=\"w\"?>
+", make_subject_block(keywords))
+}
+
+// replaces the dc:subject of an existing sidecar, keeping everything else in it intact.
+pub fn update_xmp_sidecar(existing: &str, keywords: &Vec<String>) -> Option<String> {
+    if let Some(start) = existing.find("<dc:subject") {
+        let end = existing[start..].find("</dc:subject>")? + start + "</dc:subject>".len();
+        return Some(format!("{}{}{}", &existing[..start], make_subject_block(keywords), &existing[end..]));
+    }
+
+    // no subject yet, so we add one right after the opening of the first description.
+    let desc = existing.find("<rdf:Description")?;
+    let desc_end = existing[desc..].find('>')? + desc;
+
+    // self closing description, we can't put anything in it.
+    if existing[..desc_end].ends_with('/') {
+        return None;
+    }
+
+    let block = make_subject_block(keywords)
+        .replacen("<dc:subject>", "<dc:subject xmlns:dc=\"http://purl.org/dc/elements/1.1/\">", 1);
+
+    Some(format!("{}\n   {}{}", &existing[..desc_end + 1], block, &existing[desc_end + 1..]))
+}
+
+impl TifariDb
+{
+    // gives the image a tag for every keyword embedded in it's file. returns the number of tags given.
+    pub fn import_embedded_keywords(&mut self, cfg: &TifariConfig, image_id: i64) -> Result<usize> {
+        let path = self.get_image_file_path(cfg, image_id)?;
+
+        let tags: Vec<String> = read_embedded_keywords(&path)?
+            .iter()
+            .map(|k| keyword_to_tag(k))
+            .filter(|t| TifariDb::is_valid_tag(t))
+            .collect();
+
+        self.import_tags(image_id, &tags)
+    }
+
+    // imports the embedded keywords of every image. returns the number of tags given.
+    pub fn import_all_embedded_keywords(&mut self, cfg: &TifariConfig) -> Result<usize> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare("SELECT id FROM images")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        let mut given = 0;
+        for id in ids {
+            match self.import_embedded_keywords(cfg, id) {
+                Ok(num) => given += num,
+                Err(e) => println!("Failed to import keywords of image {}. Error: {:?}", id, e),
+            }
+        }
+
+        Ok(given)
+    }
+
+    // writes the image's tags into it's xmp sidecar, creating the sidecar if it doesn't exist.
+    pub fn write_xmp_sidecar(&self, cfg: &TifariConfig, image_id: i64) -> Result<()> {
+        let image = self.get_image_from_db(image_id)?;
+
+        let mut keywords: Vec<String> = image.get_tags().iter().map(|t| t.get_name().clone()).collect();
+        keywords.sort();
+
+        let path = sidecar_path(&self.get_image_file_path(cfg, image_id)?);
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(existing) => match update_xmp_sidecar(&existing, &keywords) {
+                Some(v) => v,
+                None => {
+                    println!("Not touching xmp sidecar {:?}, couldn't find where to put the keywords.", path);
+                    return Ok(());
+                }
+            },
+            Err(_) => make_xmp_sidecar(&keywords),
+        };
+
+        std::fs::write(&path, contents)?;
+        Ok(())
+    }
+
+    // writes the sidecars of every image. returns the number of sidecars written.
+    pub fn sync_xmp_sidecars(&self, cfg: &TifariConfig) -> Result<usize> {
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare("SELECT id FROM images")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        let mut written = 0;
+        for id in ids {
+            match self.write_xmp_sidecar(cfg, id) {
+                Ok(()) => written += 1,
+                Err(e) => println!("Failed to write xmp sidecar for image {}. Error: {:?}", id, e),
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xmp_subject_roundtrip() {
+        let keywords = vec!["hand".to_string(), "R&D".to_string()];
+        let sidecar = make_xmp_sidecar(&keywords);
+
+        assert_eq!(parse_xmp_subjects(&sidecar), keywords);
+
+        let updated = update_xmp_sidecar(&sidecar, &vec!["foot".to_string()]).unwrap();
+        assert_eq!(parse_xmp_subjects(&updated), vec!["foot".to_string()]);
+        assert!(updated.contains("<x:xmpmeta"));
+    }
+
+    #[test]
+    fn xmp_subject_added_to_existing_sidecar() {
+        let existing = "<x:xmpmeta><rdf:RDF><rdf:Description rdf:about=\"\" xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\">\
+                        <xmp:Rating>5</xmp:Rating></rdf:Description></rdf:RDF></x:xmpmeta>";
+
+        let updated = update_xmp_sidecar(existing, &vec!["hand".to_string()]).unwrap();
+        assert_eq!(parse_xmp_subjects(&updated), vec!["hand".to_string()]);
+        assert!(updated.contains("<xmp:Rating>5</xmp:Rating>"));
+    }
+
+    #[test]
+    fn iptc_keywords() {
+        let mut iptc = vec![0x1C, 2, 5, 0, 5];
+        iptc.extend(b"title");
+        iptc.extend(&[0x1C, 2, 25, 0, 4]);
+        iptc.extend(b"hand");
+        iptc.extend(&[0x1C, 2, 25, 0, 8]);
+        iptc.extend(b"red hair");
+
+        let keywords = parse_iptc_keywords(&iptc);
+        assert_eq!(keywords, vec!["hand".to_string(), "red hair".to_string()]);
+        assert_eq!(keyword_to_tag(&keywords[1]), "red_hair");
+
+        // segments too short to hold their own length end the walk instead of panicking.
+        assert_eq!(find_jpeg_iptc(&[0xFF, 0xD8, 0xFF, 0xED, 0, 0, 0xFF, 0xDA]), None);
+        assert_eq!(find_jpeg_iptc(&[0xFF, 0xD8, 0xFF, 0xED, 0, 1]), None);
+        assert_eq!(find_jpeg_iptc(&[0xFF, 0xD8, 0xFF, 0xED, 0, 200, b'P']), None);
+    }
+}
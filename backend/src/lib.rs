@@ -1,14 +1,49 @@
-#![feature(try_trait)]
 extern crate rusqlite;
 extern crate notify;
 extern crate chrono;
 extern crate models;
 extern crate serde;
+extern crate md5;
+extern crate image;
+extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
 
 mod error;
+mod regions;
+mod metadata;
+mod saved_searches;
+mod journal;
+mod hashing;
+mod export;
+mod keywords;
+mod captions;
+mod booru;
+mod backup;
+mod integrity;
+mod roots;
+mod missing;
+mod relink;
+mod files;
+mod random;
+mod gesture;
+mod stats;
+mod related;
+mod suggestions;
+mod tag_queue;
+mod pool;
+
+pub use self::hashing::{hash_file, visual_hash_file, visual_distance};
+pub use self::export::EXPORT_VERSION;
+pub use self::keywords::{read_embedded_keywords, sidecar_path};
+pub use self::captions::{CaptionOptions, CaptionOrder, CaptionConflictPolicy, caption_path, caption_is_shared};
+pub use self::backup::{list_snapshots, validate_snapshot};
+pub use self::roots::{ImageRoot, DEFAULT_ROOT_NAME, is_valid_root_name};
+pub use self::files::{TRASH_DIR_NAME, is_valid_file_name, detect_image_type};
+pub use self::tag_queue::{TagQueueOrder, DEFAULT_DEFER_SECONDS};
+pub use self::pool::{DbPool, PooledDb, DEFAULT_POOL_SIZE};
+pub mod query;
 
 pub use self::error::*;
 use std::sync::{Arc, RwLock};
@@ -21,13 +56,49 @@ pub struct TifariConfig
     api_address: String,
     frontend_address: String,
     db_root : String,
+    // the root named "default"
     image_root: String,
+    // every other root
+    #[serde(default)]
+    roots: Vec<ImageRoot>,
+    // import the xmp/iptc keywords of new images as tags while scanning
+    #[serde(default)]
+    import_embedded_keywords: bool,
+    // keep xmp sidecars of images in sync with their tags
+    #[serde(default)]
+    write_xmp_sidecars: bool,
+    // import the tags in the .txt caption files next to new images while scanning
+    #[serde(default)]
+    import_captions: bool,
+    #[serde(default = "default_caption_separator")]
+    caption_separator: String,
+    // where database snapshots are kept and how many of them. 0 keeps every snapshot.
+    #[serde(default = "default_backup_dir")]
+    backup_dir: String,
+    #[serde(default = "default_backup_keep")]
+    backup_keep: usize,
+    // images whose files went missing keep their tags for this many days, in case the files come back.
+    #[serde(default = "default_missing_retention_days")]
+    missing_retention_days: u64,
+    // a scan that would find more than this fraction of the library missing is refused unless forced.
+    #[serde(default = "default_max_missing_fraction")]
+    max_missing_fraction: f64,
 }
 
+fn default_caption_separator() -> String { String::from(",") }
+fn default_backup_dir() -> String { String::from("backups") }
+fn default_backup_keep() -> usize { 10 }
+fn default_missing_retention_days() -> u64 { 30 }
+fn default_max_missing_fraction() -> f64 { 0.25 }
+
 pub struct ScanData {
     is_scanning: std::sync::atomic::AtomicBool,
     scan_total: std::sync::atomic::AtomicUsize,
     scan_current: std::sync::atomic::AtomicUsize,
+    // makes the next scan go through even if it would find too many images missing.
+    force_next: std::sync::atomic::AtomicBool,
+    // how many images the last scan would've found missing, if it was refused for it.
+    refused_missing: std::sync::atomic::AtomicUsize,
 }
 
 impl ScanData {
@@ -36,6 +107,8 @@ impl ScanData {
             is_scanning: std::sync::atomic::AtomicBool::new(false), 
             scan_total: std::sync::atomic::AtomicUsize::new(0), 
             scan_current: std::sync::atomic::AtomicUsize::new(0), 
+            force_next: std::sync::atomic::AtomicBool::new(false),
+            refused_missing: std::sync::atomic::AtomicUsize::new(0),
         }
     }
 
@@ -57,6 +130,19 @@ impl ScanData {
     pub fn set_scan_current(&self, current: usize)
     { self.scan_current.store(current, std::sync::atomic::Ordering::Release) }
 
+    pub fn get_refused_missing(&self) -> usize
+    { self.refused_missing.load(std::sync::atomic::Ordering::Acquire) }
+
+    pub fn set_refused_missing(&self, num: usize)
+    { self.refused_missing.store(num, std::sync::atomic::Ordering::Release) }
+
+    pub fn set_force_next(&self, force: bool)
+    { self.force_next.store(force, std::sync::atomic::Ordering::Release) }
+
+    // returns whether the next scan is forced, resetting it.
+    pub fn take_force_next(&self) -> bool
+    { self.force_next.swap(false, std::sync::atomic::Ordering::AcqRel) }
+
 }
 
 impl TifariConfig 
@@ -67,6 +153,15 @@ impl TifariConfig
             frontend_address: String::from("127.0.0.1:3555"),
             db_root: String::from("image_and_tag.db"),
             image_root: String::from(""),
+            roots: vec![],
+            import_embedded_keywords: false,
+            write_xmp_sidecars: false,
+            import_captions: false,
+            caption_separator: default_caption_separator(),
+            backup_dir: default_backup_dir(),
+            backup_keep: default_backup_keep(),
+            missing_retention_days: default_missing_retention_days(),
+            max_missing_fraction: default_max_missing_fraction(),
         }
     }
 
@@ -74,22 +169,87 @@ impl TifariConfig
     pub fn get_frontend_address(&self) -> &String { &self.frontend_address }
     pub fn get_root(&self) -> &String { &self.image_root }
     pub fn get_db_root(&self) -> &String{ &self.db_root }
+    pub fn get_import_embedded_keywords(&self) -> bool { self.import_embedded_keywords }
+    pub fn get_write_xmp_sidecars(&self) -> bool { self.write_xmp_sidecars }
+    pub fn get_import_captions(&self) -> bool { self.import_captions }
+    pub fn get_caption_separator(&self) -> &String { &self.caption_separator }
+    pub fn get_backup_dir(&self) -> &String { &self.backup_dir }
+    pub fn get_backup_keep(&self) -> usize { self.backup_keep }
+    pub fn get_missing_retention_days(&self) -> u64 { self.missing_retention_days }
+    pub fn get_max_missing_fraction(&self) -> f64 { self.max_missing_fraction }
+
+    // every configured root, starting with the default one when image_root is set.
+    pub fn get_roots(&self) -> Vec<ImageRoot> {
+        let mut roots = vec![];
+
+        if self.image_root.len() > 0 {
+            roots.push(ImageRoot::new(DEFAULT_ROOT_NAME.to_string(), self.image_root.clone()));
+        }
+
+        roots.extend(self.roots.iter().filter(|r| r.get_name() != DEFAULT_ROOT_NAME).cloned());
+        roots
+    }
+
+    pub fn get_root_by_name(&self, name: &str) -> Option<ImageRoot> {
+        self.get_roots().into_iter().find(|r| r.get_name() == name)
+    }
 
 
     pub fn update(&mut self, patch: HashMap<String, String>) {
         let mut patch = patch;
 
-        let mut apply_patch = |var: &mut String, var_name: &str| {
-            match patch.remove(var_name) {
-                Some(val) => *var = val,
-                None => {},
+        {
+            let mut apply_patch = |var: &mut String, var_name: &str| {
+                match patch.remove(var_name) {
+                    Some(val) => *var = val,
+                    None => {},
+                }
+            };
+
+            apply_patch(&mut self.api_address, "api_address");
+            apply_patch(&mut self.frontend_address, "frontend_address");
+            apply_patch(&mut self.db_root, "db_root");
+            apply_patch(&mut self.image_root, "image_root");
+            apply_patch(&mut self.caption_separator, "caption_separator");
+            apply_patch(&mut self.backup_dir, "backup_dir");
+        }
+
+        {
+            let mut apply_bool_patch = |var: &mut bool, var_name: &str| {
+                match patch.remove(var_name) {
+                    Some(val) => *var = val == "true",
+                    None => {},
+                }
+            };
+
+            apply_bool_patch(&mut self.import_embedded_keywords, "import_embedded_keywords");
+            apply_bool_patch(&mut self.write_xmp_sidecars, "write_xmp_sidecars");
+            apply_bool_patch(&mut self.import_captions, "import_captions");
+        }
+
+        if let Some(keep) = patch.remove("backup_keep").and_then(|v| v.parse().ok()) {
+            self.backup_keep = keep;
+        }
+
+        if let Some(days) = patch.remove("missing_retention_days").and_then(|v| v.parse().ok()) {
+            self.missing_retention_days = days;
+        }
+
+        if let Some(fraction) = patch.remove("max_missing_fraction").and_then(|v| v.parse::<f64>().ok()) {
+            if fraction >= 0.0 {
+                self.max_missing_fraction = fraction;
             }
-        };
+        }
 
-        apply_patch(&mut self.api_address, "api_address");
-        apply_patch(&mut self.frontend_address, "frontend_address");
-        apply_patch(&mut self.db_root, "db_root");
-        apply_patch(&mut self.image_root, "image_root");
+        // roots come in as a json list of { "name", "path" }. roots with bad or duplicate names are dropped.
+        if let Some(roots) = patch.remove("roots").and_then(|v| serde_json::from_str::<Vec<ImageRoot>>(&v).ok()) {
+            let mut names = HashSet::new();
+
+            self.roots = roots.into_iter()
+                .filter(|r| is_valid_root_name(r.get_name()) && r.get_name() != DEFAULT_ROOT_NAME)
+                .filter(|r| names.insert(r.get_name().clone()))
+                .collect();
+        }
     }
 }
 
@@ -102,12 +262,17 @@ impl TifariDb
 {
     pub fn get_image_from_db(&self, id: i64) -> Result<models::Image>
     {
-        let (id, path, time): (i64, String, i64) = self.connection.query_row(
-            "SELECT id, path, created_at_time
-            FROM images 
-            WHERE id=?",
+        let (id, path, time, root, missing_since): (i64, String, i64, String, Option<i64>) = match self.connection.query_row(
+            "SELECT images.id, images.path, images.created_at_time, roots.name, images.missing_since
+            FROM images JOIN roots ON roots.id = images.root_id
+            WHERE images.id=?",
             &[&id],
-            |row| (row.get(0), row.get(1), row.get(2)))?;
+            |row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4)))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(BackendError::ImageNotFound),
+            Err(e) => return Err(BackendError::from(e)),
+        };
 
         let mut statement = self.connection.prepare(
             &format!("SELECT id, name 
@@ -122,7 +287,13 @@ impl TifariDb
             tags.insert(models::Tag::new(tag_id, tag_name));
         }
 
-        Ok(models::Image::new(id, path, time, tags))
+        let mut image = models::Image::new(id, path, time, tags);
+        image.set_root(root);
+        image.set_missing_since(missing_since);
+        image.set_regions(self.get_regions_of_image(id)?);
+        image.set_metadata(self.get_image_metadata(id)?);
+
+        Ok(image)
     }
 
     fn insert_into_tag_queue(tx: &rusqlite::Transaction, image_id: i64) -> Result<()> {
@@ -130,19 +301,27 @@ impl TifariDb
             "INSERT INTO tag_queue (id, image_id) VALUES (null, :image_id)",
             &[(":image_id", &image_id)])?;
 
+        TifariDb::reset_tag_queue_state(tx, image_id)?;
+
         Ok(())
     }
 
+    // inserts the image into the default root.
     pub fn try_insert_image(&mut self, path: &str) -> Result<i64>
     {
-        let tx = self.connection.transaction()?;
+        self.try_insert_image_in_root(roots::DEFAULT_ROOT_ID, path)
+    }
+
+    pub fn try_insert_image_in_root(&mut self, root_id: i64, path: &str) -> Result<i64>
+    {
+        let tx = self.write_transaction()?;
 
         let exists = 
         {
             let mut statement = tx.prepare(
-                "SELECT id FROM images WHERE path=? LIMIT 1")?;
+                "SELECT id FROM images WHERE root_id=? AND path=? LIMIT 1")?;
 
-            match statement.exists(&[&path]) {
+            match statement.exists(&[&root_id, &path]) {
                 Ok(val) => Ok(val),
                 Err(e) => Err(BackendError::SQLite(e)),
             }
@@ -154,9 +333,10 @@ impl TifariDb
         }
 
         tx.execute_named(
-            "INSERT INTO images (id, path, created_at_time) 
-            VALUES (null, :path, :time)",
-            &[(":path", &path),
+            "INSERT INTO images (id, root_id, path, created_at_time) 
+            VALUES (null, :root_id, :path, :time)",
+            &[(":root_id", &root_id),
+              (":path", &path),
               (":time", &chrono::Utc::now().timestamp())
             ])?;
 
@@ -180,8 +360,14 @@ impl TifariDb
             &[],
             |row| row.get(0))?;
 
+        // regions hold tags too, so those count as references as well
+        let num_regions_using_tag: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM region_tags WHERE tag_id=?",
+            &[&tag_id],
+            |row| row.get(0))?;
+
         // check if any images still reference this tag
-        if 0 >= num_rows_in_image_ids_table 
+        if 0 >= num_rows_in_image_ids_table && 0 >= num_regions_using_tag
         {
             // if not, drop the image_id table and the tag entry from the database
             tx.execute(
@@ -207,25 +393,50 @@ impl TifariDb
         Ok(())
     }
 
+    // erases the image with the path from the default root.
     pub fn erase_image(&mut self, path: &str) -> Result<()>
     {
-        let tx = self.connection.transaction()?;
+        match self.find_image_in_root(roots::DEFAULT_ROOT_ID, path)? {
+            Some(image_id) => self.erase_image_by_id(image_id),
+            None => Err(BackendError::ImageNotFound),
+        }
+    }
 
-        // get image id and it's tag array table id from db
-        let image_id: i64 = tx.query_row(
-            "SELECT id FROM images WHERE path=? LIMIT 1",
-            &[&path],
-            |row| { row.get(0) })?;
+    pub fn erase_image_by_id(&mut self, image_id: i64) -> Result<()>
+    {
+        let tx = self.write_transaction()?;
+        TifariDb::erase_image_tx(&tx, image_id)?;
+        tx.commit()?;
+        Ok(())
+    }
 
+    pub(crate) fn erase_image_tx(tx: &rusqlite::Transaction, image_id: i64) -> Result<()>
+    {
         // delete the image
         tx.execute(
             "DELETE FROM images WHERE id=?",
             &[&image_id])?;
 
-        TifariDb::remove_image_from_tag_queue(&tx, image_id)?;
+        TifariDb::remove_image_from_tag_queue(tx, image_id)?;
+        tx.execute("DELETE FROM tag_queue_states WHERE image_id=?", &[&image_id])?;
+
+        TifariDb::erase_regions_of_image(tx, image_id)?;
+        TifariDb::erase_metadata_of_image(tx, image_id)?;
+        tx.execute("DELETE FROM image_files WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM image_visual_hashes WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM caption_sync WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM trash WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM random_history WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM gesture_session_images WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM image_views WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM drawings WHERE image_id=?", &[&image_id])?;
     
+        TifariDb::remove_image_tag_pairs(tx, image_id)?;
+
         // gets all the tag ids and their image id array tables that contain this image id.
-        {
+        // they're collected before any of them are touched, as sqlite won't drop the table of an
+        // unused tag while the statement reading this image's tags is still going.
+        let tag_ids: Vec<i64> = {
             let mut statement = tx.prepare(
                 &format!("SELECT id 
                          FROM tags 
@@ -233,20 +444,22 @@ impl TifariDb
                              SELECT * from tags_array_table_{}
                          )", image_id))?;
 
-            // iterates over all the image id array table ids
-            for result in statement.query_map(&[], 
-                    |row| row.get::<i32, i64>(0))?
-            {
-                let tag_id = result?;
+            let mut tag_ids = vec![];
+            for result in statement.query_map(&[], |row| row.get::<usize, i64>(0))? {
+                tag_ids.push(result?);
+            }
+            tag_ids
+        };
 
-                // ...and deletes the image id from them.
-                tx.execute(
-                    &format!("DELETE FROM image_ids_array_table_{} WHERE image_id=?", tag_id),
-                    &[&image_id])?;
+        // iterates over all the image id array table ids
+        for tag_id in tag_ids {
+            // ...and deletes the image id from them.
+            tx.execute(
+                &format!("DELETE FROM image_ids_array_table_{} WHERE image_id=?", tag_id),
+                &[&image_id])?;
 
-                // ...and erase the tag from the db if it's no longer referenced by and image
-                TifariDb::erase_tag_if_not_used(&tx, tag_id)?;
-            }
+            // ...and erase the tag from the db if it's no longer referenced by and image
+            TifariDb::erase_tag_if_not_used(tx, tag_id)?;
         }
 
         // lastly, we drop the image's tag_array_table
@@ -254,7 +467,6 @@ impl TifariDb
             &format!("DROP TABLE IF EXISTS tags_array_table_{}", image_id),
             &[])?;
 
-        tx.commit()?;
         Ok(())
     }
 
@@ -271,22 +483,148 @@ impl TifariDb
                     id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
                     path TEXT NOT NULL,
                     created_at_time INTEGER NOT NULL,
+                    root_id INTEGER NOT NULL DEFAULT 1,
                     UNIQUE(id, path));
 
+            CREATE TABLE IF NOT EXISTS roots (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    name TEXT NOT NULL UNIQUE);
+
             CREATE TABLE IF NOT EXISTS tag_queue (
                     id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
                     image_id INTEGER NOT NULL,
                     UNIQUE(id, image_id));
 
+            CREATE TABLE IF NOT EXISTS tag_queue_states (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    needs_review INTEGER NOT NULL,
+                    deferred_until INTEGER,
+                    done INTEGER NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS regions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    image_id INTEGER NOT NULL,
+                    x REAL NOT NULL,
+                    y REAL NOT NULL,
+                    width REAL NOT NULL,
+                    height REAL NOT NULL,
+                    note TEXT NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS region_tags (
+                    region_id INTEGER NOT NULL,
+                    tag_id INTEGER NOT NULL,
+                    UNIQUE(region_id, tag_id));
+
+            CREATE TABLE IF NOT EXISTS image_metadata (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    artist TEXT NOT NULL,
+                    license TEXT NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS image_sources (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    image_id INTEGER NOT NULL,
+                    url TEXT NOT NULL,
+                    UNIQUE(image_id, url));
+
+            CREATE TABLE IF NOT EXISTS saved_searches (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    name TEXT NOT NULL UNIQUE,
+                    query TEXT NOT NULL,
+                    sort_order TEXT NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS journal (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    created_at_time INTEGER NOT NULL,
+                    operation TEXT NOT NULL,
+                    undone INTEGER NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS journal_changes (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    journal_id INTEGER NOT NULL,
+                    kind TEXT NOT NULL,
+                    target_id INTEGER NOT NULL,
+                    tag_name TEXT NOT NULL,
+                    new_tag_name TEXT NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS image_files (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    hash TEXT NOT NULL,
+                    size INTEGER NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS image_visual_hashes (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    hash INTEGER);
+
+            CREATE TABLE IF NOT EXISTS caption_sync (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    file_hash TEXT NOT NULL,
+                    tags_hash TEXT NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS random_history (
+                    request_id INTEGER NOT NULL,
+                    image_id INTEGER NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS gesture_sessions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    query TEXT NOT NULL,
+                    schedule TEXT NOT NULL,
+                    created_at_time INTEGER NOT NULL,
+                    finished_at_time INTEGER,
+                    position INTEGER NOT NULL,
+                    slot_started_at INTEGER NOT NULL,
+                    paused_at INTEGER);
+
+            CREATE TABLE IF NOT EXISTS gesture_session_images (
+                    session_id INTEGER NOT NULL,
+                    slot INTEGER NOT NULL,
+                    image_id INTEGER NOT NULL,
+                    duration INTEGER NOT NULL,
+                    seconds_spent INTEGER,
+                    skipped INTEGER NOT NULL,
+                    UNIQUE(session_id, slot));
+
+            CREATE TABLE IF NOT EXISTS image_views (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    view_count INTEGER NOT NULL,
+                    last_viewed_at INTEGER NOT NULL);
+
+            CREATE TABLE IF NOT EXISTS drawings (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+                    image_id INTEGER NOT NULL,
+                    drawn_at_time INTEGER NOT NULL,
+                    seconds INTEGER);
+
+            CREATE TABLE IF NOT EXISTS trash (
+                    image_id INTEGER PRIMARY KEY NOT NULL,
+                    file_name TEXT NOT NULL,
+                    trashed_at_time INTEGER NOT NULL);
+
             COMMIT;
         ")?;
 
+        self.migrate_image_roots()?;
+        self.migrate_missing_images()?;
+        self.migrate_tag_pairs()?;
+
         Ok(())
     }
 
+    pub(crate) fn has_column(&self, table: &str, column: &str) -> Result<bool> {
+        let mut statement = self.connection.prepare(&format!("PRAGMA table_info({})", table))?;
+
+        for result in statement.query_map(&[], |row| row.get::<usize, String>(1))? {
+            if result? == column {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
     pub fn new(cfg: Arc<RwLock<TifariConfig>>) -> Result<Self> 
     {
         let conn = rusqlite::Connection::open(cfg.read().unwrap().get_db_root())?;
+        TifariDb::configure_connection(&conn)?;
         let db = TifariDb { connection: conn };
 
         Ok(db)
@@ -300,19 +638,18 @@ impl TifariDb
         Ok(db)
     }
 
-    pub fn give_tag(&mut self, image_id: i64, tag: &str) -> Result<i64>
-    {
-        if tag.starts_with("-") || 
+    fn is_valid_tag(tag: &str) -> bool {
+        !(tag.starts_with("-") || 
             tag.starts_with(" ") ||
             tag.starts_with("\t") ||
             tag.starts_with("\r") ||
             tag.starts_with("\n") ||
-            tag.len() <= 0 {
-            return Err(BackendError::BadTag); 
-        }
-
-        let tx = self.connection.transaction()?;
+            tag.len() <= 0)
+    }
 
+    // returns the id of the tag with the given name, creating the tag (and it's image id array table) if needed.
+    fn get_or_create_tag(tx: &rusqlite::Transaction, tag: &str) -> Result<i64>
+    {
         let tag_id: i64 = match tx.query_row(
                             "SELECT id
                             FROM tags 
@@ -343,7 +680,46 @@ impl TifariDb
             }
         }?;
 
-        TifariDb::remove_image_from_tag_queue(&tx, image_id)?;
+        Ok(tag_id)
+    }
+
+    pub(crate) fn image_exists(tx: &rusqlite::Transaction, image_id: i64) -> Result<bool>
+    {
+        let mut statement = tx.prepare("SELECT id FROM images WHERE id=? LIMIT 1")?;
+        Ok(statement.exists(&[&image_id])?)
+    }
+
+    // expects the image to exist.
+    pub(crate) fn image_has_tag(tx: &rusqlite::Transaction, image_id: i64, tag_id: i64) -> Result<bool>
+    {
+        let mut statement = tx.prepare(
+            &format!("SELECT tag_id FROM tags_array_table_{} WHERE tag_id=? LIMIT 1", image_id))?;
+        Ok(statement.exists(&[&tag_id])?)
+    }
+
+    pub(crate) fn get_tag_id(tx: &rusqlite::Transaction, tag: &str) -> Result<Option<i64>>
+    {
+        match tx.query_row("SELECT id FROM tags WHERE name=? LIMIT 1", &[&tag], |row| row.get(0)) {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    pub(crate) fn get_tag_name(tx: &rusqlite::Transaction, tag_id: i64) -> Result<String>
+    {
+        match tx.query_row("SELECT name FROM tags WHERE id=?", &[&tag_id], |row| row.get(0)) {
+            Ok(name) => Ok(name),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(BackendError::TagNotFound(tag_id)),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    pub(crate) fn add_tag_tx(tx: &rusqlite::Transaction, image_id: i64, tag: &str) -> Result<i64>
+    {
+        let tag_id = TifariDb::get_or_create_tag(tx, tag)?;
+
+        TifariDb::remove_image_from_tag_queue(tx, image_id)?;
 
         tx.execute(
             &format!("INSERT INTO image_ids_array_table_{} (image_id)
@@ -355,25 +731,29 @@ impl TifariDb
                      VALUES (?)", image_id),
             &[&tag_id])?;
 
-        tx.commit()?;
+        TifariDb::add_tag_pairs(tx, image_id, tag_id)?;
+
         Ok(tag_id)
     }
 
-    pub fn remove_tag(&mut self, image_id: i64, tag_id: i64) -> Result<()>
+    // returns whether the image had the tag.
+    pub(crate) fn remove_tag_tx(tx: &rusqlite::Transaction, image_id: i64, tag_id: i64) -> Result<bool>
     {
-        let tx = self.connection.transaction()?;
-
         // remove tag id from image tag array
-        tx.execute(
+        let removed = tx.execute(
             &format!("DELETE FROM tags_array_table_{} WHERE tag_id=?", image_id),
             &[&tag_id])?;
 
+        if removed > 0 {
+            TifariDb::remove_tag_pairs(tx, image_id, tag_id)?;
+        }
+
         // remove image id from tag image array
         tx.execute(
             &format!("DELETE FROM image_ids_array_table_{} WHERE image_id=?", tag_id),
             &[&image_id])?;
 
-        TifariDb::erase_tag_if_not_used(&tx, tag_id)?;
+        TifariDb::erase_tag_if_not_used(tx, tag_id)?;
 
         // get the number of tags this image has
         let tag_count: i64 = tx.query_row(
@@ -382,252 +762,609 @@ impl TifariDb
             |row| row.get(0))?;
 
         if 0 >= tag_count {
-            TifariDb::insert_into_tag_queue(&tx, image_id)?;
+            TifariDb::insert_into_tag_queue(tx, image_id)?;
         }
 
-        tx.commit()?;
-        Ok(())
+        Ok(removed > 0)
     }
 
-    pub fn get_tag_queue(&self) -> Result<Vec<models::Image>> 
+    pub fn give_tag(&mut self, image_id: i64, tag: &str) -> Result<i64>
     {
-        let mut statement = self.connection.prepare(
-            "SELECT id, path, created_at_time
-            FROM images
-            WHERE id IN (SELECT image_id 
-                        FROM tag_queue)
-            ORDER BY id DESC"
-            )?;
+        if !TifariDb::is_valid_tag(tag) {
+            return Err(BackendError::BadTag); 
+        }
 
-        let mut results = vec![];
-        for result in statement.query_map(&[], |row| (row.get(0), row.get(1), row.get(2)))?
-        {
-            let result = result?;
-            let (id, path, created_at_time) = (result.0, result.1, result.2);
+        let tx = self.write_transaction()?;
 
-            results.push(models::Image::new_no_tags(id, path, created_at_time));
+        // every image has it's own tag table, which isn't there for an image that doesn't exist.
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
         }
 
-        Ok(results)
+        let tag_id = TifariDb::add_tag_tx(&tx, image_id, tag)?;
+
+        TifariDb::record_journal(&tx, "add_tag", &vec![journal::Change::add(image_id, tag)])?;
+
+        tx.commit()?;
+        Ok(tag_id)
     }
 
-    fn make_tag_id_list(&self, tags: &Vec<&str>) -> Result<String>
+    pub fn remove_tag(&mut self, image_id: i64, tag_id: i64) -> Result<()>
     {
-        if tags.len() <= 0
-        {
-            return Ok(String::from("()"));
-        }
+        let tx = self.write_transaction()?;
 
-        let mut query = "SELECT id FROM tags WHERE name IN (".to_string();
-        let mut params: Vec<&rusqlite::types::ToSql> = Vec::with_capacity(tags.len());
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
+        }
 
-        query.push_str(&"?, ".repeat(tags.len() - 1));
-        query.push_str("?)");
+        let tag_name = TifariDb::get_tag_name(&tx, tag_id)?;
 
-        for i in 0..tags.len()
-        {
-            params.push(&tags[i]);
+        if TifariDb::remove_tag_tx(&tx, image_id, tag_id)? {
+            TifariDb::record_journal(&tx, "remove_tag", &vec![journal::Change::remove(image_id, &tag_name)])?;
         }
 
-        let mut statement = self.connection.prepare(&query)?;
-
-        let mut tag_ids_query = String::from("(");
-        let mut skip_first_comma = true;
-        for result in statement.query_map(params.as_slice(), |row| row.get(0))?
-        {
-            let result: i64 = result?;
+        tx.commit()?;
+        Ok(())
+    }
 
-            if !skip_first_comma
-            {
-                tag_ids_query.push_str(", ");
-            }
+    // gives every tag to every image in one transaction, skipping the pairs that can't be applied.
+    // returns the tags that were given, once per image.
+    pub fn give_tags(&mut self, image_ids: &Vec<i64>, tags: &Vec<String>) -> Result<Vec<models::Tag>>
+    {
+        let tx = self.write_transaction()?;
 
-            skip_first_comma = false;
-            tag_ids_query.push_str(&result.to_string());
-        }
+        let mut changes = vec![];
+        let given = TifariDb::give_tags_tx(&tx, image_ids, tags, &mut changes)?;
 
-        tag_ids_query.push(')');
+        TifariDb::record_journal(&tx, "add_tags", &changes)?;
 
-        Ok(tag_ids_query)
+        tx.commit()?;
+        Ok(given)
     }
 
-    pub fn search(&self, tags: &Vec<&str>) -> Result<Vec<models::Image>>
+    // like give_tags, but isn't journaled. for tags imported from the image's own files while
+    // scanning, which aren't something the user did and shouldn't throw away what they can redo.
+    pub(crate) fn import_tags(&mut self, image_id: i64, tags: &Vec<String>) -> Result<usize>
     {
-        if 0 >= tags.len()
-        {
-            return Ok(vec![]);
-        }
+        let tx = self.write_transaction()?;
 
-        let mut tags_contains = vec![];
-        let mut tags_remove = vec![];
+        let given = TifariDb::give_tags_tx(&tx, &vec![image_id], tags, &mut vec![])?;
 
-        for tag in tags {
-            if tag.starts_with("-") { tags_remove.push(&tag[1..]); }
-            else { tags_contains.push(*tag); }
-        }
+        tx.commit()?;
+        Ok(given.len())
+    }
 
-        let tag_ids_query = self.make_tag_id_list(&tags_contains)?;
-        let not_in_tag_ids = self.make_tag_id_list(&tags_remove)?;
+    fn give_tags_tx(tx: &rusqlite::Transaction,
+                    image_ids: &Vec<i64>,
+                    tags: &Vec<String>,
+                    changes: &mut Vec<journal::Change>) -> Result<Vec<models::Tag>>
+    {
+        let mut given = vec![];
 
-        let mut statement = self.connection.prepare(
-            "SELECT id 
-            FROM images 
-            ORDER BY id DESC")?;
+        for tag in tags {
+            if !TifariDb::is_valid_tag(tag) {
+                continue;
+            }
 
-        let mut results = vec![];
-        for result in statement.query_map(&[], |row| row.get(0))?
-        {
-            let image_id: i64 = result?;
+            for image_id in image_ids {
+                if !TifariDb::image_exists(tx, *image_id)? {
+                    continue;
+                }
 
-            let count: i64 = self.connection.query_row(
-                &format!("SELECT COUNT(*) 
-                         FROM tags_array_table_{}
-                         WHERE tag_id IN {} 
-                         AND NOT EXISTS(SELECT 1 FROM tags_array_table_{} WHERE tag_id IN {})",
-                         image_id, tag_ids_query, image_id, not_in_tag_ids),
-                         &[],
-                         |row| row.get(0))?;
+                if let Some(tag_id) = TifariDb::get_tag_id(tx, tag)? {
+                    if TifariDb::image_has_tag(tx, *image_id, tag_id)? {
+                        continue;
+                    }
+                }
 
-            let count = count as usize;
+                let tag_id = TifariDb::add_tag_tx(tx, *image_id, tag)?;
 
-            if count == tags_contains.len()
-            {
-                results.push(self.get_image_from_db(image_id)?);
+                given.push(models::Tag::new(tag_id, tag.clone()));
+                changes.push(journal::Change::add(*image_id, tag));
             }
         }
 
-        Ok(results)
+        Ok(given)
     }
 
-    pub fn get_num_elements_in_tag_queue(&self) -> Result<i64> {
+    // removes every tag from every image in one transaction, skipping the pairs that don't exist.
+    pub fn remove_tags(&mut self, image_ids: &Vec<i64>, tag_ids: &Vec<i64>) -> Result<()>
+    {
+        let tx = self.write_transaction()?;
 
-        let retval = self.connection.query_row(
-            "SELECT count(*) FROM tag_queue", 
-            &[],
-            |row| row.get(0))?;
+        let mut changes = vec![];
 
-        Ok(retval)
-    }
+        for tag_id in tag_ids {
+            for image_id in image_ids {
+                // the tag can get erased midway through, once it's removed from it's last image.
+                let tag_name: String = match tx.query_row("SELECT name FROM tags WHERE id=?", &[tag_id], |row| row.get(0)) {
+                    Ok(name) => name,
+                    Err(rusqlite::Error::QueryReturnedNoRows) => break,
+                    Err(e) => return Err(BackendError::from(e)),
+                };
 
-    pub fn get_all_tags(&self) -> Result<Vec<models::TagWithUsage>> {
-        let mut statement = self.connection.prepare("SELECT id, name FROM tags")?;
-        let mut retvals = vec![];
+                if !TifariDb::image_exists(&tx, *image_id)? || !TifariDb::image_has_tag(&tx, *image_id, *tag_id)? {
+                    continue;
+                }
 
-        for result in statement.query_map(&[], |row| (row.get(0), row.get(1)))? {
-            let result = result?;
-            let (id, name) = (result.0, result.1);
+                TifariDb::remove_tag_tx(&tx, *image_id, *tag_id)?;
+                changes.push(journal::Change::remove(*image_id, &tag_name));
+            }
+        }
 
-            let num_times_used = self.connection.query_row(
-                &format!("SELECT count(*) FROM image_ids_array_table_{}", id), 
-                &[],
-                |row| row.get(0))?;
+        TifariDb::record_journal(&tx, "remove_tags", &changes)?;
 
-            retvals.push(models::TagWithUsage::new(id, name, num_times_used));
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn rename_tag(&mut self, tag_id: i64, name: &str) -> Result<()>
+    {
+        if !TifariDb::is_valid_tag(name) {
+            return Err(BackendError::BadTag);
         }
 
-        Ok(retvals)
-    }
+        let tx = self.write_transaction()?;
 
-    fn get_all_image_paths(&self) -> Result<HashSet<String>> {
-        let mut db_imgs = HashSet::new();
-        let mut statement = self.connection.prepare("SELECT path FROM images")?;
+        let old_name = TifariDb::get_tag_name(&tx, tag_id)?;
 
-        for result in statement.query_map(&[], |row| row.get(0))? {
-            db_imgs.insert(result?);
+        if TifariDb::get_tag_id(&tx, name)?.is_some() {
+            return Err(BackendError::TagExists);
         }
 
-        Ok(db_imgs)
+        tx.execute("UPDATE tags SET name=? WHERE id=?", &[&name, &tag_id])?;
+
+        TifariDb::record_journal(&tx, "rename_tag", &vec![journal::Change::rename(&old_name, name)])?;
+
+        tx.commit()?;
+        Ok(())
     }
 
-    pub fn reload_root_unsafe(&mut self, root: &str, scan: &Arc<ScanData>) {
-        use std::fs;
+    // moves every use of the source tag onto the target tag, erasing the source tag.
+    pub fn merge_tags(&mut self, source_id: i64, target_id: i64) -> Result<()>
+    {
+        if source_id == target_id {
+            return Err(BackendError::NoChangesOccured);
+        }
 
-        println!("Starting root scan at \"{}\"", root);
+        let tx = self.write_transaction()?;
 
-        let iter = match fs::read_dir(root) {
-            Ok(v) => v,
-            Err(_) => { 
-                println!("Failed to start root scan. The image root directory is most likely invalid.");
-                return;
-            }
-        };
+        let source_name = TifariDb::get_tag_name(&tx, source_id)?;
+        let target_name = TifariDb::get_tag_name(&tx, target_id)?;
 
+        let mut changes = vec![];
 
-        let mut root_imgs = HashSet::new();
+        let image_ids: Vec<i64> = {
+            let mut statement = tx.prepare(
+                &format!("SELECT image_id FROM image_ids_array_table_{}", source_id))?;
 
-        for entry in iter
-        {
-        
-            let entry = match entry {
-                Ok(e) => e,
-                Err(e) => { 
-                    println!("Error in initial root scan: {:?}", e);
-                    continue;
-                }
-            };
+            let mut image_ids = vec![];
+            for result in statement.query_map(&[], |row| row.get(0))? {
+                image_ids.push(result?);
+            }
+            image_ids
+        };
 
-            let data = match entry.metadata() {
-                Ok(e) => e,
-                Err(e) => {
-                    println!("Error in initial root scan: {:?}", e);
-                    continue;
-                }
-            };
+        for image_id in image_ids {
+            TifariDb::remove_tag_tx(&tx, image_id, source_id)?;
+            changes.push(journal::Change::remove(image_id, &source_name));
 
-            if data.is_file() {
-                let path = entry.path().file_name().unwrap().to_string_lossy().to_string();
-//                println!("Found initial file: {:?}", path);
-                root_imgs.insert(path);
+            if !TifariDb::image_has_tag(&tx, image_id, target_id)? {
+                TifariDb::add_tag_tx(&tx, image_id, &target_name)?;
+                changes.push(journal::Change::add(image_id, &target_name));
             }
         }
 
-        let db_imgs = match self.get_all_image_paths() {
-            Ok(imgs) => imgs,
-            Err(e) => {
-                println!("Failed to query paths in image table from database: {:?}", e);
-                return;
+        let region_ids: Vec<i64> = {
+            let mut statement = tx.prepare("SELECT region_id FROM region_tags WHERE tag_id=?")?;
+
+            let mut region_ids = vec![];
+            for result in statement.query_map(&[&source_id], |row| row.get(0))? {
+                region_ids.push(result?);
             }
+            region_ids
         };
 
-        scan.set_scan_total(db_imgs.difference(&root_imgs).count() + root_imgs.difference(&db_imgs).count());
+        for region_id in region_ids {
+            TifariDb::remove_region_tag_tx(&tx, region_id, source_id)?;
+            changes.push(journal::Change::region_remove(region_id, &source_name));
 
-        let mut scan_current = 1;
+            if TifariDb::add_region_tag_tx(&tx, region_id, &target_name)?.is_some() {
+                changes.push(journal::Change::region_add(region_id, &target_name));
+            }
+        }
 
-        for path_to_rm in db_imgs.difference(&root_imgs) {
+        TifariDb::record_journal(&tx, "merge_tags", &changes)?;
 
-            scan.set_scan_current(scan_current);
-            scan_current += 1;
+        tx.commit()?;
+        Ok(())
+    }
 
-            
-            match self.erase_image(&path_to_rm) {
-                Ok(()) => {}, //println!("Erasing image: {}", path_to_rm),
-                Err(e) => println!("failed to erase image {}. Error: {:?}", path_to_rm, e),
-            } 
+    // the ids of the tags with the given names. names no tag has are left out.
+    fn find_tag_ids(&self, tags: &Vec<&str>) -> Result<Vec<i64>>
+    {
+        if tags.len() <= 0
+        {
+            return Ok(vec![]);
         }
 
-        for path_to_add in root_imgs.difference(&db_imgs) {
-
-            scan.set_scan_current(scan_current);
-            scan_current += 1;
+        let mut query = "SELECT id FROM tags WHERE name IN (".to_string();
+        let mut params: Vec<&rusqlite::types::ToSql> = Vec::with_capacity(tags.len());
+
+        query.push_str(&"?, ".repeat(tags.len() - 1));
+        query.push_str("?)");
+
+        for i in 0..tags.len()
+        {
+            params.push(&tags[i]);
+        }
+
+        let mut statement = self.connection.prepare(&query)?;
+
+        let mut tag_ids = vec![];
+        for result in statement.query_map(params.as_slice(), |row| row.get(0))?
+        {
+            tag_ids.push(result?);
+        }
+
+        Ok(tag_ids)
+    }
+
+    fn make_tag_id_list(&self, tags: &Vec<&str>) -> Result<String>
+    {
+        let tag_ids: Vec<String> = self.find_tag_ids(tags)?.iter().map(|id| id.to_string()).collect();
+        Ok(format!("({})", tag_ids.join(", ")))
+    }
+
+    // parses the search tokens and expands any saved searches they reference.
+    pub fn parse_query(&self, tags: &Vec<&str>) -> Result<query::SearchQuery>
+    {
+        let mut query = query::SearchQuery::parse(tags)?;
+        self.resolve_saved_searches(&mut query, &mut HashSet::new())?;
+
+        Ok(query)
+    }
+
+    // the conditions on the images table for the query's metadata filters and missing images,
+    // along with the parameters they take.
+    pub(crate) fn make_filter_conditions(query: &query::SearchQuery) -> (Vec<String>, Vec<&rusqlite::types::ToSql>)
+    {
+        let mut conditions = vec![];
+        let mut params: Vec<&rusqlite::types::ToSql> = vec![];
+
+        for filter in &query.metadata {
+            conditions.push(TifariDb::make_metadata_condition(filter));
+            params.push(&filter.pattern);
+        }
+
+        if let Some(condition) = query.missing.unwrap_or_default().to_sql() {
+            conditions.push(condition.to_string());
+        }
+
+        (conditions, params)
+    }
+
+    // returns the ids of the images matching the query, in the query's sort order.
+    fn find_matching_images(&self, query: &query::SearchQuery) -> Result<Vec<i64>>
+    {
+        let tags_contains: Vec<&str> = query.tags_contains.iter().map(|e| &e[..]).collect();
+        let tags_remove: Vec<&str> = query.tags_remove.iter().map(|e| &e[..]).collect();
+
+        let tag_ids = self.find_tag_ids(&tags_contains)?;
+        let not_in_tag_ids = self.make_tag_id_list(&tags_remove)?;
+
+        // no image has a tag that doesn't exist.
+        if tag_ids.len() < tags_contains.len() {
+            return Ok(vec![]);
+        }
+
+        let tag_ids_query = format!("({})", tag_ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(", "));
+
+        let (conditions, params) = TifariDb::make_filter_conditions(query);
+
+        let where_clause =
+            if conditions.is_empty() { String::new() }
+            else { format!("WHERE {}", conditions.join(" AND ")) };
+
+        let mut statement = self.connection.prepare(
+            &format!("SELECT id 
+                     FROM images 
+                     {}
+                     {}", where_clause, query.order.unwrap_or_default().to_sql()))?;
+
+        let mut image_ids: Vec<i64> = vec![];
+        for result in statement.query_map(params.as_slice(), |row| row.get(0))?
+        {
+            image_ids.push(result?);
+        }
+
+        let mut results = vec![];
+        for image_id in image_ids
+        {
+            // tags given to a region of the image count as tags of the image itself.
+            let image_tags = format!(
+                "SELECT tag_id FROM tags_array_table_{}
+                UNION
+                SELECT rt.tag_id FROM region_tags rt
+                JOIN regions r ON r.id = rt.region_id
+                WHERE r.image_id = {}", image_id, image_id);
+
+            let count: i64 = self.connection.query_row(
+                &format!("SELECT COUNT(*) 
+                         FROM ({})
+                         WHERE tag_id IN {} 
+                         AND NOT EXISTS(SELECT 1 FROM ({}) WHERE tag_id IN {})",
+                         image_tags, tag_ids_query, image_tags, not_in_tag_ids),
+                         &[],
+                         |row| row.get(0))?;
+
+            let count = count as usize;
+
+            if count == tag_ids.len()
+            {
+                results.push(image_id);
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn load_search_results(&self, image_ids: &[i64], query: &query::SearchQuery) -> Result<Vec<models::Image>>
+    {
+        let tags_contains: Vec<&str> = query.tags_contains.iter().map(|e| &e[..]).collect();
+        let tag_ids_query = self.make_tag_id_list(&tags_contains)?;
+
+        let mut results = Vec::with_capacity(image_ids.len());
+        for image_id in image_ids
+        {
+            let mut image = self.get_image_from_db(*image_id)?;
+            image.set_matched_regions(self.get_matching_region_ids(*image_id, &tag_ids_query)?);
+            results.push(image);
+        }
+
+        Ok(results)
+    }
+
+    pub fn search(&self, tags: &Vec<&str>) -> Result<Vec<models::Image>>
+    {
+        if 0 >= tags.len()
+        {
+            return Ok(vec![]);
+        }
+
+        let query = self.parse_query(tags)?;
+        let image_ids = self.find_matching_images(&query)?;
+
+        self.load_search_results(&image_ids, &query)
+    }
+
+    pub fn search_page(&self, tags: &Vec<&str>, offset: usize, limit: usize) -> Result<models::SearchPage>
+    {
+        if 0 >= tags.len()
+        {
+            return Ok(models::SearchPage::new(0, offset, vec![]));
+        }
+
+        let query = self.parse_query(tags)?;
+        let image_ids = self.find_matching_images(&query)?;
+
+        let start = std::cmp::min(offset, image_ids.len());
+        let end = std::cmp::min(start.saturating_add(limit), image_ids.len());
+
+        let images = self.load_search_results(&image_ids[start..end], &query)?;
+        Ok(models::SearchPage::new(image_ids.len(), offset, images))
+    }
+
+    pub fn get_all_tags(&self) -> Result<Vec<models::TagWithUsage>> {
+        let mut statement = self.connection.prepare("SELECT id, name FROM tags")?;
+        let mut retvals = vec![];
+
+        for result in statement.query_map(&[], |row| (row.get(0), row.get(1)))? {
+            let result = result?;
+            let (id, name) = (result.0, result.1);
+
+            let num_times_used = self.connection.query_row(
+                &format!("SELECT count(*) FROM image_ids_array_table_{}", id), 
+                &[],
+                |row| row.get(0))?;
+
+            retvals.push(models::TagWithUsage::new(id, name, num_times_used));
+        }
+
+        Ok(retvals)
+    }
+
+    fn is_sidecar_file(path: &std::path::Path) -> bool {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.eq_ignore_ascii_case("txt") || ext.eq_ignore_ascii_case("xmp") || ext.eq_ignore_ascii_case("json"),
+            None => false,
+        }
+    }
+
+    // file names in the root directory, or None when it can't be read.
+    fn list_root_files(root: &str) -> Option<HashSet<String>> {
+        let iter = match std::fs::read_dir(root) {
+            Ok(v) => v,
+            Err(_) => return None,
+        };
+
+        let mut root_imgs = HashSet::new();
+
+        for entry in iter
+        {
+        
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => { 
+                    println!("Error in initial root scan: {:?}", e);
+                    continue;
+                }
+            };
+
+            let data = match entry.metadata() {
+                Ok(e) => e,
+                Err(e) => {
+                    println!("Error in initial root scan: {:?}", e);
+                    continue;
+                }
+            };
+
+            // caption, xmp and booru metadata sidecars live next to the images, but aren't images themselves.
+            if data.is_file() && !TifariDb::is_sidecar_file(&entry.path()) {
+                let path = entry.path().file_name().unwrap().to_string_lossy().to_string();
+//                println!("Found initial file: {:?}", path);
+                root_imgs.insert(path);
+            }
+        }
+
+        Some(root_imgs)
+    }
 
-            match self.try_insert_image(&path_to_add) {
-                Ok(id) => {},//println!("Adding new image: {} {}", path_to_add, id),
-                Err(e) => println!("Failed to insert new image {} to image db. Error: {:?}", path_to_add, e),
+    fn on_new_image(&mut self, cfg: &TifariConfig, id: i64, path: &str) {
+        if cfg.get_import_embedded_keywords() {
+            if let Err(e) = self.import_embedded_keywords(cfg, id) {
+                println!("Failed to import keywords of new image {}. Error: {:?}", path, e);
+            }
+        }
+
+        if cfg.get_import_captions() {
+            let caption = match self.get_image_file_path(cfg, id) {
+                Ok(file) => caption_path(&file),
+                Err(_) => return,
+            };
+
+            if caption.is_file() {
+                if let Err(e) = self.import_caption(cfg, id) {
+                    println!("Failed to import caption of new image {}. Error: {:?}", path, e);
+                }
+            }
+        }
+    }
+
+    // scans every configured root. a root that can't be read (an unmounted drive, say) is skipped
+    // without touching it's images, and so are roots that aren't configured anymore.
+    // images whose files are gone are only marked missing, and get their files back if they reappear.
+    // they're erased once they've been missing for longer than the retention period.
+    // a scan that'd mark too much of the library missing at once is refused, unless forced.
+    pub fn reload_root_unsafe(&mut self, cfg: &TifariConfig, scan: &Arc<ScanData>, force: bool) -> Result<()> {
+        let mut work = vec![];
+
+        for root in cfg.get_roots() {
+            println!("Starting root scan at \"{}\"", root.get_path());
+
+            let root_id = match self.get_or_create_root_id(root.get_name()) {
+                Ok(v) => v,
+                Err(e) => {
+                    println!("Failed to add root {} to the database: {:?}", root.get_name(), e);
+                    continue;
+                }
             };
+
+            let root_imgs = match TifariDb::list_root_files(root.get_path()) {
+                Some(v) => v,
+                None => {
+                    println!("Failed to scan root {}. It's directory is most likely invalid.", root.get_name());
+                    continue;
+                }
+            };
+
+            let db_imgs = match self.get_image_paths_of_root(root_id) {
+                Ok(imgs) => imgs,
+                Err(e) => {
+                    println!("Failed to query paths in image table from database: {:?}", e);
+                    continue;
+                }
+            };
+
+            work.push((root_id, root_imgs, db_imgs));
+        }
+
+        let missing_ids = self.get_missing_image_ids()?;
+
+        let vanishing: usize = work.iter()
+            .map(|&(_, ref root_imgs, ref db_imgs)| {
+                db_imgs.iter().filter(|&(p, id)| !root_imgs.contains(p) && !missing_ids.contains(id)).count()
+            })
+            .sum();
+
+        let present = self.get_num_present_images()?;
+        if !force && missing::is_too_many_missing(vanishing, present, cfg.get_max_missing_fraction()) {
+            println!("Refusing to mark {} of {} images as missing. Reload with force to do it anyway.", vanishing, present);
+            return Err(BackendError::TooManyMissingImages(vanishing, present));
+        }
+
+        scan.set_scan_total(vanishing + work.iter()
+                            .map(|&(_, ref root_imgs, ref db_imgs)| {
+                                db_imgs.iter().filter(|&(p, id)| root_imgs.contains(p) && missing_ids.contains(id)).count()
+                                    + root_imgs.iter().filter(|p| !db_imgs.contains_key(*p)).count()
+                            })
+                            .sum::<usize>());
+
+        let mut scan_current = 1;
+
+        for (root_id, root_imgs, db_imgs) in work {
+            for (path_to_rm, id) in db_imgs.iter().filter(|&(p, id)| !root_imgs.contains(p) && !missing_ids.contains(id)) {
+
+                scan.set_scan_current(scan_current);
+                scan_current += 1;
+
+                match self.mark_image_missing(*id) {
+                    Ok(()) => {},
+                    Err(e) => println!("failed to mark image {} as missing. Error: {:?}", path_to_rm, e),
+                } 
+            }
+
+            for (path_to_restore, id) in db_imgs.iter().filter(|&(p, id)| root_imgs.contains(p) && missing_ids.contains(id)) {
+
+                scan.set_scan_current(scan_current);
+                scan_current += 1;
+
+                if let Err(e) = self.restore_missing_image(cfg, *id) {
+                    println!("Failed to restore missing image {}. Error: {:?}", path_to_restore, e);
+                    continue;
+                }
+
+                // the file that came back isn't necessarily the one that went missing.
+                if let Err(e) = self.forget_image_hash(*id) {
+                    println!("Failed to reset the hash of restored image {}. Error: {:?}", path_to_restore, e);
+                }
+            }
+
+            for path_to_add in root_imgs.iter().filter(|p| !db_imgs.contains_key(*p)) {
+
+                scan.set_scan_current(scan_current);
+                scan_current += 1;
+
+                match self.try_insert_image_in_root(root_id, &path_to_add) {
+                    Ok(id) => self.on_new_image(cfg, id, &path_to_add),
+                    Err(e) => println!("Failed to insert new image {} to image db. Error: {:?}", path_to_add, e),
+                };
+            }
+        }
+
+        // the rest of the scan is already in, but a purge that fails keeps failing, so the scan does too.
+        let num_purged = self.purge_missing_images(cfg)?;
+        if num_purged > 0 {
+            println!("Erased {} images that have been missing for over {} days.", num_purged, cfg.get_missing_retention_days());
         }
         
         println!("Done.");
-
+        Ok(())
     }
 
-    pub fn reload_root(&mut self, root: &str, scan: Arc<ScanData>) {
+    pub fn reload_root(&mut self, cfg: &TifariConfig, scan: Arc<ScanData>) {
         if scan.is_scanning.load(std::sync::atomic::Ordering::Acquire) {
             return;
         }
 
         scan.set_is_scanning(true);
 
-        self.reload_root_unsafe(root, &scan);
+        let force = scan.take_force_next();
+        match self.reload_root_unsafe(cfg, &scan, force) {
+            Ok(()) => scan.set_refused_missing(0),
+            Err(BackendError::TooManyMissingImages(num, _)) => scan.set_refused_missing(num),
+            Err(e) => println!("Failed to scan the roots. Error: {:?}", e),
+        }
 
         scan.set_is_scanning(false);
         scan.set_scan_total(0);
@@ -642,7 +1379,8 @@ mod tests {
     #[test]
     fn db_image_insertion()
     {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
         db.try_insert_image(&"test/img.png").unwrap();
         assert!(db.try_insert_image(&"test/img.png") .is_err());
     }
@@ -650,7 +1388,8 @@ mod tests {
     #[test]
     fn db_image_erase()
     {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
         let img = "test/img.png";
 
         assert!(db.erase_image(&img).is_err());
@@ -662,14 +1401,25 @@ mod tests {
     #[test]
     fn db_image_tag()
     {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
         let img = "test/img.png";
 
         let tag1 = "tag_1";
         let tag2 = "tag_2";
 
-        assert!(db.give_tag(1, &tag1).is_err());
-        assert!(db.remove_tag(1, 1).is_err());
+        match db.give_tag(1, &tag1) {
+            Err(BackendError::ImageNotFound) => {},
+            _ => panic!("expected ImageNotFound"),
+        }
+        match db.remove_tag(1, 1) {
+            Err(BackendError::ImageNotFound) => {},
+            _ => panic!("expected ImageNotFound"),
+        }
+        match db.get_image_from_db(1) {
+            Err(BackendError::ImageNotFound) => {},
+            _ => panic!("expected ImageNotFound"),
+        }
         assert!(db.give_tag(1, &tag2).is_err());
         assert!(db.remove_tag(1, 2).is_err());
 
@@ -677,7 +1427,10 @@ mod tests {
         let tag1_id = db.give_tag(img_id, &tag1).unwrap();
 
         assert!(db.give_tag(img_id, &tag1).is_err());
-        assert!(db.remove_tag(img_id, 2).is_err());
+        match db.remove_tag(img_id, 2) {
+            Err(BackendError::TagNotFound(2)) => {},
+            _ => panic!("expected TagNotFound"),
+        }
 
         db.remove_tag(img_id, tag1_id).unwrap();
 
@@ -690,7 +1443,8 @@ mod tests {
 
     #[test]
     fn db_consistent_tag_ids() {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
 
         let img1 = "img1";
         let img2 = "img2";
@@ -729,7 +1483,8 @@ mod tests {
     #[test]
     fn db_duplicate_tags()
     {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
         let img = "test/img.png";
 
         let tag1 = "tag_1";
@@ -744,7 +1499,8 @@ mod tests {
 
     #[test]
     fn db_tag_queue_element_counter() {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
 
         let img1 = "test/img1.png";
         let img2 = "test/img2.png";
@@ -780,7 +1536,8 @@ mod tests {
 
     #[test]
     fn db_tag_queue_gets_filled_when_tags_are_removed_from_images() {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
 
         let img1 = "test/img1.png";
         let img2 = "test/img2.png";
@@ -853,7 +1610,8 @@ mod tests {
     #[test]
     fn db_tag_queue() 
     {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
         let img1 = "test/img1.png";
         let img2 = "test/img2.png";
 
@@ -906,7 +1664,8 @@ mod tests {
 
         let no_tag3 = "-tag3";
 
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
 
         let img1_id = db.try_insert_image(&img1).unwrap();
         let img2_id = db.try_insert_image(&img2).unwrap();
@@ -934,7 +1693,8 @@ mod tests {
 
     #[test]
     fn db_disallow_some_tags() {
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
         let img = db.try_insert_image("image").unwrap();
 
         assert!(db.give_tag(img, &"").is_err());
@@ -956,7 +1716,8 @@ mod tests {
         let tag2 = "tag2";
         let tag3 = "tag3";
 
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
 
         let img1_id = db.try_insert_image(&img1).unwrap();
         let tag1_id = db.give_tag(img1_id, &tag1).unwrap();
@@ -985,7 +1746,8 @@ mod tests {
             assert_eq!(results[0].get_id(), img1_id);
         }
 
-        let mut db = TifariDb::new_in_memory().unwrap().setup_tables();
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
 
         let img1_id = db.try_insert_image(&img1).unwrap();
         let tag1_id = db.give_tag(img1_id, &tag1).unwrap();
@@ -1035,4 +1797,1257 @@ mod tests {
             assert_eq!(results[1].get_id(), img1_id);
         }
     }
+
+    #[test]
+    fn db_search_matches_region_tags() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("test/img1.png").unwrap();
+        let img2_id = db.try_insert_image("test/img2.png").unwrap();
+
+        db.give_tag(img1_id, "figure").unwrap();
+        db.give_tag(img2_id, "figure").unwrap();
+
+        let region_id = db.add_region(img1_id, 0.25, 0.25, 0.5, 0.5, "left hand", &vec!["hand".to_string()]).unwrap();
+
+        {
+            let results = db.search(&vec!["figure", "hand"]).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].get_id(), img1_id);
+            assert_eq!(results[0].get_matched_regions(), &vec![region_id]);
+        }
+
+        {
+            let results = db.search(&vec!["figure", "-hand"]).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].get_id(), img2_id);
+        }
+
+        let image = db.get_image_from_db(img1_id).unwrap();
+        assert_eq!(image.get_regions().len(), 1);
+        assert_eq!(image.get_regions()[0].get_note(), "left hand");
+    }
+
+    #[test]
+    fn db_region_tags_lifecycle() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img_id = db.try_insert_image("test/img.png").unwrap();
+
+        assert!(db.add_region(img_id, 0.5, 0.5, 0.75, 0.25, "", &vec![]).is_err());
+        assert!(db.add_region(img_id + 1, 0.0, 0.0, 0.5, 0.5, "", &vec![]).is_err());
+
+        let region_id = db.add_region(img_id, 0.0, 0.0, 0.5, 0.5, "", &vec![]).unwrap();
+        let tag_id = db.give_region_tag(region_id, "hand").unwrap();
+
+        // a region tag doesn't take the image out of the tag queue
+        assert_eq!(db.get_num_elements_in_tag_queue().unwrap(), 1);
+        assert_eq!(db.get_all_tags().unwrap().len(), 1);
+
+        db.remove_region_tag(region_id, tag_id).unwrap();
+        assert_eq!(db.get_all_tags().unwrap().len(), 0);
+
+        db.give_region_tag(region_id, "hand").unwrap();
+
+        // the tags given together are journaled as one operation
+        let given = db.give_region_tags(region_id, &vec!["hand".to_string(), "arm".to_string(),
+                                                          "foot".to_string(), "-arm".to_string()]).unwrap();
+        assert_eq!(given.len(), 2);
+        assert_eq!(db.get_all_tags().unwrap().len(), 3);
+        assert_eq!(db.undo().unwrap().get_operation(), "add_region_tags");
+        assert_eq!(db.get_all_tags().unwrap().len(), 1);
+        assert!(db.give_region_tags(region_id + 1, &vec!["arm".to_string()]).is_err());
+
+        db.erase_image("test/img.png").unwrap();
+
+        assert_eq!(db.get_all_tags().unwrap().len(), 0);
+        assert!(db.get_region(region_id).is_err());
+    }
+
+    #[test]
+    fn db_search_by_metadata() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("test/img1.png").unwrap();
+        let img2_id = db.try_insert_image("test/img2.png").unwrap();
+
+        db.give_tag(img1_id, "hand").unwrap();
+        db.give_tag(img2_id, "hand").unwrap();
+
+        db.update_image_metadata(img1_id,
+                                 Some(&vec!["https://www.artstation.com/artwork/abc".to_string()]),
+                                 Some("Some Artist"),
+                                 None).unwrap();
+
+        {
+            let results = db.search(&vec!["source:*artstation*"]).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].get_id(), img1_id);
+            assert_eq!(results[0].get_metadata().get_artist(), "Some Artist");
+        }
+
+        {
+            let results = db.search(&vec!["hand", "-artist:some*"]).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].get_id(), img2_id);
+        }
+
+        db.update_image_metadata(img1_id, None, None, Some("CC-BY")).unwrap();
+        let metadata = db.get_image_metadata(img1_id).unwrap();
+        assert_eq!(metadata.get_artist(), "Some Artist");
+        assert_eq!(metadata.get_license(), "CC-BY");
+        assert_eq!(metadata.get_sources().len(), 1);
+    }
+
+    #[test]
+    fn db_saved_searches() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("test/img1.png").unwrap();
+        let img2_id = db.try_insert_image("test/img2.png").unwrap();
+        let img3_id = db.try_insert_image("test/img3.png").unwrap();
+
+        db.give_tag(img1_id, "figure").unwrap();
+        db.give_tag(img2_id, "figure").unwrap();
+        db.give_tag(img3_id, "figure").unwrap();
+        db.give_tag(img3_id, "nsfw").unwrap();
+
+        assert!(db.save_search("weekly practice", "figure", "newest").is_err());
+        assert!(db.save_search("weekly_practice", "figure", "sideways").is_err());
+
+        db.save_search("sfw", "-nsfw", "newest").unwrap();
+        db.save_search("weekly_practice", "figure saved:sfw", "oldest").unwrap();
+
+        {
+            let page = db.run_saved_search("weekly_practice", 0, 1).unwrap();
+            assert_eq!(page.get_total(), 2);
+            assert_eq!(page.get_images().len(), 1);
+            assert_eq!(page.get_images()[0].get_id(), img1_id);
+        }
+
+        {
+            let page = db.run_saved_search("weekly_practice", 1, 10).unwrap();
+            assert_eq!(page.get_images().len(), 1);
+            assert_eq!(page.get_images()[0].get_id(), img2_id);
+        }
+
+        {
+            let results = db.search(&vec!["saved:weekly_practice", "order:newest"]).unwrap();
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].get_id(), img2_id);
+        }
+
+        // saved searches can't reference themselves, directly or through another one.
+        assert!(db.save_search("loop", "saved:loop", "newest").is_err());
+        db.save_search("loop", "saved:other", "newest").unwrap();
+        assert!(db.save_search("other", "figure saved:loop", "newest").is_err());
+        db.remove_saved_search("loop").unwrap();
+
+        // a tag of the saved search given again in the query doesn't change the results.
+        db.save_search("nsfw_figures", "figure nsfw", "newest").unwrap();
+        {
+            let results = db.search(&vec!["saved:nsfw_figures", "figure"]).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].get_id(), img3_id);
+
+            let results = db.search(&vec!["saved:sfw", "-nsfw", "figure"]).unwrap();
+            assert_eq!(results.len(), 2);
+        }
+        db.remove_saved_search("nsfw_figures").unwrap();
+
+        db.remove_saved_search("sfw").unwrap();
+        assert!(db.run_saved_search("weekly_practice", 0, 10).is_err());
+        assert_eq!(db.get_all_saved_searches().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn db_undo_redo_bulk_operations() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("test/img1.png").unwrap();
+        let img2_id = db.try_insert_image("test/img2.png").unwrap();
+
+        let tags = vec!["hand".to_string(), "gesture".to_string()];
+        let given = db.give_tags(&vec![img1_id, img2_id], &tags).unwrap();
+        assert_eq!(given.len(), 4);
+
+        let tag_ids: Vec<i64> = db.get_all_tags().unwrap().iter().map(|t| t.get_id()).collect();
+        db.remove_tags(&vec![img1_id, img2_id], &tag_ids).unwrap();
+
+        assert_eq!(db.get_all_tags().unwrap().len(), 0);
+        assert_eq!(db.get_num_elements_in_tag_queue().unwrap(), 2);
+
+        {
+            let entry = db.undo().unwrap();
+            assert_eq!(entry.get_operation(), "remove_tags");
+            assert_eq!(entry.get_image_ids(), &vec![img1_id, img2_id]);
+        }
+
+        assert_eq!(db.search(&vec!["hand", "gesture"]).unwrap().len(), 2);
+        assert_eq!(db.get_num_elements_in_tag_queue().unwrap(), 0);
+
+        db.undo().unwrap();
+        assert_eq!(db.get_all_tags().unwrap().len(), 0);
+        assert!(db.undo().is_err());
+
+        db.redo().unwrap();
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 2);
+
+        // a new operation drops whatever is left to redo
+        db.give_tag(img1_id, "figure").unwrap();
+        assert!(db.redo().is_err());
+
+        let history = db.get_history(10).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].get_operation(), "add_tag");
+        assert!(!history[1].is_undone());
+
+        // tags imported while scanning aren't journaled and leave the redo stack alone
+        db.undo().unwrap();
+        assert_eq!(db.import_tags(img2_id, &vec!["figure".to_string(), "hand".to_string()]).unwrap(), 1);
+        assert_eq!(db.get_history(10).unwrap().len(), 2);
+        db.redo().unwrap();
+        assert_eq!(db.search(&vec!["figure"]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn db_rename_and_merge_tags() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("test/img1.png").unwrap();
+        let img2_id = db.try_insert_image("test/img2.png").unwrap();
+
+        let hand_id = db.give_tag(img1_id, "hand").unwrap();
+        let hands_id = db.give_tag(img2_id, "hands").unwrap();
+        db.give_tag(img1_id, "hands").unwrap();
+
+        assert!(db.rename_tag(hand_id, "hands").is_err());
+        db.rename_tag(hand_id, "palm").unwrap();
+        assert_eq!(db.search(&vec!["palm"]).unwrap().len(), 1);
+
+        db.undo().unwrap();
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+
+        db.merge_tags(hand_id, hands_id).unwrap();
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 0);
+        assert_eq!(db.search(&vec!["hands"]).unwrap().len(), 2);
+        assert_eq!(db.get_all_tags().unwrap().len(), 1);
+
+        db.undo().unwrap();
+        {
+            let results = db.search(&vec!["hand"]).unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].get_id(), img1_id);
+        }
+        assert_eq!(db.search(&vec!["hands"]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn db_export_import_roundtrip() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("img1.png").unwrap();
+        db.try_insert_image("img2.png").unwrap();
+
+        db.give_tag(img1_id, "hand").unwrap();
+        db.update_image_metadata(img1_id, Some(&vec!["https://example.com/1".to_string()]), Some("Artist"), None).unwrap();
+        db.add_region(img1_id, 0.0, 0.0, 0.5, 0.5, "thumb", &vec!["thumb".to_string()]).unwrap();
+
+        let doc = db.export_library().unwrap();
+        assert_eq!(doc.get_version(), EXPORT_VERSION);
+        assert_eq!(doc.get_images().len(), 2);
+        assert_eq!(doc.get_images()[0].get_tags(), &vec!["hand".to_string()]);
+
+        let mut other = TifariDb::new_in_memory().unwrap();
+        other.setup_tables().unwrap();
+
+        let other_img1_id = other.try_insert_image("img1.png").unwrap();
+        other.try_insert_image("img3.png").unwrap();
+
+        let report = other.import_library(&TifariConfig::default(), &doc).unwrap();
+        assert_eq!(report.get_matched_by_path(), 1);
+        assert_eq!(report.get_unmatched(), &vec!["img2.png".to_string()]);
+        assert_eq!(report.get_tags_added(), 1);
+        assert_eq!(report.get_regions_added(), 1);
+
+        let image = other.get_image_from_db(other_img1_id).unwrap();
+        assert_eq!(image.get_tags().len(), 1);
+        assert_eq!(image.get_metadata().get_artist(), "Artist");
+        assert_eq!(image.get_regions()[0].get_note(), "thumb");
+
+        // importing again doesn't duplicate anything
+        let report = other.import_library(&TifariConfig::default(), &doc).unwrap();
+        assert_eq!(report.get_tags_added(), 0);
+        assert_eq!(report.get_regions_added(), 0);
+
+        assert_eq!(other.search(&vec!["thumb"]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn db_caption_sync() {
+        let root = std::env::temp_dir().join("tifari_caption_sync_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), root.to_str().unwrap().to_string());
+        cfg.update(patch);
+
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("img1.png").unwrap();
+        let caption = root.join("img1.txt");
+        std::fs::write(&caption, "hand, red hair").unwrap();
+
+        let mut options = CaptionOptions::default();
+
+        // the image has no tags, so the caption is imported
+        let report = db.sync_captions(&cfg, &options).unwrap();
+        assert_eq!(report.get_imported(), 1);
+        assert_eq!(db.get_image_from_db(img1_id).unwrap().get_tags().len(), 2);
+
+        // nothing changed
+        let report = db.sync_captions(&cfg, &options).unwrap();
+        assert_eq!((report.get_imported(), report.get_exported()), (0, 0));
+
+        db.give_tag(img1_id, "gesture").unwrap();
+        let report = db.sync_captions(&cfg, &options).unwrap();
+        assert_eq!(report.get_exported(), 1);
+        assert_eq!(std::fs::read_to_string(&caption).unwrap(), "gesture, hand, red_hair");
+
+        // both sides changed
+        std::fs::write(&caption, "hand, pose").unwrap();
+        db.give_tag(img1_id, "sitting").unwrap();
+
+        let report = db.sync_captions(&cfg, &options).unwrap();
+        assert_eq!(report.get_conflicts(), &vec!["img1.png".to_string()]);
+
+        options.conflict_policy = CaptionConflictPolicy::Merge;
+        db.sync_captions(&cfg, &options).unwrap();
+        assert_eq!(std::fs::read_to_string(&caption).unwrap(), "gesture, hand, pose, red_hair, sitting");
+
+        // the whole sync can be undone
+        db.undo().unwrap();
+        assert_eq!(db.get_image_from_db(img1_id).unwrap().get_tags().len(), 4);
+
+        // img2.png and img2.jpg would share img2.txt, so neither of them touches it
+        std::fs::write(root.join("img2.png"), "png").unwrap();
+        std::fs::write(root.join("img2.jpg"), "jpg").unwrap();
+        std::fs::write(root.join("img2.txt"), "foot").unwrap();
+        let img2_id = db.try_insert_image("img2.png").unwrap();
+        let img3_id = db.try_insert_image("img2.jpg").unwrap();
+        db.give_tag(img3_id, "hand").unwrap();
+
+        assert!(caption_is_shared(&root.join("img2.png")));
+        assert_eq!(db.import_caption(&cfg, img2_id).unwrap(), 0);
+
+        let report = db.sync_captions(&cfg, &options).unwrap();
+        assert_eq!(report.get_conflicts(), &vec!["img2.png".to_string(), "img2.jpg".to_string()]);
+        assert_eq!(std::fs::read_to_string(root.join("img2.txt")).unwrap(), "foot");
+        assert_eq!(db.get_image_from_db(img2_id).unwrap().get_tags().len(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn db_booru_import() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("0123abcd.jpg").unwrap();
+
+        let posts: Vec<models::BooruPost> = serde_json::from_str(r#"[
+            { "md5": "0123abcd", "file_ext": "jpg", "rating": "s", "source": "https://example.com/1",
+              "tag_string": "hand someone", "tag_string_artist": "someone" },
+            { "md5": "ffff", "file_ext": "png", "tag_string": "foot" }
+        ]"#).unwrap();
+
+        let report = db.import_booru_posts(&TifariConfig::default(), &posts, true).unwrap();
+        assert_eq!(report.get_images().len(), 1);
+        assert_eq!(report.get_images()[0].get_matched_by(), "filename");
+        assert_eq!(report.get_images()[0].get_tags_added().len(), 3);
+        assert_eq!(report.get_unmatched(), &vec!["ffff".to_string()]);
+
+        // a dry run doesn't change anything
+        assert_eq!(db.get_image_from_db(img1_id).unwrap().get_tags().len(), 0);
+        assert!(db.get_history(10).unwrap().is_empty());
+
+        let report = db.import_booru_posts(&TifariConfig::default(), &posts, false).unwrap();
+        assert_eq!(report.get_images()[0].get_sources_added(), &vec!["https://example.com/1".to_string()]);
+
+        let image = db.get_image_from_db(img1_id).unwrap();
+        assert_eq!(image.get_tags().len(), 3);
+        assert_eq!(image.get_metadata().get_artist(), "someone");
+
+        // every imported tag can be searched for
+        for tag in image.get_tags() {
+            let results = db.search(&vec![&tag.get_name()[..]]).unwrap();
+            assert_eq!(results.len(), 1, "searching for {}", tag.get_name());
+            assert_eq!(results[0].get_id(), img1_id);
+        }
+        assert_eq!(db.search(&vec!["artist_someone", "rating:safe"]).unwrap().len(), 1);
+
+        let report = db.import_booru_posts(&TifariConfig::default(), &posts, false).unwrap();
+        assert_eq!(report.get_images()[0].get_tags_added().len(), 0);
+    }
+
+    #[test]
+    fn db_snapshot_and_restore() {
+        let dir = std::env::temp_dir().join("tifari_snapshot_test");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("backup_dir".to_string(), dir.to_str().unwrap().to_string());
+        patch.insert("backup_keep".to_string(), "2".to_string());
+        cfg.update(patch);
+
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+        db.try_insert_image("img1.png").unwrap();
+
+        let first = db.make_snapshot(&cfg).unwrap();
+        validate_snapshot(&dir.join(first.get_name())).unwrap();
+
+        db.try_insert_image("img2.png").unwrap();
+
+        // restoring takes a snapshot of the current state first
+        let previous = db.restore_snapshot(&cfg, first.get_name()).unwrap();
+        assert_eq!(db.get_image_paths_of_root(roots::DEFAULT_ROOT_ID).unwrap().len(), 1);
+        assert_eq!(list_snapshots(&cfg).unwrap().len(), 2);
+
+        db.restore_snapshot(&cfg, previous.get_name()).unwrap();
+        assert_eq!(db.get_image_paths_of_root(roots::DEFAULT_ROOT_ID).unwrap().len(), 2);
+
+        // only the newest two are kept
+        assert_eq!(list_snapshots(&cfg).unwrap().len(), 2);
+        assert!(db.restore_snapshot(&cfg, first.get_name()).is_err());
+        assert!(db.restore_snapshot(&cfg, "../image_and_tag.db").is_err());
+
+        let garbage = dir.join("tifari-20000101-000000-000000.db");
+        std::fs::write(&garbage, "not a database").unwrap();
+        assert!(validate_snapshot(&garbage).is_err());
+        assert!(db.restore_snapshot(&cfg, "tifari-20000101-000000-000000.db").is_err());
+        std::fs::remove_file(&garbage).unwrap();
+
+        // files that aren't named like a snapshot aren't listed or rotated out, and the snapshots
+        // are ordered by when they were taken
+        let other = dir.join("tifari-zzz.db");
+        std::fs::write(&other, "some other file").unwrap();
+        let newest = db.make_snapshot(&cfg).unwrap();
+
+        let snapshots = list_snapshots(&cfg).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].get_name(), newest.get_name());
+        assert!(other.is_file());
+        assert!(db.restore_snapshot(&cfg, "tifari-zzz.db").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn db_integrity_check_and_repair() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let img1_id = db.try_insert_image("img1.png").unwrap();
+        db.give_tag(img1_id, "hand").unwrap();
+        let hand_id: i64 = db.connection.query_row(
+            "SELECT id FROM tags WHERE name='hand'", &[], |row| row.get(0)).unwrap();
+
+        assert!(db.check_integrity(false).unwrap().is_consistent());
+
+        // drift the array tables apart by hand
+        db.connection.execute_batch(&format!("
+            DELETE FROM image_ids_array_table_{};
+            CREATE TABLE tags_array_table_999 (tag_id INTEGER NOT NULL UNIQUE);
+            INSERT INTO tag_queue (id, image_id) VALUES (null, 999);
+            INSERT INTO tags (id, name) VALUES (null, 'ghost');
+            ", hand_id)).unwrap();
+
+        let report = db.check_integrity(false).unwrap();
+        assert!(!report.is_consistent());
+        assert!(!report.get_repaired());
+        assert_eq!(report.get_orphan_tables(), &vec!["tags_array_table_999".to_string()]);
+        assert_eq!(report.get_missing_tables().len(), 1);
+        assert_eq!(report.get_one_sided_memberships(), 1);
+        assert_eq!(report.get_orphan_queue_rows(), 1);
+        assert_eq!(report.get_unused_tags(), &vec!["ghost".to_string()]);
+
+        // checking without repairing leaves everything as it was
+        assert_eq!(db.check_integrity(false).unwrap().get_orphan_queue_rows(), 1);
+
+        assert!(db.check_integrity(true).unwrap().get_repaired());
+        assert!(db.check_integrity(false).unwrap().is_consistent());
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn db_multiple_roots() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let nas_id = db.get_or_create_root_id("nas").unwrap();
+        assert!(nas_id != roots::DEFAULT_ROOT_ID);
+        assert_eq!(db.get_or_create_root_id("nas").unwrap(), nas_id);
+
+        // the same path in two roots is two different images
+        let local_img_id = db.try_insert_image("img.png").unwrap();
+        let nas_img_id = db.try_insert_image_in_root(nas_id, "img.png").unwrap();
+        assert!(local_img_id != nas_img_id);
+
+        db.give_tag(local_img_id, "hand").unwrap();
+        db.give_tag(nas_img_id, "hand").unwrap();
+        db.give_tag(nas_img_id, "sitting").unwrap();
+
+        assert_eq!(db.get_image_from_db(nas_img_id).unwrap().get_root(), "nas");
+        assert_eq!(db.get_image_location(nas_img_id).unwrap(), (nas_id, "img.png".to_string()));
+
+        // images of a root that isn't configured can't be found on disk
+        match db.get_image_file_path(&TifariConfig::default(), nas_img_id) {
+            Err(BackendError::RootNotFound(name)) => assert_eq!(name, "nas"),
+            _ => panic!("expected RootNotFound"),
+        }
+
+        // a removed root's images go missing instead of being erased right away
+        assert_eq!(db.mark_root_missing("nas").unwrap(), 1);
+        assert_eq!(db.mark_root_missing("nas").unwrap(), 0);
+        assert_eq!(db.mark_root_missing("usb").unwrap(), 0);
+
+        let results = db.search(&vec!["hand"]).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].get_root(), DEFAULT_ROOT_NAME);
+        assert_eq!(db.search(&vec!["sitting"]).unwrap().len(), 0);
+        assert_eq!(db.search(&vec!["sitting", "missing:only"]).unwrap().len(), 1);
+        assert!(db.get_image_from_db(nas_img_id).unwrap().get_missing_since().is_some());
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("missing_retention_days".to_string(), "0".to_string());
+        cfg.update(patch);
+
+        assert_eq!(db.purge_missing_images(&cfg).unwrap(), 1);
+        assert!(db.get_image_paths_of_root(nas_id).unwrap().is_empty());
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn db_missing_images() {
+        let root = std::env::temp_dir().join("tifari_missing_images_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), root.to_str().unwrap().to_string());
+        patch.insert("max_missing_fraction".to_string(), "0.5".to_string());
+        cfg.update(patch);
+
+        let scan = Arc::new(ScanData::default());
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        for name in &["img1.png", "img2.png", "img3.png"] {
+            std::fs::write(root.join(name), name).unwrap();
+        }
+
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+        let img1_id = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "img1.png").unwrap().unwrap();
+        db.give_tag(img1_id, "hand").unwrap();
+
+        // a missing image keeps it's tags, but is hidden from search
+        std::fs::remove_file(root.join("img1.png")).unwrap();
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+
+        assert!(db.get_image_from_db(img1_id).unwrap().get_missing_since().is_some());
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 0);
+        assert_eq!(db.search(&vec!["hand", "missing:only"]).unwrap().len(), 1);
+        assert_eq!(db.search(&vec!["hand", "missing:include"]).unwrap().len(), 1);
+
+        // and is back once it's file is
+        std::fs::write(root.join("img1.png"), "img1.png").unwrap();
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+
+        assert!(db.get_image_from_db(img1_id).unwrap().get_missing_since().is_none());
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+
+        // losing most of the library at once is refused unless forced
+        for name in &["img1.png", "img2.png"] {
+            std::fs::remove_file(root.join(name)).unwrap();
+        }
+
+        match db.reload_root_unsafe(&cfg, &scan, false) {
+            Err(BackendError::TooManyMissingImages(2, 3)) => {},
+            _ => panic!("expected the scan to be refused"),
+        }
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+
+        db.reload_root_unsafe(&cfg, &scan, true).unwrap();
+        assert_eq!(db.get_num_present_images().unwrap(), 1);
+
+        // with no retention, missing images are erased on the next scan
+        let mut patch = HashMap::new();
+        patch.insert("missing_retention_days".to_string(), "0".to_string());
+        cfg.update(patch);
+
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+        assert!(db.get_image_from_db(img1_id).is_err());
+        assert_eq!(db.search(&vec!["hand", "missing:include"]).unwrap().len(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn db_relink_missing_images() {
+        let root = std::env::temp_dir().join("tifari_relink_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), root.to_str().unwrap().to_string());
+        cfg.update(patch);
+
+        let scan = Arc::new(ScanData::default());
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        std::fs::write(root.join("a.png"), "aaa").unwrap();
+        std::fs::write(root.join("b.png"), "bbb").unwrap();
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+
+        // the scan leaves hashing to the scan thread
+        let a_id = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "a.png").unwrap().unwrap();
+        assert_eq!(db.get_image_hash(a_id).unwrap(), None);
+        db.ensure_image_hashes(&cfg).unwrap();
+        assert!(db.get_image_hash(a_id).unwrap().is_some());
+
+        db.give_tag(a_id, "hand").unwrap();
+
+        std::fs::rename(root.join("a.png"), root.join("c.png")).unwrap();
+        db.reload_root_unsafe(&cfg, &scan, true).unwrap();
+        let c_id = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "c.png").unwrap().unwrap();
+
+        let missing = db.get_missing_images(&cfg).unwrap();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].get_image().get_id(), a_id);
+        assert_eq!(missing[0].get_candidates().len(), 1);
+        assert_eq!(missing[0].get_candidates()[0].get_image().get_id(), c_id);
+        assert_eq!(missing[0].get_candidates()[0].get_matched_by(), "hash");
+
+        // tagged images aren't relinked over
+        let b_id = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "b.png").unwrap().unwrap();
+        db.give_tag(b_id, "sitting").unwrap();
+
+        match db.relink_image(a_id, b_id) {
+            Err(BackendError::BadRelink(_)) => {},
+            _ => panic!("expected BadRelink"),
+        }
+
+        let image = db.relink_image(a_id, c_id).unwrap();
+        assert_eq!(image.get_path(), "c.png");
+        assert!(image.get_missing_since().is_none());
+        assert_eq!(image.get_tags().len(), 1);
+
+        assert!(db.get_image_from_db(c_id).is_err());
+        assert!(db.get_missing_images(&cfg).unwrap().is_empty());
+        assert!(db.get_tag_queue().unwrap().iter().all(|i| i.get_id() != a_id && i.get_id() != c_id));
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn db_trash_and_move_files() {
+        let root = std::env::temp_dir().join("tifari_files_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), root.to_str().unwrap().to_string());
+        cfg.update(patch);
+
+        let scan = Arc::new(ScanData::default());
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        std::fs::write(root.join("a.png"), "aaa").unwrap();
+        std::fs::write(root.join("a.txt"), "hand").unwrap();
+        std::fs::write(root.join("b.png"), "bbb").unwrap();
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+
+        let a_id = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "a.png").unwrap().unwrap();
+        db.give_tag(a_id, "hand").unwrap();
+
+        // moving keeps the tags and takes the caption along
+        for bad in &["../a.png", "sub/a.png", ".hidden.png", "b.png"] {
+            assert!(db.move_image(&cfg, a_id, bad).is_err());
+        }
+
+        let image = db.move_image(&cfg, a_id, "c.png").unwrap();
+        assert_eq!(image.get_path(), "c.png");
+        assert_eq!(image.get_tags().len(), 1);
+        assert!(root.join("c.png").is_file() && root.join("c.txt").is_file());
+        assert!(!root.join("a.png").exists());
+
+        // a scan afterwards doesn't see anything missing or new
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+        assert_eq!(db.get_num_present_images().unwrap(), 2);
+
+        // trashing hides the image but keeps it's tags until it's restored
+        db.trash_image(&cfg, a_id).unwrap();
+        assert!(!root.join("c.png").exists());
+        assert_eq!(db.get_trash().unwrap().len(), 1);
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 0);
+
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+
+        let image = db.restore_image(&cfg, a_id).unwrap();
+        assert!(image.get_missing_since().is_none());
+        assert!(root.join("c.png").is_file());
+        assert!(db.get_trash().unwrap().is_empty());
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+
+        match db.restore_image(&cfg, a_id) {
+            Err(BackendError::ImageNotInTrash) => {},
+            _ => panic!("expected ImageNotInTrash"),
+        }
+
+        // a trashed image whose file comes back is taken out of the trash, along with the trashed copy
+        db.trash_image(&cfg, a_id).unwrap();
+        std::fs::write(root.join("c.png"), "aaa").unwrap();
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+        assert!(db.get_trash().unwrap().is_empty());
+        assert_eq!(db.search(&vec!["hand"]).unwrap().len(), 1);
+        assert_eq!(std::fs::read_dir(root.join(TRASH_DIR_NAME)).unwrap().count(), 0);
+
+        // emptying the trash deletes the file for good
+        db.trash_image(&cfg, a_id).unwrap();
+        assert_eq!(db.empty_trash(&cfg).unwrap(), 1);
+        assert!(db.get_image_from_db(a_id).is_err());
+        assert_eq!(std::fs::read_dir(root.join(TRASH_DIR_NAME)).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn db_add_image_file() {
+        let root = std::env::temp_dir().join("tifari_upload_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), root.to_str().unwrap().to_string());
+        cfg.update(patch);
+
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let png = b"\x89PNG\r\n\x1a\n0000";
+        let tags = vec!["hand".to_string(), "pose".to_string()];
+
+        let image = db.add_image_file(&cfg, DEFAULT_ROOT_NAME, "ref.jpg", png, &tags).unwrap();
+        assert_eq!(image.get_path(), "ref.png");
+        assert_eq!(image.get_tags().len(), 2);
+        assert_eq!(std::fs::read(root.join("ref.png")).unwrap(), png.to_vec());
+        assert!(db.get_tag_queue().unwrap().is_empty());
+
+        // the name is taken, so the second upload gets a number
+        let image = db.add_image_file(&cfg, DEFAULT_ROOT_NAME, "ref.png", png, &vec![]).unwrap();
+        assert_eq!(image.get_path(), "ref-1.png");
+        assert_eq!(db.get_tag_queue().unwrap().len(), 1);
+
+        match db.add_image_file(&cfg, DEFAULT_ROOT_NAME, "ref.png", b"<html>", &vec![]) {
+            Err(BackendError::NotAnImage) => {},
+            _ => panic!("expected NotAnImage"),
+        }
+
+        match db.add_image_file(&cfg, "nas", "ref.png", png, &vec![]) {
+            Err(BackendError::RootNotFound(_)) => {},
+            _ => panic!("expected RootNotFound"),
+        }
+
+        // a bad tag fails the upload before anything is written
+        match db.add_image_file(&cfg, DEFAULT_ROOT_NAME, "ref.png", png, &vec!["hand".to_string(), "-pose".to_string()]) {
+            Err(BackendError::BadTag) => {},
+            _ => panic!("expected BadTag"),
+        }
+        assert_eq!(db.get_tag_queue().unwrap().len(), 1);
+
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 2);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn db_random_images() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let mut ids = vec![];
+        for i in 0..5 {
+            ids.push(db.try_insert_image(&format!("img{}.png", i)).unwrap());
+        }
+
+        for id in &ids[0..3] {
+            db.give_tag(*id, "figure").unwrap();
+        }
+        db.give_tag(ids[0], "nsfw").unwrap();
+
+        let images = db.get_random_images(&vec!["figure", "-nsfw"], 10, None, 0).unwrap();
+        let mut found: Vec<i64> = images.iter().map(|i| i.get_id()).collect();
+        found.sort();
+        assert_eq!(found, vec![ids[1], ids[2]]);
+
+        // everything when there's no query, nothing for tags that don't exist
+        assert_eq!(db.get_random_images(&vec![], 10, None, 0).unwrap().len(), 5);
+        assert!(db.get_random_images(&vec!["nonexistent"], 10, None, 0).unwrap().is_empty());
+
+        let pick = |db: &mut TifariDb, seed| -> Vec<i64> {
+            db.get_random_images(&vec![], 3, Some(seed), 0).unwrap().iter().map(|i| i.get_id()).collect()
+        };
+        assert_eq!(pick(&mut db, 42), pick(&mut db, 42));
+
+        // the images of the last requests are left out
+        let first = db.get_random_images(&vec!["figure", "-nsfw"], 1, None, 1).unwrap();
+        let second = db.get_random_images(&vec!["figure", "-nsfw"], 1, None, 1).unwrap();
+        assert!(first[0].get_id() != second[0].get_id());
+        assert!(db.get_random_images(&vec!["figure", "-nsfw"], 1, None, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn db_gesture_sessions() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        for i in 0..2 {
+            let id = db.try_insert_image(&format!("img{}.png", i)).unwrap();
+            db.give_tag(id, "figure").unwrap();
+        }
+
+        assert!(db.create_gesture_session("figure", "ten times thirty", None).is_err());
+        match db.create_gesture_session("figure", "1x99999999999999999h", None) {
+            Err(BackendError::BadSchedule(_)) => {},
+            _ => panic!("expected BadSchedule"),
+        }
+        assert!(db.create_gesture_session("nonexistent", "1x30s", None).is_err());
+
+        // more slots than images, so the images are shown again.
+        let session = db.create_gesture_session("figure", "3x30s, 1x2m", Some(7)).unwrap();
+        let id = session.get_id();
+        let start = session.get_created_at_time();
+
+        assert_eq!(session.get_slots().len(), 4);
+        assert_eq!(session.get_position(), 0);
+        assert_eq!(session.get_remaining_seconds(), 30);
+
+        // the clock keeps running between requests.
+        let session = db.get_gesture_session_at(id, start + 70).unwrap();
+        assert_eq!(session.get_position(), 2);
+        assert_eq!(session.get_remaining_seconds(), 20);
+        assert_eq!(session.get_slots()[0].get_seconds_spent(), Some(30));
+
+        // time spent paused doesn't count.
+        db.pause_gesture_session_at(id, start + 75).unwrap();
+        let session = db.get_gesture_session_at(id, start + 500).unwrap();
+        assert!(session.is_paused());
+        assert_eq!(session.get_position(), 2);
+        assert_eq!(session.get_remaining_seconds(), 15);
+
+        db.resume_gesture_session_at(id, start + 500).unwrap();
+        db.skip_gesture_image_at(id, start + 505).unwrap();
+
+        let session = db.get_gesture_session_at(id, start + 505).unwrap();
+        assert_eq!(session.get_position(), 3);
+        assert_eq!(session.get_remaining_seconds(), 120);
+        assert_eq!(session.get_slots()[2].get_seconds_spent(), Some(20));
+        assert!(session.get_slots()[2].is_skipped());
+
+        let session = db.get_gesture_session_at(id, start + 1000).unwrap();
+        assert_eq!(session.get_finished_at_time(), Some(start + 625));
+        assert_eq!(session.get_position(), 4);
+        assert!(session.get_current().is_none());
+        assert_eq!(session.get_slots()[3].get_seconds_spent(), Some(120));
+
+        // ending a session early keeps the time of the image that was up.
+        let session = db.create_gesture_session("figure", "5x1m", None).unwrap();
+        let (id, start) = (session.get_id(), session.get_created_at_time());
+
+        db.end_gesture_session_at(id, start + 90).unwrap();
+        let session = db.get_gesture_session_at(id, start + 1000).unwrap();
+        assert_eq!(session.get_finished_at_time(), Some(start + 90));
+        assert_eq!(session.get_position(), 5);
+        assert_eq!(session.get_slots()[1].get_seconds_spent(), Some(30));
+        assert_eq!(session.get_slots()[2].get_seconds_spent(), None);
+
+        assert_eq!(db.get_gesture_sessions().unwrap().len(), 2);
+        // the images that were up for their whole time count as drawn from.
+        assert_eq!(db.get_practice_log(10).unwrap().len(), 4);
+        assert!(db.get_gesture_session(1000).is_err());
+    }
+
+    #[test]
+    fn db_image_stats() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let mut ids = vec![];
+        for i in 0..3 {
+            let id = db.try_insert_image(&format!("img{}.png", i)).unwrap();
+            db.give_tag(id, "figure").unwrap();
+            ids.push(id);
+        }
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+
+        db.record_image_view_at(a, 10).unwrap();
+        db.record_image_view_at(a, 20).unwrap();
+        db.record_image_view_at(b, 5).unwrap();
+        db.record_drawing_at(c, Some(120), 100).unwrap();
+        db.record_drawing_at(a, None, 50).unwrap();
+        assert!(db.record_image_view_at(1000, 10).is_err());
+
+        let stats = db.get_image_stats(a).unwrap();
+        assert_eq!(stats.get_view_count(), 2);
+        assert_eq!(stats.get_last_viewed_at(), Some(20));
+        assert_eq!(stats.get_draw_count(), 1);
+        assert_eq!(stats.get_last_drawn_at(), Some(50));
+
+        let stats = db.get_image_stats(c).unwrap();
+        assert_eq!(stats.get_view_count(), 0);
+        assert_eq!(stats.get_last_viewed_at(), None);
+
+        let order = |db: &TifariDb, order: &str| -> Vec<i64> {
+            db.search(&vec!["figure", order]).unwrap().iter().map(|i| i.get_id()).collect()
+        };
+        assert_eq!(order(&db, "order:least_viewed"), vec![c, b, a]);
+        assert_eq!(order(&db, "order:not_viewed_recently"), vec![c, b, a]);
+        assert_eq!(order(&db, "order:least_drawn"), vec![b, a, c]);
+        assert_eq!(order(&db, "order:not_drawn_recently"), vec![b, a, c]);
+
+        let log = db.get_practice_log(10).unwrap();
+        assert_eq!(log.iter().map(|d| d.get_image_id()).collect::<Vec<_>>(), vec![c, a]);
+        assert_eq!(log[0].get_seconds(), Some(120));
+
+        db.erase_image_by_id(c).unwrap();
+        assert_eq!(db.get_practice_log(10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn db_related_tags() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let tags_of = vec![
+            vec!["hand", "foreshortening", "male"],
+            vec!["hand", "gesture", "male"],
+            vec!["hand", "gesture"],
+            vec!["hand", "female"],
+            vec!["gesture", "female"],
+        ];
+
+        let mut ids = vec![];
+        for (i, tags) in tags_of.iter().enumerate() {
+            let id = db.try_insert_image(&format!("img{}.png", i)).unwrap();
+            for tag in tags {
+                db.give_tag(id, tag).unwrap();
+            }
+            ids.push(id);
+        }
+
+        let tag_id = |db: &TifariDb, name: &str| -> i64 {
+            db.get_all_tags().unwrap().iter().find(|t| t.get_name() == name).unwrap().get_id()
+        };
+        let hand = tag_id(&db, "hand");
+
+        let names = |related: Vec<models::RelatedTag>| -> Vec<(String, i64)> {
+            related.iter().map(|t| (t.get_name().clone(), t.get_count())).collect()
+        };
+
+        assert_eq!(names(db.get_related_tags(hand, 10).unwrap()), vec![
+            ("gesture".to_string(), 2), ("male".to_string(), 2),
+            ("female".to_string(), 1), ("foreshortening".to_string(), 1)]);
+        assert_eq!(db.get_related_tags(hand, 1).unwrap().len(), 1);
+        assert!(db.get_related_tags(1000, 10).is_err());
+
+        // the counts follow tags being taken off and images being erased.
+        let male = tag_id(&db, "male");
+        db.remove_tag(ids[1], male).unwrap();
+        db.erase_image_by_id(ids[0]).unwrap();
+
+        assert_eq!(names(db.get_related_tags(hand, 10).unwrap()), vec![
+            ("gesture".to_string(), 2), ("female".to_string(), 1)]);
+
+        // rebuilding the counts from scratch gives the same result.
+        db.check_integrity(true).unwrap();
+        assert_eq!(names(db.get_related_tags(hand, 10).unwrap()), vec![
+            ("gesture".to_string(), 2), ("female".to_string(), 1)]);
+
+        assert_eq!(names(db.get_search_related_tags(&vec!["gesture", "-female"], 10).unwrap()), vec![
+            ("hand".to_string(), 2)]);
+    }
+
+    #[test]
+    fn db_suggested_tags() {
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), std::env::temp_dir().join("tifari_suggest_figure").to_str().unwrap().to_string());
+        cfg.update(patch);
+
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let first = db.try_insert_image("pose_01.png").unwrap();
+        let second = db.try_insert_image("pose_02.png").unwrap();
+        let other = db.try_insert_image("other.png").unwrap();
+
+        db.give_tags(&vec![first], &vec!["pose".to_string(), "male".to_string()]).unwrap();
+        db.give_tags(&vec![other], &vec!["figure".to_string(), "red hair".to_string()]).unwrap();
+
+        let suggested = |db: &TifariDb, now: i64| -> Vec<(String, f64)> {
+            db.get_suggested_tags_at(&cfg, second, 10, now).unwrap().iter()
+                .map(|s| (s.get_name().clone(), s.get_confidence()))
+                .collect()
+        };
+
+        let check = |got: Vec<(String, f64)>, expected: Vec<(&str, f64)>| {
+            assert_eq!(got.len(), expected.len());
+            for (got, expected) in got.iter().zip(expected.iter()) {
+                assert_eq!(got.0, expected.0);
+                assert!((got.1 - expected.1).abs() < 1e-9);
+            }
+        };
+
+        // the series, the file name and the folder, with every tag also given in this session.
+        let now = chrono::Utc::now().timestamp();
+        check(suggested(&db, now), vec![("pose", 0.86), ("male", 0.72), ("figure", 0.58), ("red hair", 0.3)]);
+
+        // once the session is over.
+        check(suggested(&db, now + suggestions::TAGGING_SESSION_SECONDS + 60), vec![("pose", 0.8), ("male", 0.6), ("figure", 0.4)]);
+
+        // tags the image has aren't suggested.
+        db.give_tag(second, "pose").unwrap();
+        assert!(suggested(&db, now).iter().all(|s| s.0 != "pose"));
+
+        assert!(db.get_suggested_tags(&cfg, 1000, 10).is_err());
+    }
+
+    #[test]
+    fn db_visually_similar_suggestions() {
+        let root = std::env::temp_dir().join("tifari_visual_hash_test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("image_root".to_string(), root.to_str().unwrap().to_string());
+        cfg.update(patch);
+
+        // b is a smaller and brighter copy of a, c is it's mirror image
+        image::ImageBuffer::from_fn(64, 64, |x, y| image::Luma([(x * 3 + y) as u8])).save(root.join("a.png")).unwrap();
+        image::ImageBuffer::from_fn(32, 32, |x, y| image::Luma([(x * 6 + y + 20) as u8])).save(root.join("b.png")).unwrap();
+        image::ImageBuffer::from_fn(64, 64, |x, y| image::Luma([(200 - x * 3 + y) as u8])).save(root.join("c.png")).unwrap();
+        std::fs::write(root.join("d.png"), "not really a png").unwrap();
+
+        let scan = Arc::new(ScanData::default());
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+        db.reload_root_unsafe(&cfg, &scan, false).unwrap();
+        db.ensure_visual_hashes(&cfg).unwrap();
+
+        let a = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "a.png").unwrap().unwrap();
+        let b = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "b.png").unwrap().unwrap();
+        let c = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "c.png").unwrap().unwrap();
+        let d = db.find_image_in_root(roots::DEFAULT_ROOT_ID, "d.png").unwrap().unwrap();
+
+        {
+            let hash = |id: i64| db.get_visual_hash(id).unwrap().unwrap();
+            assert!(visual_distance(hash(a), hash(b)) <= 2);
+            assert!(visual_distance(hash(a), hash(c)) >= 32);
+            assert_eq!(db.get_visual_hash(d).unwrap(), None);
+
+            let mut similar = db.find_visually_similar_images(hash(b), 10).unwrap();
+            similar.sort();
+            let mut expected = vec![a, b];
+            expected.sort();
+            assert_eq!(similar, expected);
+        }
+
+        db.give_tag(a, "hand").unwrap();
+        db.give_tag(c, "foot").unwrap();
+
+        // after the tagging session, so that only what the images look like counts
+        let later = chrono::Utc::now().timestamp() + suggestions::TAGGING_SESSION_SECONDS + 60;
+        let suggested = db.get_suggested_tags_at(&cfg, b, 10, later).unwrap();
+        assert_eq!(suggested.len(), 1);
+        assert_eq!(suggested[0].get_name(), "hand");
+        assert_eq!(suggested[0].get_sources(), &vec!["similar_image".to_string()]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn db_tag_queue_workflow() {
+        let mut db = TifariDb::new_in_memory().unwrap();
+        db.setup_tables().unwrap();
+
+        let a = db.try_insert_image("a.png").unwrap();
+        let b = db.try_insert_image("b.png").unwrap();
+        let c = db.try_insert_image("c.png").unwrap();
+
+        let nas_id = db.get_or_create_root_id("nas").unwrap();
+        let d = db.try_insert_image_in_root(nas_id, "d.png").unwrap();
+
+        let queue = |db: &TifariDb, order: TagQueueOrder, root: Option<&str>, now: i64| -> Vec<i64> {
+            db.get_tag_queue_at(order, root, now).unwrap().iter().map(|i| i.get_id()).collect()
+        };
+
+        assert_eq!(queue(&db, TagQueueOrder::Newest, None, 0), vec![d, c, b, a]);
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, None, 0), vec![a, b, c, d]);
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, Some("nas"), 0), vec![d]);
+        assert_eq!(queue(&db, TagQueueOrder::Random, None, 0).len(), 4);
+
+        // deferred images come back once their date has passed.
+        db.defer_image(a, Some(100)).unwrap();
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, None, 50), vec![b, c, d]);
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, None, 100), vec![a, b, c, d]);
+        assert_eq!(db.get_num_elements_in_tag_queue_at(50).unwrap(), 3);
+
+        // tagged images can be put up for review, and stay in the queue until they're done.
+        db.give_tag(b, "hand").unwrap();
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, None, 100), vec![a, c, d]);
+
+        db.mark_image_for_review(b).unwrap();
+        let review = db.get_tag_queue_at(TagQueueOrder::Oldest, None, 100).unwrap();
+        assert_eq!(review[1].get_id(), b);
+        assert_eq!(review[1].get_tags().len(), 1);
+
+        db.mark_image_done(b).unwrap();
+        db.mark_image_done(c).unwrap();
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, None, 100), vec![a, d]);
+
+        // an image that loses it's last tag is back in the queue, even when it was done.
+        let hand = db.get_all_tags().unwrap()[0].get_id();
+        db.remove_tag(b, hand).unwrap();
+        assert_eq!(queue(&db, TagQueueOrder::Oldest, None, 100), vec![a, b, d]);
+
+        assert!(db.defer_image(1000, None).is_err());
+
+        db.erase_image_by_id(c).unwrap();
+        let left: i64 = db.connection.query_row("SELECT COUNT(*) FROM tag_queue_states WHERE image_id=?", &[&c], |row| row.get(0)).unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn db_pool_concurrent_writes() {
+        let dir = std::env::temp_dir().join("tifari_pool_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("db_root".to_string(), dir.join("pool.db").to_str().unwrap().to_string());
+        cfg.update(patch);
+        let cfg = Arc::new(RwLock::new(cfg));
+
+        let pool = DbPool::new(cfg.clone(), 2);
+        let image_id = {
+            let mut db = pool.get().unwrap();
+            db.setup_tables().unwrap();
+
+            let image_id = db.try_insert_image("img.png").unwrap();
+
+            // rusqlite won't read pragmas that can change the database, so the mode is told by the log file.
+            assert!(dir.join("pool.db-wal").exists());
+            image_id
+        };
+        assert_eq!(pool.get_num_idle(), 1);
+
+        // a long lived connection, like the scan thread's, reading while the others write.
+        let scan = TifariDb::new(cfg.clone()).unwrap();
+
+        let threads: Vec<_> = (0..4).map(|t| {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                for i in 0..20 {
+                    let mut db = pool.get().unwrap();
+                    db.give_tag(image_id, &format!("tag_{}_{}", t, i)).unwrap();
+                }
+            })
+        }).collect();
+
+        for _ in 0..20 {
+            scan.get_image_from_db(image_id).unwrap();
+        }
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(scan.get_image_from_db(image_id).unwrap().get_tags().len(), 80);
+        assert!(pool.get_num_idle() <= 2);
+
+        // connections to the old database aren't handed out once the config points elsewhere.
+        let mut patch = HashMap::new();
+        patch.insert("db_root".to_string(), dir.join("other.db").to_str().unwrap().to_string());
+        cfg.write().unwrap().update(patch);
+
+        let db = pool.get().unwrap();
+        db.setup_tables().unwrap();
+        assert_eq!(db.get_num_elements_in_tag_queue().unwrap(), 0);
+        drop(db);
+        assert_eq!(pool.get_num_idle(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn write_transaction_outlasts_busy_timeout() {
+        let dir = std::env::temp_dir().join("tifari_busy_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("busy.db");
+        let mut cfg = TifariConfig::default();
+        let mut patch = HashMap::new();
+        patch.insert("db_root".to_string(), path.to_str().unwrap().to_string());
+        cfg.update(patch);
+        let cfg = Arc::new(RwLock::new(cfg));
+
+        let mut db = TifariDb::new(cfg.clone()).unwrap();
+        db.setup_tables().unwrap();
+        let image_id = db.try_insert_image("img.png").unwrap();
+
+        // a short busy timeout, so that the write lock below is held for longer than it.
+        db.connection.busy_timeout(std::time::Duration::from_millis(100)).unwrap();
+
+        let (locked, wait_locked) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+            locked.send(()).unwrap();
+
+            std::thread::sleep(std::time::Duration::from_millis(250));
+            conn.execute_batch("ROLLBACK").unwrap();
+        });
+
+        wait_locked.recv().unwrap();
+        let start = std::time::Instant::now();
+        db.give_tag(image_id, "tag").unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+
+        writer.join().unwrap();
+        assert_eq!(db.get_image_from_db(image_id).unwrap().get_tags().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
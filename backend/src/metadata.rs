@@ -0,0 +1,89 @@
+use super::*;
+
+impl TifariDb
+{
+    pub fn get_image_metadata(&self, image_id: i64) -> Result<models::ImageMetadata> {
+        let (artist, license): (String, String) = match self.connection.query_row(
+            "SELECT artist, license FROM image_metadata WHERE image_id=?",
+            &[&image_id],
+            |row| (row.get(0), row.get(1)))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => (String::new(), String::new()),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        let mut statement = self.connection.prepare(
+            "SELECT url FROM image_sources WHERE image_id=? ORDER BY id ASC")?;
+
+        let mut sources = vec![];
+        for result in statement.query_map(&[&image_id], |row| row.get(0))? {
+            sources.push(result?);
+        }
+
+        Ok(models::ImageMetadata::new(sources, artist, license))
+    }
+
+    // updates only the given fields, leaving the rest as they were.
+    pub fn update_image_metadata(&mut self,
+                                 image_id: i64,
+                                 sources: Option<&Vec<String>>,
+                                 artist: Option<&str>,
+                                 license: Option<&str>) -> Result<()>
+    {
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
+        }
+
+        tx.execute(
+            "INSERT OR IGNORE INTO image_metadata (image_id, artist, license) VALUES (?, '', '')",
+            &[&image_id])?;
+
+        if let Some(artist) = artist {
+            tx.execute(
+                "UPDATE image_metadata SET artist=? WHERE image_id=?",
+                &[&artist, &image_id])?;
+        }
+
+        if let Some(license) = license {
+            tx.execute(
+                "UPDATE image_metadata SET license=? WHERE image_id=?",
+                &[&license, &image_id])?;
+        }
+
+        if let Some(sources) = sources {
+            tx.execute("DELETE FROM image_sources WHERE image_id=?", &[&image_id])?;
+
+            for url in sources {
+                tx.execute(
+                    "INSERT OR IGNORE INTO image_sources (id, image_id, url) VALUES (null, ?, ?)",
+                    &[&image_id, url])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn erase_metadata_of_image(tx: &rusqlite::Transaction, image_id: i64) -> Result<()> {
+        tx.execute("DELETE FROM image_metadata WHERE image_id=?", &[&image_id])?;
+        tx.execute("DELETE FROM image_sources WHERE image_id=?", &[&image_id])?;
+        Ok(())
+    }
+
+    // builds a condition on images.id for the given filter. the filter's pattern has to be bound as a parameter.
+    pub(crate) fn make_metadata_condition(filter: &query::MetadataFilter) -> String {
+        let subquery = match filter.field {
+            query::MetadataField::Source =>
+                "SELECT image_id FROM image_sources WHERE url LIKE ? ESCAPE '\\'",
+            query::MetadataField::Artist =>
+                "SELECT image_id FROM image_metadata WHERE artist LIKE ? ESCAPE '\\'",
+            query::MetadataField::License =>
+                "SELECT image_id FROM image_metadata WHERE license LIKE ? ESCAPE '\\'",
+        };
+
+        format!("id {} IN ({})", if filter.negate { "NOT" } else { "" }, subquery)
+    }
+}
@@ -0,0 +1,104 @@
+use super::*;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// whether losing this many images out of the ones present is more than the config allows in one scan.
+// a fraction of 1 or more lets any scan through.
+pub(crate) fn is_too_many_missing(vanishing: usize, present: usize, max_fraction: f64) -> bool {
+    if vanishing == 0 || max_fraction >= 1.0 {
+        return false;
+    }
+
+    vanishing as f64 > present as f64 * max_fraction
+}
+
+impl TifariDb
+{
+    // adds the missing_since column to images in databases made before missing images were kept.
+    pub(crate) fn migrate_missing_images(&self) -> Result<()> {
+        if !self.has_column("images", "missing_since")? {
+            self.connection.execute("ALTER TABLE images ADD COLUMN missing_since INTEGER", &[])?;
+        }
+
+        Ok(())
+    }
+
+    // hides the image from search until it's file shows up again. an image that's already missing
+    // keeps the time it went missing at.
+    pub fn mark_image_missing(&self, image_id: i64) -> Result<()> {
+        self.connection.execute(
+            "UPDATE images SET missing_since=? WHERE id=? AND missing_since IS NULL",
+            &[&chrono::Utc::now().timestamp(), &image_id])?;
+
+        Ok(())
+    }
+
+    // a trashed image whose file shows up again is taken out of the trash, and the trashed copy is
+    // deleted, as nothing would refer to it anymore.
+    pub fn restore_missing_image(&self, cfg: &TifariConfig, image_id: i64) -> Result<()> {
+        self.delete_trashed_file(cfg, image_id);
+
+        self.connection.execute("UPDATE images SET missing_since=NULL WHERE id=?", &[&image_id])?;
+        self.connection.execute("DELETE FROM trash WHERE image_id=?", &[&image_id])?;
+        Ok(())
+    }
+
+    pub(crate) fn get_missing_image_ids(&self) -> Result<HashSet<i64>> {
+        let mut statement = self.connection.prepare("SELECT id FROM images WHERE missing_since IS NOT NULL")?;
+
+        let mut ids = HashSet::new();
+        for result in statement.query_map(&[], |row| row.get(0))? {
+            ids.insert(result?);
+        }
+
+        Ok(ids)
+    }
+
+    pub fn get_num_present_images(&self) -> Result<usize> {
+        let num: i64 = self.connection.query_row(
+            "SELECT COUNT(*) FROM images WHERE missing_since IS NULL",
+            &[],
+            |row| row.get(0))?;
+
+        Ok(num as usize)
+    }
+
+    // erases the images that have been missing for longer than the retention period, tags and all.
+    // trashed images are missing as well, so their files are deleted from the trash with them.
+    // returns the number of images erased.
+    pub fn purge_missing_images(&mut self, cfg: &TifariConfig) -> Result<usize> {
+        let cutoff = chrono::Utc::now().timestamp() - cfg.get_missing_retention_days() as i64 * SECONDS_PER_DAY;
+
+        let ids: Vec<i64> = {
+            let mut statement = self.connection.prepare(
+                "SELECT id FROM images WHERE missing_since IS NOT NULL AND missing_since <= ?")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[&cutoff], |row| row.get(0))? {
+                ids.push(result?);
+            }
+            ids
+        };
+
+        for id in &ids {
+            self.delete_trashed_file(cfg, *id);
+            self.erase_image_by_id(*id)?;
+        }
+
+        Ok(ids.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fraction() {
+        assert!(!is_too_many_missing(0, 0, 0.25));
+        assert!(!is_too_many_missing(25, 100, 0.25));
+        assert!(is_too_many_missing(26, 100, 0.25));
+        assert!(is_too_many_missing(1, 0, 0.25));
+        assert!(!is_too_many_missing(100, 100, 1.0));
+    }
+}
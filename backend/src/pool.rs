@@ -0,0 +1,125 @@
+use super::*;
+use std::sync::Mutex;
+
+// how many idle connections the pool keeps around. more are opened when they're all in use.
+pub const DEFAULT_POOL_SIZE: usize = 8;
+
+// how long a connection waits on another one that's writing before giving up with SQLITE_BUSY.
+const BUSY_TIMEOUT_MS: u64 = 5000;
+
+// how many times a write transaction that couldn't start because of SQLITE_BUSY is tried again,
+// and how long it waits before the first retry. the wait doubles with each retry.
+const WRITE_RETRIES: u32 = 3;
+const WRITE_RETRY_DELAY_MS: u64 = 50;
+
+pub(crate) fn is_busy(e: &rusqlite::Error) -> bool {
+    match e {
+        rusqlite::Error::SqliteFailure(e, _) =>
+            e.code == rusqlite::ErrorCode::DatabaseBusy || e.code == rusqlite::ErrorCode::DatabaseLocked,
+        _ => false,
+    }
+}
+
+impl TifariDb
+{
+    // readers don't block the writer in WAL mode, so the scan and the api can share the database.
+    // in memory databases stay in their own journal mode.
+    pub(crate) fn configure_connection(conn: &rusqlite::Connection) -> Result<()> {
+        conn.busy_timeout(std::time::Duration::from_millis(BUSY_TIMEOUT_MS))?;
+        conn.execute_batch("PRAGMA journal_mode = WAL")?;
+        Ok(())
+    }
+
+    // begins a transaction that takes the write lock right away. a deferred transaction that reads
+    // first and writes later can't wait for the lock and fails straight away if another
+    // connection wrote in the meantime, while this one waits out the busy timeout. when even that
+    // runs out, beginning it is tried again a few times before giving up.
+    pub(crate) fn write_transaction(&mut self) -> Result<rusqlite::Transaction> {
+        let mut delay = WRITE_RETRY_DELAY_MS;
+        let mut retries = 0;
+
+        // a failed begin leaves the connection unborrowed, but the borrow checker can't tell when
+        // the transaction of a successful one is returned from inside the loop, so each try
+        // borrows the connection through a pointer.
+        let conn: *mut rusqlite::Connection = &mut self.connection;
+
+        loop {
+            match unsafe { &mut *conn }.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate) {
+                Ok(tx) => return Ok(tx),
+                Err(ref e) if is_busy(e) && retries < WRITE_RETRIES => {
+                    std::thread::sleep(std::time::Duration::from_millis(delay));
+                    delay *= 2;
+                    retries += 1;
+                },
+                Err(e) => return Err(BackendError::from(e)),
+            }
+        }
+    }
+}
+
+// a set of open connections to the database, shared by the threads that need one for a short
+// while, like the api's request handlers. cloning it gives another handle to the same pool.
+#[derive(Clone)]
+pub struct DbPool {
+    cfg: Arc<RwLock<TifariConfig>>,
+    // the idle connections, along with the path of the database they're for.
+    idle: Arc<Mutex<Vec<(String, TifariDb)>>>,
+    max_idle: usize,
+}
+
+impl DbPool {
+    pub fn new(cfg: Arc<RwLock<TifariConfig>>, max_idle: usize) -> Self {
+        DbPool { cfg, idle: Arc::new(Mutex::new(vec![])), max_idle }
+    }
+
+    // an idle connection, or a new one if there's none. connections to a database the config
+    // doesn't point to anymore are closed.
+    pub fn get(&self) -> Result<PooledDb> {
+        let path = self.cfg.read().unwrap().get_db_root().clone();
+
+        let db = {
+            let mut idle = self.idle.lock().unwrap();
+            idle.retain(|&(ref p, _)| *p == path);
+            idle.pop().map(|(_, db)| db)
+        };
+
+        let db = match db {
+            Some(v) => v,
+            None => TifariDb::new(self.cfg.clone())?,
+        };
+
+        Ok(PooledDb { db: Some(db), path, idle: self.idle.clone(), max_idle: self.max_idle })
+    }
+
+    pub fn get_num_idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+// a connection taken from the pool, which goes back to it when dropped.
+pub struct PooledDb {
+    db: Option<TifariDb>,
+    path: String,
+    idle: Arc<Mutex<Vec<(String, TifariDb)>>>,
+    max_idle: usize,
+}
+
+impl std::ops::Deref for PooledDb {
+    type Target = TifariDb;
+    fn deref(&self) -> &TifariDb { self.db.as_ref().unwrap() }
+}
+
+impl std::ops::DerefMut for PooledDb {
+    fn deref_mut(&mut self) -> &mut TifariDb { self.db.as_mut().unwrap() }
+}
+
+impl Drop for PooledDb {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            let mut idle = self.idle.lock().unwrap();
+            if idle.len() < self.max_idle {
+                idle.push((self.path.clone(), db));
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataField {
+    Source,
+    Artist,
+    License,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataFilter {
+    pub field: MetadataField,
+    // sql LIKE pattern, escaped with '\'
+    pub pattern: String,
+    pub negate: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Newest,
+    Oldest,
+    Path,
+    LeastViewed,
+    NotViewedRecently,
+    LeastDrawn,
+    NotDrawnRecently,
+}
+
+impl SortOrder {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(SortOrder::Newest),
+            "oldest" => Some(SortOrder::Oldest),
+            "path" => Some(SortOrder::Path),
+            "least_viewed" => Some(SortOrder::LeastViewed),
+            "not_viewed_recently" => Some(SortOrder::NotViewedRecently),
+            "least_drawn" => Some(SortOrder::LeastDrawn),
+            "not_drawn_recently" => Some(SortOrder::NotDrawnRecently),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "newest",
+            SortOrder::Oldest => "oldest",
+            SortOrder::Path => "path",
+            SortOrder::LeastViewed => "least_viewed",
+            SortOrder::NotViewedRecently => "not_viewed_recently",
+            SortOrder::LeastDrawn => "least_drawn",
+            SortOrder::NotDrawnRecently => "not_drawn_recently",
+        }
+    }
+
+    // the ORDER BY clause for a select on the images table. images that were never viewed or
+    // drawn from sort as NULL, which comes first.
+    pub fn to_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "ORDER BY id DESC",
+            SortOrder::Oldest => "ORDER BY id ASC",
+            SortOrder::Path => "ORDER BY path ASC",
+            SortOrder::LeastViewed =>
+                "ORDER BY COALESCE((SELECT view_count FROM image_views WHERE image_id=images.id), 0) ASC, id ASC",
+            SortOrder::NotViewedRecently =>
+                "ORDER BY (SELECT last_viewed_at FROM image_views WHERE image_id=images.id) ASC, id ASC",
+            SortOrder::LeastDrawn =>
+                "ORDER BY (SELECT COUNT(*) FROM drawings WHERE image_id=images.id) ASC, id ASC",
+            SortOrder::NotDrawnRecently =>
+                "ORDER BY (SELECT MAX(drawn_at_time) FROM drawings WHERE image_id=images.id) ASC, id ASC",
+        }
+    }
+}
+
+impl Default for SortOrder {
+    fn default() -> Self { SortOrder::Newest }
+}
+
+// what to do with images whose files the scanner couldn't find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingFilter {
+    Hide,
+    Include,
+    Only,
+}
+
+impl MissingFilter {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "hide" => Some(MissingFilter::Hide),
+            "include" => Some(MissingFilter::Include),
+            "only" => Some(MissingFilter::Only),
+            _ => None,
+        }
+    }
+
+    // the condition for a select on the images table, if any.
+    pub fn to_sql(&self) -> Option<&'static str> {
+        match self {
+            MissingFilter::Hide => Some("missing_since IS NULL"),
+            MissingFilter::Include => None,
+            MissingFilter::Only => Some("missing_since IS NOT NULL"),
+        }
+    }
+}
+
+impl Default for MissingFilter {
+    fn default() -> Self { MissingFilter::Hide }
+}
+
+// a search query split into it's parts. tokens that aren't prefixed with a known
+// "name:" are treated as tags.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchQuery {
+    pub tags_contains: Vec<String>,
+    pub tags_remove: Vec<String>,
+    pub metadata: Vec<MetadataFilter>,
+    pub order: Option<SortOrder>,
+    pub missing: Option<MissingFilter>,
+    // names of saved searches to be merged into this query
+    pub saved: Vec<String>,
+}
+
+// converts a glob with '*' wildcards into a LIKE pattern.
+fn glob_to_like(glob: &str) -> String {
+    let mut pattern = String::with_capacity(glob.len());
+
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push('%'),
+            '%' | '_' | '\\' => { pattern.push('\\'); pattern.push(c); },
+            _ => pattern.push(c),
+        }
+    }
+
+    pattern
+}
+
+// the "name:" prefixes the parser takes for itself. a tag starting with one of them can't be searched for.
+const FILTER_NAMESPACES: [&str; 6] = ["order", "missing", "saved", "source", "artist", "license"];
+
+pub(crate) fn is_filter_namespace(namespace: &str) -> bool {
+    FILTER_NAMESPACES.contains(&namespace)
+}
+
+// tags are only listed once, as images are matched by how many of the listed tags they have.
+fn push_unique(tags: &mut Vec<String>, tag: &str) {
+    if !tags.iter().any(|t| t == tag) {
+        tags.push(tag.to_string());
+    }
+}
+
+impl SearchQuery {
+    pub fn parse(tokens: &Vec<&str>) -> Result<Self> {
+        let mut query = SearchQuery::default();
+
+        for token in tokens {
+            if token.starts_with("order:") {
+                match SortOrder::parse(&token["order:".len()..]) {
+                    Some(order) => query.order = Some(order),
+                    None => return Err(BackendError::BadQuery(token.to_string())),
+                }
+                continue;
+            }
+
+            if token.starts_with("missing:") {
+                match MissingFilter::parse(&token["missing:".len()..]) {
+                    Some(missing) => query.missing = Some(missing),
+                    None => return Err(BackendError::BadQuery(token.to_string())),
+                }
+                continue;
+            }
+
+            if token.starts_with("saved:") {
+                query.saved.push(token["saved:".len()..].to_string());
+                continue;
+            }
+
+            let (negate, token) =
+                if token.starts_with("-") { (true, &token[1..]) }
+                else { (false, &token[..]) };
+
+            let field =
+                if token.starts_with("source:") { Some((MetadataField::Source, "source:".len())) }
+                else if token.starts_with("artist:") { Some((MetadataField::Artist, "artist:".len())) }
+                else if token.starts_with("license:") { Some((MetadataField::License, "license:".len())) }
+                else { None };
+
+            match field {
+                Some((field, prefix_len)) => query.metadata.push(MetadataFilter {
+                    field,
+                    pattern: glob_to_like(&token[prefix_len..]),
+                    negate,
+                }),
+                None => {
+                    if negate { push_unique(&mut query.tags_remove, token); }
+                    else { push_unique(&mut query.tags_contains, token); }
+                }
+            }
+        }
+
+        Ok(query)
+    }
+
+    // splits a query string, as stored in saved searches, into tokens.
+    pub fn tokenize(query: &str) -> Vec<&str> {
+        query.split_whitespace().collect()
+    }
+
+    // merges the tags and filters of another query into this one. the sort order and
+    // missing filter of this query take precedence.
+    pub fn merge(&mut self, other: SearchQuery) {
+        for tag in &other.tags_contains {
+            push_unique(&mut self.tags_contains, tag);
+        }
+
+        for tag in &other.tags_remove {
+            push_unique(&mut self.tags_remove, tag);
+        }
+
+        self.metadata.extend(other.metadata);
+
+        if self.order.is_none() {
+            self.order = other.order;
+        }
+
+        if self.missing.is_none() {
+            self.missing = other.missing;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_parse() {
+        let query = SearchQuery::parse(&vec!["hand", "-nsfw", "source:*artstation*", "-artist:bob_", "order:oldest", "saved:daily"]).unwrap();
+
+        assert_eq!(query.tags_contains, vec!["hand".to_string()]);
+        assert_eq!(query.tags_remove, vec!["nsfw".to_string()]);
+
+        assert_eq!(query.metadata.len(), 2);
+        assert_eq!(query.metadata[0], MetadataFilter { field: MetadataField::Source, pattern: "%artstation%".to_string(), negate: false });
+        assert_eq!(query.metadata[1], MetadataFilter { field: MetadataField::Artist, pattern: "bob\\_".to_string(), negate: true });
+
+        assert_eq!(query.order, Some(SortOrder::Oldest));
+        assert_eq!(query.saved, vec!["daily".to_string()]);
+
+        assert_eq!(query.missing, None);
+
+        assert!(SearchQuery::parse(&vec!["order:sideways"]).is_err());
+        assert_eq!(SearchQuery::parse(&vec!["order:not_drawn_recently"]).unwrap().order, Some(SortOrder::NotDrawnRecently));
+
+        let mut query = SearchQuery::parse(&vec!["hand", "hand", "-nsfw"]).unwrap();
+        query.merge(SearchQuery::parse(&vec!["hand", "-nsfw", "figure"]).unwrap());
+        assert_eq!(query.tags_contains, vec!["hand".to_string(), "figure".to_string()]);
+        assert_eq!(query.tags_remove, vec!["nsfw".to_string()]);
+
+        assert_eq!(SearchQuery::parse(&vec!["missing:only"]).unwrap().missing, Some(MissingFilter::Only));
+        assert!(SearchQuery::parse(&vec!["missing:sometimes"]).is_err());
+    }
+}
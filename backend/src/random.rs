@@ -0,0 +1,170 @@
+use super::*;
+
+// how many past requests are remembered for leaving recently served images out.
+pub const RANDOM_HISTORY_LENGTH: usize = 1000;
+
+// splitmix64. small, and the same seed always gives the same picks, which std doesn't promise.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn get_time_seed() -> u64 {
+    let now = chrono::Utc::now();
+    (now.timestamp() as u64).wrapping_mul(1_000_000_000).wrapping_add(now.timestamp_subsec_nanos() as u64)
+}
+
+// count distinct numbers below total, in a random order.
+fn pick_offsets(rng: &mut Rng, total: usize, count: usize) -> Vec<usize> {
+    let count = std::cmp::min(count, total);
+    let mut picked = HashSet::new();
+    let mut offsets = Vec::with_capacity(count);
+
+    while offsets.len() < count {
+        let offset = rng.below(total);
+        if picked.insert(offset) {
+            offsets.push(offset);
+        }
+    }
+
+    offsets
+}
+
+impl TifariDb
+{
+    // the condition for images that have the tag, on themselves or on one of their regions.
+    fn make_has_tag_condition(tag_id: i64, negate: bool) -> String {
+        format!("id {} IN (
+                    SELECT image_id FROM image_ids_array_table_{}
+                    UNION
+                    SELECT regions.image_id FROM region_tags
+                    JOIN regions ON regions.id = region_tags.region_id
+                    WHERE region_tags.tag_id = {})",
+                if negate { "NOT" } else { "" }, tag_id, tag_id)
+    }
+
+    // picks count random images matching the query, none of them twice. the matches are counted and
+    // picked by their offset, so the ids of every match are never loaded. images served by the last
+    // exclude_recent requests are left out. the same seed picks the same images as long as the
+    // library doesn't change.
+    pub fn get_random_images(&mut self, tags: &Vec<&str>, count: usize, seed: Option<u64>, exclude_recent: usize) -> Result<Vec<models::Image>> {
+        let query = self.parse_query(tags)?;
+
+        let mut tag_conditions = vec![];
+
+        for tag in &query.tags_contains {
+            match self.get_tag_id_by_name(tag)? {
+                Some(tag_id) => tag_conditions.push(TifariDb::make_has_tag_condition(tag_id, false)),
+                // no image can have a tag that doesn't exist.
+                None => return Ok(vec![]),
+            }
+        }
+
+        for tag in &query.tags_remove {
+            if let Some(tag_id) = self.get_tag_id_by_name(tag)? {
+                tag_conditions.push(TifariDb::make_has_tag_condition(tag_id, true));
+            }
+        }
+
+        let last_request_id: i64 = self.connection.query_row(
+            "SELECT COALESCE(MAX(request_id), 0) FROM random_history",
+            &[],
+            |row| row.get(0))?;
+
+        let exclude_recent = std::cmp::min(exclude_recent, RANDOM_HISTORY_LENGTH) as i64;
+        let oldest_excluded = last_request_id - exclude_recent;
+
+        let image_ids = {
+            let (mut conditions, mut params) = TifariDb::make_filter_conditions(&query);
+            conditions.extend(tag_conditions);
+
+            if exclude_recent > 0 {
+                conditions.push(String::from("id NOT IN (SELECT image_id FROM random_history WHERE request_id > ?)"));
+                params.push(&oldest_excluded);
+            }
+
+            let where_clause =
+                if conditions.is_empty() { String::new() }
+                else { format!("WHERE {}", conditions.join(" AND ")) };
+
+            let total: i64 = self.connection.query_row(
+                &format!("SELECT COUNT(*) FROM images {}", where_clause),
+                params.as_slice(),
+                |row| row.get(0))?;
+
+            let mut rng = Rng(seed.unwrap_or_else(get_time_seed));
+
+            let mut statement = self.connection.prepare(
+                &format!("SELECT id FROM images {} ORDER BY id ASC LIMIT 1 OFFSET ?", where_clause))?;
+
+            let mut image_ids = vec![];
+            for offset in pick_offsets(&mut rng, total as usize, count) {
+                let offset = offset as i64;
+                let mut offset_params = params.clone();
+                offset_params.push(&offset);
+
+                for result in statement.query_map(offset_params.as_slice(), |row| row.get::<usize, i64>(0))? {
+                    image_ids.push(result?);
+                }
+            }
+            image_ids
+        };
+
+        let tx = self.write_transaction()?;
+
+        let request_id = last_request_id + 1;
+        for id in &image_ids {
+            tx.execute("INSERT INTO random_history (request_id, image_id) VALUES (?, ?)", &[&request_id, id])?;
+        }
+
+        tx.execute(
+            "DELETE FROM random_history WHERE request_id <= ?",
+            &[&(request_id - RANDOM_HISTORY_LENGTH as i64)])?;
+
+        tx.commit()?;
+
+        let mut images = vec![];
+        for id in image_ids {
+            images.push(self.get_image_from_db(id)?);
+        }
+
+        Ok(images)
+    }
+
+    fn get_tag_id_by_name(&self, tag: &str) -> Result<Option<i64>> {
+        match self.connection.query_row("SELECT id FROM tags WHERE name=? LIMIT 1", &[&tag], |row| row.get(0)) {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_offsets() {
+        let offsets = pick_offsets(&mut Rng(7), 10, 4);
+        assert_eq!(offsets.len(), 4);
+        assert_eq!(offsets.iter().collect::<HashSet<_>>().len(), 4);
+        assert!(offsets.iter().all(|o| *o < 10));
+
+        assert_eq!(offsets, pick_offsets(&mut Rng(7), 10, 4));
+        assert_eq!(pick_offsets(&mut Rng(7), 3, 5).len(), 3);
+        assert!(pick_offsets(&mut Rng(7), 0, 5).is_empty());
+    }
+}
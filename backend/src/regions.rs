@@ -0,0 +1,300 @@
+use super::*;
+
+impl TifariDb
+{
+    // regions are stored in normalized coordinates, so they have to fit inside the unit square.
+    pub(crate) fn is_valid_region(x: f64, y: f64, width: f64, height: f64) -> bool {
+        0.0 <= x && 0.0 <= y &&
+            0.0 < width && 0.0 < height &&
+            x + width <= 1.0 && y + height <= 1.0
+    }
+
+    fn get_region_tags(&self, region_id: i64) -> Result<HashSet<models::Tag>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name
+            FROM tags
+            WHERE id IN (SELECT tag_id FROM region_tags WHERE region_id=?)")?;
+
+        let mut tags = HashSet::new();
+        for result in statement.query_map(&[&region_id], |row| (row.get(0), row.get(1)))? {
+            let (tag_id, tag_name) = result?;
+            tags.insert(models::Tag::new(tag_id, tag_name));
+        }
+
+        Ok(tags)
+    }
+
+    pub fn get_region(&self, region_id: i64) -> Result<models::Region> {
+        let (image_id, x, y, width, height, note): (i64, f64, f64, f64, f64, String) = self.connection.query_row(
+            "SELECT image_id, x, y, width, height, note
+            FROM regions
+            WHERE id=?",
+            &[&region_id],
+            |row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4), row.get(5)))?;
+
+        let tags = self.get_region_tags(region_id)?;
+
+        Ok(models::Region::new(region_id, image_id, x, y, width, height, note, tags))
+    }
+
+    pub fn get_regions_of_image(&self, image_id: i64) -> Result<Vec<models::Region>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id FROM regions WHERE image_id=? ORDER BY id ASC")?;
+
+        let mut ids = vec![];
+        for result in statement.query_map(&[&image_id], |row| row.get(0))? {
+            ids.push(result?);
+        }
+
+        let mut regions = Vec::with_capacity(ids.len());
+        for id in ids {
+            regions.push(self.get_region(id)?);
+        }
+
+        Ok(regions)
+    }
+
+    pub fn add_region(&mut self,
+                      image_id: i64,
+                      x: f64, y: f64, width: f64, height: f64,
+                      note: &str,
+                      tags: &Vec<String>) -> Result<i64>
+    {
+        if !TifariDb::is_valid_region(x, y, width, height) {
+            return Err(BackendError::BadRegion);
+        }
+
+        for tag in tags {
+            if !TifariDb::is_valid_tag(tag) {
+                return Err(BackendError::BadTag);
+            }
+        }
+
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
+        }
+
+        tx.execute_named(
+            "INSERT INTO regions (id, image_id, x, y, width, height, note)
+            VALUES (null, :image_id, :x, :y, :width, :height, :note)",
+            &[(":image_id", &image_id),
+              (":x", &x),
+              (":y", &y),
+              (":width", &width),
+              (":height", &height),
+              (":note", &note),
+            ])?;
+
+        let region_id = tx.last_insert_rowid();
+
+        let mut changes = vec![];
+        for tag in tags {
+            if TifariDb::add_region_tag_tx(&tx, region_id, tag)?.is_some() {
+                changes.push(journal::Change::region_add(region_id, tag));
+            }
+        }
+
+        TifariDb::record_journal(&tx, "add_region_tags", &changes)?;
+
+        tx.commit()?;
+        Ok(region_id)
+    }
+
+    pub fn update_region(&mut self,
+                         region_id: i64,
+                         x: f64, y: f64, width: f64, height: f64,
+                         note: &str) -> Result<()>
+    {
+        if !TifariDb::is_valid_region(x, y, width, height) {
+            return Err(BackendError::BadRegion);
+        }
+
+        let changed = self.connection.execute_named(
+            "UPDATE regions
+            SET x=:x, y=:y, width=:width, height=:height, note=:note
+            WHERE id=:id",
+            &[(":x", &x),
+              (":y", &y),
+              (":width", &width),
+              (":height", &height),
+              (":note", &note),
+              (":id", &region_id),
+            ])?;
+
+        if 0 >= changed {
+            return Err(BackendError::RegionNotFound);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn region_exists(tx: &rusqlite::Transaction, region_id: i64) -> Result<bool> {
+        let mut statement = tx.prepare("SELECT id FROM regions WHERE id=? LIMIT 1")?;
+        Ok(statement.exists(&[&region_id])?)
+    }
+
+    // expects the region to exist. returns the tag's id if the region didn't have the tag yet.
+    pub(crate) fn add_region_tag_tx(tx: &rusqlite::Transaction, region_id: i64, tag: &str) -> Result<Option<i64>> {
+        let tag_id = TifariDb::get_or_create_tag(tx, tag)?;
+
+        let changed = tx.execute(
+            "INSERT OR IGNORE INTO region_tags (region_id, tag_id) VALUES (?, ?)",
+            &[&region_id, &tag_id])?;
+
+        Ok(if changed > 0 { Some(tag_id) } else { None })
+    }
+
+    // returns whether the region had the tag.
+    pub(crate) fn remove_region_tag_tx(tx: &rusqlite::Transaction, region_id: i64, tag_id: i64) -> Result<bool> {
+        let changed = tx.execute(
+            "DELETE FROM region_tags WHERE region_id=? AND tag_id=?",
+            &[&region_id, &tag_id])?;
+
+        if changed > 0 {
+            TifariDb::erase_tag_if_not_used(tx, tag_id)?;
+        }
+
+        Ok(changed > 0)
+    }
+
+    pub fn give_region_tag(&mut self, region_id: i64, tag: &str) -> Result<i64> {
+        if !TifariDb::is_valid_tag(tag) {
+            return Err(BackendError::BadTag);
+        }
+
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::region_exists(&tx, region_id)? {
+            return Err(BackendError::RegionNotFound);
+        }
+
+        let tag_id = match TifariDb::add_region_tag_tx(&tx, region_id, tag)? {
+            Some(v) => v,
+            None => return Err(BackendError::NoChangesOccured),
+        };
+
+        TifariDb::record_journal(&tx, "add_region_tag", &vec![journal::Change::region_add(region_id, tag)])?;
+
+        tx.commit()?;
+        Ok(tag_id)
+    }
+
+    // gives the region every valid tag it doesn't have yet, journaled as a single operation.
+    // returns the tags that were given.
+    pub fn give_region_tags(&mut self, region_id: i64, tags: &Vec<String>) -> Result<Vec<models::Tag>> {
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::region_exists(&tx, region_id)? {
+            return Err(BackendError::RegionNotFound);
+        }
+
+        let mut given = vec![];
+        let mut changes = vec![];
+
+        for tag in tags {
+            if !TifariDb::is_valid_tag(tag) {
+                continue;
+            }
+
+            if let Some(tag_id) = TifariDb::add_region_tag_tx(&tx, region_id, tag)? {
+                given.push(models::Tag::new(tag_id, tag.clone()));
+                changes.push(journal::Change::region_add(region_id, tag));
+            }
+        }
+
+        TifariDb::record_journal(&tx, "add_region_tags", &changes)?;
+
+        tx.commit()?;
+        Ok(given)
+    }
+
+    pub fn remove_region_tag(&mut self, region_id: i64, tag_id: i64) -> Result<()> {
+        let tx = self.write_transaction()?;
+
+        let tag_name = TifariDb::get_tag_name(&tx, tag_id)?;
+
+        if !TifariDb::remove_region_tag_tx(&tx, region_id, tag_id)? {
+            return Err(BackendError::NoChangesOccured);
+        }
+
+        TifariDb::record_journal(&tx, "remove_region_tag", &vec![journal::Change::region_remove(region_id, &tag_name)])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn erase_region(&mut self, region_id: i64) -> Result<()> {
+        let tx = self.write_transaction()?;
+
+        let changed = tx.execute("DELETE FROM regions WHERE id=?", &[&region_id])?;
+        if 0 >= changed {
+            return Err(BackendError::RegionNotFound);
+        }
+
+        TifariDb::erase_region_tags(&tx, region_id)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn erase_region_tags(tx: &rusqlite::Transaction, region_id: i64) -> Result<()> {
+        let tag_ids = {
+            let mut statement = tx.prepare("SELECT tag_id FROM region_tags WHERE region_id=?")?;
+            let mut tag_ids: Vec<i64> = vec![];
+
+            for result in statement.query_map(&[&region_id], |row| row.get(0))? {
+                tag_ids.push(result?);
+            }
+
+            tag_ids
+        };
+
+        tx.execute("DELETE FROM region_tags WHERE region_id=?", &[&region_id])?;
+
+        for tag_id in tag_ids {
+            TifariDb::erase_tag_if_not_used(tx, tag_id)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn erase_regions_of_image(tx: &rusqlite::Transaction, image_id: i64) -> Result<()> {
+        let region_ids = {
+            let mut statement = tx.prepare("SELECT id FROM regions WHERE image_id=?")?;
+            let mut region_ids: Vec<i64> = vec![];
+
+            for result in statement.query_map(&[&image_id], |row| row.get(0))? {
+                region_ids.push(result?);
+            }
+
+            region_ids
+        };
+
+        tx.execute("DELETE FROM regions WHERE image_id=?", &[&image_id])?;
+
+        for region_id in region_ids {
+            TifariDb::erase_region_tags(tx, region_id)?;
+        }
+
+        Ok(())
+    }
+
+    // returns the ids of the regions of the given image that carry any of the given tags.
+    pub(crate) fn get_matching_region_ids(&self, image_id: i64, tag_ids_query: &str) -> Result<Vec<i64>> {
+        let mut statement = self.connection.prepare(
+            &format!("SELECT DISTINCT r.id
+                     FROM regions r
+                     JOIN region_tags rt ON rt.region_id = r.id
+                     WHERE r.image_id=? AND rt.tag_id IN {}
+                     ORDER BY r.id ASC", tag_ids_query))?;
+
+        let mut ids = vec![];
+        for result in statement.query_map(&[&image_id], |row| row.get(0))? {
+            ids.push(result?);
+        }
+
+        Ok(ids)
+    }
+}
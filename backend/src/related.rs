@@ -0,0 +1,199 @@
+use super::*;
+
+// tag_pairs holds how many images have both of two tags, once for each order of the two, so the
+// tags related to one are a single lookup. it's kept up to date as tags are given and taken away.
+// only the tags of the images themselves are counted, not the tags of their regions.
+
+impl TifariDb
+{
+    // creates tag_pairs and counts the pairs for databases made before they were kept.
+    pub(crate) fn migrate_tag_pairs(&self) -> Result<()> {
+        let mut statement = self.connection.prepare("SELECT name FROM sqlite_master WHERE type='table' AND name='tag_pairs'")?;
+        if statement.exists(&[])? {
+            return Ok(());
+        }
+
+        self.connection.execute_batch("
+            BEGIN;
+
+            CREATE TABLE tag_pairs (
+                    tag_id INTEGER NOT NULL,
+                    other_tag_id INTEGER NOT NULL,
+                    count INTEGER NOT NULL,
+                    UNIQUE(tag_id, other_tag_id));
+        ")?;
+
+        match TifariDb::rebuild_tag_pairs(&self.connection) {
+            Ok(()) => self.connection.execute_batch("COMMIT;")?,
+            Err(e) => {
+                self.connection.execute_batch("ROLLBACK;")?;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    // counts every pair from scratch.
+    pub(crate) fn rebuild_tag_pairs(conn: &rusqlite::Connection) -> Result<()> {
+        let prefix = "tags_array_table_";
+
+        let image_ids: Vec<i64> = {
+            let mut statement = conn.prepare(
+                "SELECT name FROM sqlite_master WHERE type='table' AND substr(name, 1, ?)=?")?;
+
+            let mut ids = vec![];
+            for result in statement.query_map(&[&(prefix.len() as i64), &prefix], |row| row.get::<usize, String>(0))? {
+                if let Ok(id) = result?[prefix.len()..].parse() {
+                    ids.push(id);
+                }
+            }
+            ids
+        };
+
+        let mut counts: HashMap<(i64, i64), i64> = HashMap::new();
+
+        for image_id in image_ids {
+            let mut statement = conn.prepare(&format!(
+                "SELECT tag_id FROM tags_array_table_{} WHERE tag_id IN (SELECT id FROM tags)", image_id))?;
+
+            let mut tag_ids = vec![];
+            for result in statement.query_map(&[], |row| row.get::<usize, i64>(0))? {
+                tag_ids.push(result?);
+            }
+
+            for a in &tag_ids {
+                for b in &tag_ids {
+                    if a != b {
+                        *counts.entry((*a, *b)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        conn.execute("DELETE FROM tag_pairs", &[])?;
+
+        for (&(tag_id, other_tag_id), count) in &counts {
+            conn.execute(
+                "INSERT INTO tag_pairs (tag_id, other_tag_id, count) VALUES (?, ?, ?)",
+                &[&tag_id, &other_tag_id, count])?;
+        }
+
+        Ok(())
+    }
+
+    // counts the pairs the tag makes with the image's other tags, once it's been given to the image.
+    pub(crate) fn add_tag_pairs(tx: &rusqlite::Transaction, image_id: i64, tag_id: i64) -> Result<()> {
+        let others = format!("SELECT tag_id FROM tags_array_table_{} WHERE tag_id != {}", image_id, tag_id);
+
+        tx.execute(
+            &format!("INSERT OR IGNORE INTO tag_pairs (tag_id, other_tag_id, count) SELECT ?, tag_id, 0 FROM ({})", others),
+            &[&tag_id])?;
+        tx.execute(
+            &format!("INSERT OR IGNORE INTO tag_pairs (tag_id, other_tag_id, count) SELECT tag_id, ?, 0 FROM ({})", others),
+            &[&tag_id])?;
+
+        tx.execute(
+            &format!("UPDATE tag_pairs SET count=count + 1
+                     WHERE (tag_id=? AND other_tag_id IN ({}))
+                     OR (other_tag_id=? AND tag_id IN ({}))", others, others),
+            &[&tag_id, &tag_id])?;
+
+        Ok(())
+    }
+
+    // takes back the pairs the tag made with the image's other tags, for when it's taken off the image.
+    pub(crate) fn remove_tag_pairs(tx: &rusqlite::Transaction, image_id: i64, tag_id: i64) -> Result<()> {
+        let others = format!("SELECT tag_id FROM tags_array_table_{} WHERE tag_id != {}", image_id, tag_id);
+
+        tx.execute(
+            &format!("UPDATE tag_pairs SET count=count - 1
+                     WHERE (tag_id=? AND other_tag_id IN ({}))
+                     OR (other_tag_id=? AND tag_id IN ({}))", others, others),
+            &[&tag_id, &tag_id])?;
+
+        tx.execute(
+            "DELETE FROM tag_pairs WHERE (tag_id=? OR other_tag_id=?) AND count <= 0",
+            &[&tag_id, &tag_id])?;
+
+        Ok(())
+    }
+
+    // takes back every pair between the image's tags, for when the image is erased.
+    pub(crate) fn remove_image_tag_pairs(tx: &rusqlite::Transaction, image_id: i64) -> Result<()> {
+        let tags = format!("SELECT tag_id FROM tags_array_table_{}", image_id);
+
+        tx.execute(
+            &format!("UPDATE tag_pairs SET count=count - 1
+                     WHERE tag_id IN ({}) AND other_tag_id IN ({})", tags, tags),
+            &[])?;
+
+        tx.execute("DELETE FROM tag_pairs WHERE count <= 0", &[])?;
+
+        Ok(())
+    }
+
+    // the tags most often given along with the tag, with the number of images that have both.
+    pub fn get_related_tags(&self, tag_id: i64, limit: usize) -> Result<Vec<models::RelatedTag>> {
+        let mut statement = self.connection.prepare("SELECT id FROM tags WHERE id=?")?;
+        if !statement.exists(&[&tag_id])? {
+            return Err(BackendError::TagNotFound(tag_id));
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT tags.id, tags.name, tag_pairs.count
+             FROM tag_pairs JOIN tags ON tags.id = tag_pairs.other_tag_id
+             WHERE tag_pairs.tag_id=?
+             ORDER BY tag_pairs.count DESC, tags.name ASC
+             LIMIT ?")?;
+
+        let mut related = vec![];
+        for result in statement.query_map(&[&tag_id, &(limit as i64)],
+            |row| models::RelatedTag::new(row.get(0), row.get(1), row.get(2)))?
+        {
+            related.push(result?);
+        }
+
+        Ok(related)
+    }
+
+    // the tags of the images the search finds, other than the ones searched for, with the number
+    // of those images that have them. these are counted on demand, as they depend on the whole query.
+    pub fn get_search_related_tags(&self, tags: &Vec<&str>, limit: usize) -> Result<Vec<models::RelatedTag>> {
+        if 0 >= tags.len() {
+            return Ok(vec![]);
+        }
+
+        let query = self.parse_query(tags)?;
+        let image_ids = self.find_matching_images(&query)?;
+
+        let mut counts: HashMap<i64, i64> = HashMap::new();
+        for image_id in image_ids {
+            let mut statement = self.connection.prepare(&format!("SELECT tag_id FROM tags_array_table_{}", image_id))?;
+
+            for result in statement.query_map(&[], |row| row.get::<usize, i64>(0))? {
+                *counts.entry(result?).or_insert(0) += 1;
+            }
+        }
+
+        let mut related = vec![];
+        for (tag_id, count) in counts {
+            let name: String = match self.connection.query_row("SELECT name FROM tags WHERE id=?", &[&tag_id], |row| row.get(0)) {
+                Ok(v) => v,
+                Err(rusqlite::Error::QueryReturnedNoRows) => continue,
+                Err(e) => return Err(BackendError::from(e)),
+            };
+
+            if query.tags_contains.contains(&name) {
+                continue;
+            }
+
+            related.push(models::RelatedTag::new(tag_id, name, count));
+        }
+
+        related.sort_by(|a, b| b.get_count().cmp(&a.get_count()).then_with(|| a.get_name().cmp(b.get_name())));
+        related.truncate(limit);
+
+        Ok(related)
+    }
+}
@@ -0,0 +1,179 @@
+use super::*;
+use std::path::Path;
+
+// an untagged image that a missing image could be relinked to.
+struct Candidate {
+    id: i64,
+    file_name: String,
+    hash: Option<String>,
+    size: Option<i64>,
+}
+
+fn file_name_of(path: &str) -> String {
+    match Path::new(path).file_name() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => path.to_string(),
+    }
+}
+
+impl TifariDb
+{
+    // images that are there but have no tags yet, which is what a moved file looks like after a scan.
+    fn get_relink_candidates(&self) -> Result<Vec<Candidate>> {
+        let mut statement = self.connection.prepare(
+            "SELECT images.id, images.path, image_files.hash, image_files.size
+            FROM images LEFT JOIN image_files ON image_files.image_id = images.id
+            WHERE images.missing_since IS NULL
+            AND images.id IN (SELECT image_id FROM tag_queue)
+            ORDER BY images.id ASC")?;
+
+        let mut candidates = vec![];
+        for result in statement.query_map(&[], |row| (row.get(0), row.get(1), row.get(2), row.get(3)))? {
+            let (id, path, hash, size): (i64, String, Option<String>, Option<i64>) = result?;
+            candidates.push(Candidate { id, file_name: file_name_of(&path), hash, size });
+        }
+
+        Ok(candidates)
+    }
+
+    // every missing image along with the new files it could've been moved to. files with the same
+    // contents come first, then the ones with the same file name and size.
+    pub fn get_missing_images(&mut self, cfg: &TifariConfig) -> Result<Vec<models::MissingImage>> {
+        self.ensure_image_hashes(cfg)?;
+
+        let candidates = self.get_relink_candidates()?;
+
+        let missing: Vec<(i64, String, Option<String>, Option<i64>)> = {
+            let mut statement = self.connection.prepare(
+                "SELECT images.id, images.path, image_files.hash, image_files.size
+                FROM images LEFT JOIN image_files ON image_files.image_id = images.id
+                WHERE images.missing_since IS NOT NULL
+                ORDER BY images.missing_since DESC, images.id ASC")?;
+
+            let mut missing = vec![];
+            for result in statement.query_map(&[], |row| (row.get(0), row.get(1), row.get(2), row.get(3)))? {
+                missing.push(result?);
+            }
+            missing
+        };
+
+        let mut results = vec![];
+        for (id, path, hash, size) in missing {
+            let file_name = file_name_of(&path);
+            let mut matches = vec![];
+
+            if hash.is_some() {
+                for c in candidates.iter().filter(|c| c.hash == hash) {
+                    matches.push((c.id, "hash"));
+                }
+            }
+
+            if size.is_some() {
+                let by_name: Vec<i64> = candidates.iter()
+                    .filter(|c| c.file_name == file_name && c.size == size)
+                    .filter(|c| !matches.iter().any(|&(id, _)| id == c.id))
+                    .map(|c| c.id)
+                    .collect();
+
+                matches.extend(by_name.into_iter().map(|id| (id, "name")));
+            }
+
+            let mut relink_candidates = vec![];
+            for (candidate_id, matched_by) in matches {
+                relink_candidates.push(models::RelinkCandidate::new(
+                        self.get_image_from_db(candidate_id)?, matched_by.to_string()));
+            }
+
+            results.push(models::MissingImage::new(self.get_image_from_db(id)?, relink_candidates));
+        }
+
+        Ok(results)
+    }
+
+    // points the missing image at the new image's file and erases the new image, so the tags, regions
+    // and metadata of the missing image carry over to the file. the new image has to be untagged,
+    // so nothing is lost by erasing it.
+    pub fn relink_image(&mut self, missing_id: i64, new_id: i64) -> Result<models::Image> {
+        let tx = self.write_transaction()?;
+
+        let missing_since: Option<i64> = match tx.query_row(
+            "SELECT missing_since FROM images WHERE id=?",
+            &[&missing_id],
+            |row| row.get(0))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(BackendError::ImageNotFound),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        if missing_since.is_none() {
+            return Err(BackendError::BadRelink(format!("image {} isn't missing", missing_id)));
+        }
+
+        let (root_id, path, new_missing_since): (i64, String, Option<i64>) = match tx.query_row(
+            "SELECT root_id, path, missing_since FROM images WHERE id=?",
+            &[&new_id],
+            |row| (row.get(0), row.get(1), row.get(2)))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(BackendError::ImageNotFound),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        if new_missing_since.is_some() {
+            return Err(BackendError::BadRelink(format!("image {} is missing as well", new_id)));
+        }
+
+        let new_tag_count: i64 = tx.query_row(
+            &format!("SELECT COUNT(*) FROM tags_array_table_{}", new_id),
+            &[],
+            |row| row.get(0))?;
+
+        if new_tag_count > 0 {
+            return Err(BackendError::BadRelink(format!("image {} already has tags", new_id)));
+        }
+
+        let file_info: Option<(String, i64)> = match tx.query_row(
+            "SELECT hash, size FROM image_files WHERE image_id=?",
+            &[&new_id],
+            |row| (row.get(0), row.get(1)))
+        {
+            Ok(v) => Some(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        let visual_hash: Option<Option<i64>> = match tx.query_row(
+            "SELECT hash FROM image_visual_hashes WHERE image_id=?",
+            &[&new_id],
+            |row| row.get(0))
+        {
+            Ok(v) => Some(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        // erased first, as the two would share a path otherwise.
+        TifariDb::erase_image_tx(&tx, new_id)?;
+
+        tx.execute(
+            "UPDATE images SET root_id=?, path=?, missing_since=NULL WHERE id=?",
+            &[&root_id, &path, &missing_id])?;
+
+        tx.execute("DELETE FROM image_files WHERE image_id=?", &[&missing_id])?;
+        if let Some((hash, size)) = file_info {
+            TifariDb::set_image_file_info(&tx, missing_id, &hash, size)?;
+        }
+
+        tx.execute("DELETE FROM image_visual_hashes WHERE image_id=?", &[&missing_id])?;
+        if let Some(hash) = visual_hash {
+            TifariDb::set_visual_hash(&tx, missing_id, hash)?;
+        }
+
+        // the caption next to the new file hasn't been synced with these tags yet.
+        tx.execute("DELETE FROM caption_sync WHERE image_id=?", &[&missing_id])?;
+
+        tx.commit()?;
+        self.get_image_from_db(missing_id)
+    }
+}
@@ -0,0 +1,154 @@
+use super::*;
+use std::path::PathBuf;
+
+// the root configured through image_root. images from before roots existed belong to it.
+pub const DEFAULT_ROOT_NAME: &str = "default";
+pub(crate) const DEFAULT_ROOT_ID: i64 = 1;
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ImageRoot
+{
+    // images are tied to their root by this name, so renaming a root is the same as removing it
+    // and adding a new one.
+    name: String,
+    path: String,
+}
+
+impl ImageRoot
+{
+    pub fn new(name: String, path: String) -> Self {
+        ImageRoot { name, path }
+    }
+
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_path(&self) -> &String { &self.path }
+}
+
+// root names end up in urls, so they're kept to something that doesn't need escaping.
+pub fn is_valid_root_name(name: &str) -> bool {
+    name.len() > 0 && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+impl TifariDb
+{
+    // adds the root_id column to images in databases made before there were multiple roots.
+    pub(crate) fn migrate_image_roots(&self) -> Result<()> {
+        if !self.has_column("images", "root_id")? {
+            self.connection.execute(
+                &format!("ALTER TABLE images ADD COLUMN root_id INTEGER NOT NULL DEFAULT {}", DEFAULT_ROOT_ID),
+                &[])?;
+        }
+
+        self.connection.execute(
+            "INSERT OR IGNORE INTO roots (id, name) VALUES (?, ?)",
+            &[&DEFAULT_ROOT_ID, &DEFAULT_ROOT_NAME])?;
+
+        Ok(())
+    }
+
+    pub fn get_or_create_root_id(&mut self, name: &str) -> Result<i64> {
+        self.connection.execute("INSERT OR IGNORE INTO roots (id, name) VALUES (null, ?)", &[&name])?;
+
+        Ok(self.connection.query_row(
+            "SELECT id FROM roots WHERE name=?",
+            &[&name],
+            |row| row.get(0))?)
+    }
+
+    // the roots images have been scanned from, as (id, name).
+    pub(crate) fn get_known_roots(&self) -> Result<Vec<(i64, String)>> {
+        let mut statement = self.connection.prepare("SELECT id, name FROM roots ORDER BY id ASC")?;
+
+        let mut roots = vec![];
+        for result in statement.query_map(&[], |row| (row.get(0), row.get(1)))? {
+            roots.push(result?);
+        }
+
+        Ok(roots)
+    }
+
+    // returns the id of the root the image is in, along with it's path relative to that root.
+    pub fn get_image_location(&self, image_id: i64) -> Result<(i64, String)> {
+        match self.connection.query_row(
+            "SELECT root_id, path FROM images WHERE id=?",
+            &[&image_id],
+            |row| (row.get(0), row.get(1)))
+        {
+            Ok(v) => Ok(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(BackendError::ImageNotFound),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // where the image's file is on disk, going by the roots in the config.
+    pub fn get_image_file_path(&self, cfg: &TifariConfig, image_id: i64) -> Result<PathBuf> {
+        let (root, path) = self.get_image_root(cfg, image_id)?;
+        Ok(std::path::Path::new(root.get_path()).join(path))
+    }
+
+    // the configured root the image is in, along with it's path relative to that root.
+    pub(crate) fn get_image_root(&self, cfg: &TifariConfig, image_id: i64) -> Result<(ImageRoot, String)> {
+        let (name, path): (String, String) = match self.connection.query_row(
+            "SELECT roots.name, images.path
+            FROM images JOIN roots ON roots.id = images.root_id
+            WHERE images.id=?",
+            &[&image_id],
+            |row| (row.get(0), row.get(1)))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(BackendError::ImageNotFound),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        match cfg.get_root_by_name(&name) {
+            Some(root) => Ok((root, path)),
+            None => Err(BackendError::RootNotFound(name)),
+        }
+    }
+
+    pub fn find_image_in_root(&self, root_id: i64, path: &str) -> Result<Option<i64>> {
+        match self.connection.query_row(
+            "SELECT id FROM images WHERE root_id=? AND path=? LIMIT 1",
+            &[&root_id, &path],
+            |row| row.get(0))
+        {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    // marks every image of the root as missing, for when the root is removed from the config. they're
+    // erased once they've been missing for the retention period, like any other missing image, and
+    // found again if the root is put back before then. returns the number of images marked.
+    pub fn mark_root_missing(&self, name: &str) -> Result<usize> {
+        let root_id: i64 = match self.connection.query_row(
+            "SELECT id FROM roots WHERE name=?",
+            &[&name],
+            |row| row.get(0))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(0),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        let num = self.connection.execute(
+            "UPDATE images SET missing_since=? WHERE root_id=? AND missing_since IS NULL",
+            &[&chrono::Utc::now().timestamp(), &root_id])?;
+
+        Ok(num as usize)
+    }
+
+    // paths of the images in the root, mapped to their ids.
+    pub(crate) fn get_image_paths_of_root(&self, root_id: i64) -> Result<HashMap<String, i64>> {
+        let mut statement = self.connection.prepare("SELECT path, id FROM images WHERE root_id=?")?;
+
+        let mut paths = HashMap::new();
+        for result in statement.query_map(&[&root_id], |row| (row.get(0), row.get(1)))? {
+            let (path, id) = result?;
+            paths.insert(path, id);
+        }
+
+        Ok(paths)
+    }
+}
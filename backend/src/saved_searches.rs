@@ -0,0 +1,144 @@
+use super::*;
+
+impl TifariDb
+{
+    // saved search names are referenced as "saved:name" tokens, so they can't contain whitespace.
+    fn is_valid_saved_search_name(name: &str) -> bool {
+        name.len() > 0 && !name.chars().any(|c| c.is_whitespace())
+    }
+
+    pub fn get_saved_search(&self, name: &str) -> Result<models::SavedSearch> {
+        match self.connection.query_row(
+            "SELECT id, name, query, sort_order FROM saved_searches WHERE name=?",
+            &[&name],
+            |row| models::SavedSearch::new(row.get(0), row.get(1), row.get(2), row.get(3)))
+        {
+            Ok(v) => Ok(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(BackendError::SavedSearchNotFound(name.to_string())),
+            Err(e) => Err(BackendError::from(e)),
+        }
+    }
+
+    pub fn get_all_saved_searches(&self) -> Result<Vec<models::SavedSearch>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, name, query, sort_order FROM saved_searches ORDER BY name ASC")?;
+
+        let mut retvals = vec![];
+        for result in statement.query_map(&[],
+            |row| models::SavedSearch::new(row.get(0), row.get(1), row.get(2), row.get(3)))?
+        {
+            retvals.push(result?);
+        }
+
+        Ok(retvals)
+    }
+
+    // creates the saved search or, if one with the same name exists, replaces it's query and sort order.
+    pub fn save_search(&mut self, name: &str, query_str: &str, sort_order: &str) -> Result<i64> {
+        if !TifariDb::is_valid_saved_search_name(name) {
+            return Err(BackendError::BadSavedSearchName);
+        }
+
+        // make sure the query and the sort order are valid before storing them.
+        let parsed = query::SearchQuery::parse(&query::SearchQuery::tokenize(query_str))?;
+        if query::SortOrder::parse(sort_order).is_none() {
+            return Err(BackendError::BadQuery(sort_order.to_string()));
+        }
+
+        // a search that ends up referencing itself could never be run.
+        if self.references_saved_search(&parsed, name, &mut HashSet::new())? {
+            return Err(BackendError::BadQuery(format!("saved:{}", name)));
+        }
+
+        let tx = self.write_transaction()?;
+
+        let changed = tx.execute(
+            "UPDATE saved_searches SET query=?, sort_order=? WHERE name=?",
+            &[&query_str, &sort_order, &name])?;
+
+        if 0 >= changed {
+            tx.execute(
+                "INSERT INTO saved_searches (id, name, query, sort_order) VALUES (null, ?, ?, ?)",
+                &[&name, &query_str, &sort_order])?;
+        }
+
+        let id: i64 = tx.query_row(
+            "SELECT id FROM saved_searches WHERE name=?",
+            &[&name],
+            |row| row.get(0))?;
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    pub fn remove_saved_search(&mut self, name: &str) -> Result<()> {
+        let changed = self.connection.execute(
+            "DELETE FROM saved_searches WHERE name=?",
+            &[&name])?;
+
+        if 0 >= changed {
+            return Err(BackendError::SavedSearchNotFound(name.to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn run_saved_search(&self, name: &str, offset: usize, limit: usize) -> Result<models::SearchPage> {
+        let token = format!("saved:{}", name);
+        self.search_page(&vec![&token[..]], offset, limit)
+    }
+
+    // whether the query references the saved search with the given name, directly or through the
+    // saved searches it references. ones that don't exist yet don't reference anything.
+    fn references_saved_search(&self, query: &query::SearchQuery, name: &str, visited: &mut HashSet<String>) -> Result<bool> {
+        for saved in &query.saved {
+            if saved == name {
+                return Ok(true);
+            }
+
+            if !visited.insert(saved.clone()) {
+                continue;
+            }
+
+            let saved_search = match self.get_saved_search(saved) {
+                Ok(v) => v,
+                Err(BackendError::SavedSearchNotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+
+            let inner = query::SearchQuery::parse(&query::SearchQuery::tokenize(saved_search.get_query()))?;
+            if self.references_saved_search(&inner, name, visited)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    // replaces the "saved:" references in the query with the contents of the saved searches.
+    // visited holds the names already expanded on the current path, so that cycles are caught.
+    pub(crate) fn resolve_saved_searches(&self, query: &mut query::SearchQuery, visited: &mut HashSet<String>) -> Result<()> {
+        let saved = std::mem::replace(&mut query.saved, vec![]);
+
+        for name in saved {
+            if visited.contains(&name) {
+                return Err(BackendError::BadQuery(format!("saved:{}", name)));
+            }
+
+            let saved_search = self.get_saved_search(&name)?;
+
+            let mut inner = query::SearchQuery::parse(&query::SearchQuery::tokenize(saved_search.get_query()))?;
+            if inner.order.is_none() {
+                inner.order = query::SortOrder::parse(saved_search.get_sort_order());
+            }
+
+            visited.insert(name.clone());
+            self.resolve_saved_searches(&mut inner, visited)?;
+            visited.remove(&name);
+
+            query.merge(inner);
+        }
+
+        Ok(())
+    }
+}
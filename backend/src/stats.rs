@@ -0,0 +1,87 @@
+use super::*;
+
+impl TifariDb
+{
+    pub(crate) fn record_image_view_at(&mut self, image_id: i64, now: i64) -> Result<()> {
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
+        }
+
+        tx.execute(
+            "INSERT OR IGNORE INTO image_views (image_id, view_count, last_viewed_at) VALUES (?, 0, ?)",
+            &[&image_id, &now])?;
+
+        tx.execute(
+            "UPDATE image_views SET view_count=view_count + 1, last_viewed_at=? WHERE image_id=?",
+            &[&now, &image_id])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // counts a view of the image, for the "order:least_viewed" and "order:not_viewed_recently" sort orders.
+    pub fn record_image_view(&mut self, image_id: i64) -> Result<models::ImageStats> {
+        self.record_image_view_at(image_id, chrono::Utc::now().timestamp())?;
+        self.get_image_stats(image_id)
+    }
+
+    pub(crate) fn record_drawing_at(&mut self, image_id: i64, seconds: Option<i64>, now: i64) -> Result<()> {
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
+        }
+
+        tx.execute(
+            "INSERT INTO drawings (id, image_id, drawn_at_time, seconds) VALUES (null, ?, ?, ?)",
+            &[&image_id, &now, &seconds])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // adds the image to the practice log as drawn from just now.
+    pub fn record_drawing(&mut self, image_id: i64, seconds: Option<i64>) -> Result<models::ImageStats> {
+        self.record_drawing_at(image_id, seconds, chrono::Utc::now().timestamp())?;
+        self.get_image_stats(image_id)
+    }
+
+    pub fn get_image_stats(&self, image_id: i64) -> Result<models::ImageStats> {
+        // makes sure the image exists, images that were never viewed don't have a row.
+        self.get_image_location(image_id)?;
+
+        let (view_count, last_viewed_at): (i64, Option<i64>) = match self.connection.query_row(
+            "SELECT view_count, last_viewed_at FROM image_views WHERE image_id=?",
+            &[&image_id],
+            |row| (row.get(0), row.get(1)))
+        {
+            Ok(v) => v,
+            Err(rusqlite::Error::QueryReturnedNoRows) => (0, None),
+            Err(e) => return Err(BackendError::from(e)),
+        };
+
+        let (draw_count, last_drawn_at): (i64, Option<i64>) = self.connection.query_row(
+            "SELECT COUNT(*), MAX(drawn_at_time) FROM drawings WHERE image_id=?",
+            &[&image_id],
+            |row| (row.get(0), row.get(1)))?;
+
+        Ok(models::ImageStats::new(image_id, view_count, last_viewed_at, draw_count, last_drawn_at))
+    }
+
+    // the most recent drawings, newest first.
+    pub fn get_practice_log(&self, limit: usize) -> Result<Vec<models::Drawing>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, image_id, drawn_at_time, seconds FROM drawings ORDER BY drawn_at_time DESC, id DESC LIMIT ?")?;
+
+        let mut drawings = vec![];
+        for result in statement.query_map(&[&(limit as i64)],
+            |row| models::Drawing::new(row.get(0), row.get(1), row.get(2), row.get(3)))?
+        {
+            drawings.push(result?);
+        }
+
+        Ok(drawings)
+    }
+}
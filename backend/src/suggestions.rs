@@ -0,0 +1,260 @@
+use super::*;
+
+// how sure each source is of the tags it suggests. a tag suggested by more than one source gets
+// the chance that at least one of them is right.
+const DUPLICATE_CONFIDENCE: f64 = 0.9;
+const SIMILAR_IMAGE_CONFIDENCE: f64 = 0.7;
+const SIMILAR_NAME_CONFIDENCE: f64 = 0.6;
+const FILE_NAME_CONFIDENCE: f64 = 0.5;
+const FOLDER_CONFIDENCE: f64 = 0.4;
+const RECENT_CONFIDENCE: f64 = 0.3;
+
+// images whose visual hashes differ in at most this many of their 64 bits look alike.
+const MAX_VISUAL_DISTANCE: u32 = 10;
+
+// tags given within this many seconds count as given in the same tagging session.
+pub const TAGGING_SESSION_SECONDS: i64 = 30 * 60;
+
+// tag names are matched with '_' in place of spaces and dashes, so "red hair" matches "red_hair.png".
+fn normalize_tag_name(name: &str) -> String {
+    name.trim().to_lowercase().replace(|c: char| c == ' ' || c == '-', "_")
+}
+
+// the lowercase words of a file or folder name, leaving out numbers and single letters.
+fn get_name_tokens(name: &str) -> Vec<String> {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| t.chars().count() > 1 && !t.chars().all(|c| c.is_ascii_digit()))
+        .map(|t| t.to_string())
+        .collect()
+}
+
+// the names of the tags the tokens spell out, with up to three tokens in a row making a tag.
+fn match_tokens(tokens: &Vec<String>, tags: &HashMap<String, (i64, String)>) -> Vec<(i64, String)> {
+    let mut matches = vec![];
+
+    for start in 0..tokens.len() {
+        for len in 1..4 {
+            if start + len > tokens.len() {
+                break;
+            }
+
+            if let Some(tag) = tags.get(&tokens[start..start + len].join("_")) {
+                if !matches.contains(tag) {
+                    matches.push(tag.clone());
+                }
+            }
+        }
+    }
+
+    matches
+}
+
+// the name of the series an image is part of, "pose" for "pose_012.png", or None when the name
+// doesn't end with a number.
+fn get_series_name(path: &str) -> Option<String> {
+    let stem = std::path::Path::new(path).file_stem()?.to_string_lossy().to_lowercase();
+    let numbered = stem.trim_right_matches(')');
+    let series = numbered.trim_right_matches(|c: char| c.is_ascii_digit());
+
+    if series.len() == numbered.len() {
+        return None;
+    }
+
+    let series = series.trim_right_matches(|c: char| c == ' ' || c == '_' || c == '-' || c == '.' || c == '(');
+    if series.len() <= 0 {
+        return None;
+    }
+
+    Some(series.to_string())
+}
+
+// a suggestion as it's being put together from it's sources.
+struct Suggestion {
+    name: String,
+    // the chance that every source so far is wrong.
+    miss: f64,
+    sources: Vec<String>,
+}
+
+struct Suggestions {
+    by_tag: HashMap<i64, Suggestion>,
+}
+
+impl Suggestions {
+    fn add(&mut self, tag_id: i64, name: &str, confidence: f64, source: &str) {
+        let suggestion = self.by_tag.entry(tag_id).or_insert_with(|| Suggestion { name: name.to_string(), miss: 1.0, sources: vec![] });
+
+        suggestion.miss *= 1.0 - confidence;
+        if !suggestion.sources.iter().any(|s| s == source) {
+            suggestion.sources.push(source.to_string());
+        }
+    }
+
+    // suggests the tags of the images that are like this one, more so the more of them have a tag.
+    fn add_neighbors(&mut self, neighbor_tags: &Vec<Vec<(i64, String)>>, confidence: f64, source: &str) {
+        let tagged: Vec<&Vec<(i64, String)>> = neighbor_tags.iter().filter(|tags| !tags.is_empty()).collect();
+        if tagged.is_empty() {
+            return;
+        }
+
+        let mut counts: HashMap<&(i64, String), usize> = HashMap::new();
+        for tags in &tagged {
+            for tag in tags.iter() {
+                *counts.entry(tag).or_insert(0) += 1;
+            }
+        }
+
+        for (&&(tag_id, ref name), count) in &counts {
+            self.add(tag_id, name, confidence * *count as f64 / tagged.len() as f64, source);
+        }
+    }
+}
+
+impl TifariDb
+{
+    fn get_image_tag_list(&self, image_id: i64) -> Result<Vec<(i64, String)>> {
+        let mut statement = self.connection.prepare(
+            &format!("SELECT id, name FROM tags WHERE id IN (SELECT tag_id FROM tags_array_table_{})", image_id))?;
+
+        let mut tags = vec![];
+        for result in statement.query_map(&[], |row| (row.get(0), row.get(1)))? {
+            tags.push(result?);
+        }
+
+        Ok(tags)
+    }
+
+    // suggests tags for the image from:
+    // * images with the same contents, images that look alike going by their visual hashes, and
+    //   images of the same series going by their names, like "pose_01.png" and "pose_02.png".
+    // * tags spelled out by the image's file name and the name of it's root's folder.
+    // * tags given during the current tagging session.
+    // tags the image already has aren't suggested. the best suggestions come first.
+    pub fn get_suggested_tags(&self, cfg: &TifariConfig, image_id: i64, limit: usize) -> Result<Vec<models::TagSuggestion>> {
+        self.get_suggested_tags_at(cfg, image_id, limit, chrono::Utc::now().timestamp())
+    }
+
+    pub(crate) fn get_suggested_tags_at(&self, cfg: &TifariConfig, image_id: i64, limit: usize, now: i64) -> Result<Vec<models::TagSuggestion>> {
+        let (root_id, path) = self.get_image_location(image_id)?;
+
+        let mut suggestions = Suggestions { by_tag: HashMap::new() };
+
+        let mut all_tags: HashMap<String, (i64, String)> = HashMap::new();
+        {
+            let mut statement = self.connection.prepare("SELECT id, name FROM tags")?;
+            for result in statement.query_map(&[], |row| (row.get::<usize, i64>(0), row.get::<usize, String>(1)))? {
+                let (id, name) = result?;
+                all_tags.insert(normalize_tag_name(&name), (id, name));
+            }
+        }
+
+        let mut duplicate_ids = vec![];
+        if let Some(hash) = self.get_image_hash(image_id)? {
+            let mut duplicates = vec![];
+            for id in self.find_images_by_hash(&hash)? {
+                if id != image_id {
+                    duplicates.push(self.get_image_tag_list(id)?);
+                    duplicate_ids.push(id);
+                }
+            }
+
+            suggestions.add_neighbors(&duplicates, DUPLICATE_CONFIDENCE, "duplicate");
+        }
+
+        // duplicates look alike as well, but they've been counted already.
+        if let Some(hash) = self.get_visual_hash(image_id)? {
+            let mut similar = vec![];
+            for id in self.find_visually_similar_images(hash, MAX_VISUAL_DISTANCE)? {
+                if id != image_id && !duplicate_ids.contains(&id) {
+                    similar.push(self.get_image_tag_list(id)?);
+                }
+            }
+
+            suggestions.add_neighbors(&similar, SIMILAR_IMAGE_CONFIDENCE, "similar_image");
+        }
+
+        if let Some(series) = get_series_name(&path) {
+            let mut siblings = vec![];
+            for (other_path, id) in self.get_image_paths_of_root(root_id)? {
+                if id != image_id && get_series_name(&other_path).as_ref() == Some(&series) {
+                    siblings.push(self.get_image_tag_list(id)?);
+                }
+            }
+
+            suggestions.add_neighbors(&siblings, SIMILAR_NAME_CONFIDENCE, "similar_name");
+        }
+
+        let stem = std::path::Path::new(&path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        for (tag_id, name) in match_tokens(&get_name_tokens(&stem), &all_tags) {
+            suggestions.add(tag_id, &name, FILE_NAME_CONFIDENCE, "file_name");
+        }
+
+        // images that aren't in a configured root don't have a folder to go by.
+        if let Ok((root, _)) = self.get_image_root(cfg, image_id) {
+            let folder = std::path::Path::new(root.get_path()).file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+
+            for (tag_id, name) in match_tokens(&get_name_tokens(&folder), &all_tags) {
+                suggestions.add(tag_id, &name, FOLDER_CONFIDENCE, "folder");
+            }
+        }
+
+        {
+            let mut statement = self.connection.prepare(
+                "SELECT journal_changes.tag_name, COUNT(*)
+                FROM journal_changes JOIN journal ON journal.id = journal_changes.journal_id
+                WHERE journal_changes.kind='add' AND journal.undone=0 AND journal.created_at_time >= ?
+                GROUP BY journal_changes.tag_name")?;
+
+            let mut recent = vec![];
+            for result in statement.query_map(&[&(now - TAGGING_SESSION_SECONDS)], |row| (row.get::<usize, String>(0), row.get::<usize, i64>(1)))? {
+                recent.push(result?);
+            }
+
+            let most = recent.iter().map(|&(_, count)| count).max().unwrap_or(0);
+            for (name, count) in recent {
+                // tags erased since don't come back as suggestions.
+                if let Some(&(tag_id, ref name)) = all_tags.get(&normalize_tag_name(&name)) {
+                    suggestions.add(tag_id, name, RECENT_CONFIDENCE * count as f64 / most as f64, "recent");
+                }
+            }
+        }
+
+        for (tag_id, _) in self.get_image_tag_list(image_id)? {
+            suggestions.by_tag.remove(&tag_id);
+        }
+
+        let mut results: Vec<models::TagSuggestion> = suggestions.by_tag.into_iter()
+            .map(|(tag_id, s)| models::TagSuggestion::new(tag_id, s.name, 1.0 - s.miss, s.sources))
+            .collect();
+
+        results.sort_by(|a, b| b.get_confidence().partial_cmp(&a.get_confidence()).unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.get_name().cmp(b.get_name())));
+        results.truncate(limit);
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestion_names() {
+        assert_eq!(get_name_tokens("Red-Hair_pose 01"), vec!["red", "hair", "pose"]);
+        assert_eq!(normalize_tag_name("Red hair"), "red_hair");
+
+        assert_eq!(get_series_name("pose_012.png"), Some("pose".to_string()));
+        assert_eq!(get_series_name("pose (3).jpg"), Some("pose".to_string()));
+        assert_eq!(get_series_name("pose.png"), None);
+        assert_eq!(get_series_name("0001.png"), None);
+
+        let mut tags = HashMap::new();
+        tags.insert("red_hair".to_string(), (1, "red hair".to_string()));
+        tags.insert("pose".to_string(), (2, "pose".to_string()));
+
+        let tokens = get_name_tokens("red_hair_pose_02");
+        assert_eq!(match_tokens(&tokens, &tags), vec![(1, "red hair".to_string()), (2, "pose".to_string())]);
+    }
+}
@@ -0,0 +1,165 @@
+use super::*;
+
+// how long an image is deferred for when it's not given a date, which is what skipping it does.
+pub const DEFAULT_DEFER_SECONDS: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagQueueOrder {
+    Newest,
+    Oldest,
+    Random,
+}
+
+impl TagQueueOrder {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(TagQueueOrder::Newest),
+            "oldest" => Some(TagQueueOrder::Oldest),
+            "random" => Some(TagQueueOrder::Random),
+            _ => None,
+        }
+    }
+
+    fn to_sql(&self) -> &'static str {
+        match self {
+            TagQueueOrder::Newest => "ORDER BY images.id DESC",
+            TagQueueOrder::Oldest => "ORDER BY images.id ASC",
+            TagQueueOrder::Random => "ORDER BY RANDOM()",
+        }
+    }
+}
+
+impl Default for TagQueueOrder {
+    fn default() -> Self { TagQueueOrder::Newest }
+}
+
+// the images in the queue are the untagged ones, which are in tag_queue, along with the ones marked
+// for review. images marked done and the ones deferred to a later date are left out of it.
+// takes the current time as it's parameter.
+const TAG_QUEUE_CONDITION: &str = "
+    (images.id IN (SELECT image_id FROM tag_queue)
+        OR images.id IN (SELECT image_id FROM tag_queue_states WHERE needs_review=1))
+    AND images.id NOT IN (SELECT image_id FROM tag_queue_states WHERE done=1 OR deferred_until > ?)
+    AND images.missing_since IS NULL";
+
+impl TifariDb
+{
+    // a new untagged image, or one that lost it's last tag, isn't done anymore.
+    pub(crate) fn reset_tag_queue_state(tx: &rusqlite::Transaction, image_id: i64) -> Result<()> {
+        tx.execute("UPDATE tag_queue_states SET done=0 WHERE image_id=?", &[&image_id])?;
+        Ok(())
+    }
+
+    pub fn get_tag_queue(&self) -> Result<Vec<models::Image>> {
+        self.get_tag_queue_at(TagQueueOrder::default(), None, chrono::Utc::now().timestamp())
+    }
+
+    // the queue in the given order, only with the images of the root if one is given.
+    pub fn get_sorted_tag_queue(&self, order: TagQueueOrder, root_name: Option<&str>) -> Result<Vec<models::Image>> {
+        self.get_tag_queue_at(order, root_name, chrono::Utc::now().timestamp())
+    }
+
+    pub(crate) fn get_tag_queue_at(&self, order: TagQueueOrder, root_name: Option<&str>, now: i64) -> Result<Vec<models::Image>> {
+        let mut params: Vec<&rusqlite::types::ToSql> = vec![&now];
+
+        let root_condition =
+            if let Some(ref name) = root_name {
+                params.push(name);
+                "AND roots.name=?"
+            }
+            else { "" };
+
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT images.id, images.path, images.created_at_time, roots.name, COALESCE(tag_queue_states.needs_review, 0)
+            FROM images
+            JOIN roots ON roots.id = images.root_id
+            LEFT JOIN tag_queue_states ON tag_queue_states.image_id = images.id
+            WHERE {} {}
+            {}", TAG_QUEUE_CONDITION, root_condition, order.to_sql()))?;
+
+        let rows: Vec<(i64, String, i64, String, i64)> = {
+            let mut rows = vec![];
+            for result in statement.query_map(params.as_slice(),
+                |row| (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4)))?
+            {
+                rows.push(result?);
+            }
+            rows
+        };
+
+        let mut results = vec![];
+        for (id, path, created_at_time, root, needs_review) in rows {
+            // images up for review have tags, which are what's being reviewed.
+            if needs_review != 0 {
+                results.push(self.get_image_from_db(id)?);
+                continue;
+            }
+
+            let mut image = models::Image::new_no_tags(id, path, created_at_time);
+            image.set_root(root);
+            results.push(image);
+        }
+
+        Ok(results)
+    }
+
+    pub fn get_num_elements_in_tag_queue(&self) -> Result<i64> {
+        self.get_num_elements_in_tag_queue_at(chrono::Utc::now().timestamp())
+    }
+
+    pub(crate) fn get_num_elements_in_tag_queue_at(&self, now: i64) -> Result<i64> {
+        let retval = self.connection.query_row(
+            &format!("SELECT count(*) FROM images WHERE {}", TAG_QUEUE_CONDITION),
+            &[&now],
+            |row| row.get(0))?;
+
+        Ok(retval)
+    }
+
+    fn set_tag_queue_state(&mut self, image_id: i64, update: &str, params: &[&rusqlite::types::ToSql]) -> Result<()> {
+        let tx = self.write_transaction()?;
+
+        if !TifariDb::image_exists(&tx, image_id)? {
+            return Err(BackendError::ImageNotFound);
+        }
+
+        tx.execute(
+            "INSERT OR IGNORE INTO tag_queue_states (image_id, needs_review, deferred_until, done) VALUES (?, 0, NULL, 0)",
+            &[&image_id])?;
+
+        let mut params = params.to_vec();
+        params.push(&image_id);
+        tx.execute(&format!("UPDATE tag_queue_states SET {} WHERE image_id=?", update), params.as_slice())?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    // hides the image from the queue until the given time, or for a day if there's none.
+    pub fn defer_image(&mut self, image_id: i64, until: Option<i64>) -> Result<()> {
+        let until = until.unwrap_or(chrono::Utc::now().timestamp() + DEFAULT_DEFER_SECONDS);
+        self.set_tag_queue_state(image_id, "deferred_until=?", &[&until])
+    }
+
+    // puts the image in the queue even though it has tags, until it's marked done.
+    pub fn mark_image_for_review(&mut self, image_id: i64) -> Result<()> {
+        self.set_tag_queue_state(image_id, "needs_review=1, done=0", &[])
+    }
+
+    // takes the image out of the queue, tags or not. it comes back if it loses all of it's tags later on.
+    pub fn mark_image_done(&mut self, image_id: i64) -> Result<()> {
+        self.set_tag_queue_state(image_id, "needs_review=0, deferred_until=NULL, done=1", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_queue_orders() {
+        assert_eq!(TagQueueOrder::parse("oldest"), Some(TagQueueOrder::Oldest));
+        assert_eq!(TagQueueOrder::parse("random"), Some(TagQueueOrder::Random));
+        assert_eq!(TagQueueOrder::parse("sideways"), None);
+    }
+}
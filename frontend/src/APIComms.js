@@ -27,8 +27,10 @@ class TifariAPI {
         this.endpoint.getAllTags= endpoint + "/api/v1/get_all_tags";
         this.endpoint.getTagQueueSize = endpoint + "/api/v1/tag_queue_size";
         this.endpoint.reloadRoot = endpoint + "/api/v1/reload";
+        this.endpoint.forceReloadRoot = endpoint + "/api/v1/force_reload";
         this.endpoint.config= endpoint + "/api/v1/config";
         this.endpoint.image= endpoint + "/";
+        this.endpoint.roots= endpoint + "/roots/";
         this.endpoint.status= endpoint + "/api/v1/status";
     }
 
@@ -99,6 +101,12 @@ class TifariAPI {
         );
     }
 
+    forceReload() {
+        return this.doRequest(() => 
+            fetch(this.endpoint.forceReloadRoot, { method: "GET" })
+        );
+    }
+
     getToBeTaggedList() {
         return this.doRequest(() => 
             fetch(this.endpoint.tagQueue, { method: "GET" })
@@ -106,6 +114,9 @@ class TifariAPI {
         );
     }
     getImageUrl(img) {
+        if (img.root) {
+            return this.endpoint.roots + img.root + "/" + img.path;
+        }
         return this.endpoint.image + img.path;
     }
 
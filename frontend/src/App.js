@@ -593,6 +593,16 @@ class Bootstrapper extends Component {
             switch(payload.status) {
                 case 0: // valid
                     this.setState({stage: BS_SUCCESS, imgPathError: null});
+
+                    // the scan was stopped because too many images went missing, which usually means
+                    // a drive isn't mounted. only asked once per refused scan.
+                    if(payload.refused_missing > 0 && payload.refused_missing !== this.state.refusedMissing) {
+                        this.setState({refusedMissing: payload.refused_missing});
+
+                        if(window.confirm(`The last scan would have marked ${payload.refused_missing} images as missing and was stopped. Scan anyway?`)) {
+                            api.forceReload().then(() => this.requeryConfigState(api, false));
+                        }
+                    }
                     break;
                 case 1: //invalid image folder
                     this.setState({stage: BS_NEEDS_SETUP, imgPathError: !isInit ? "Invalid image folder.": null});
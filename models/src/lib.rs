@@ -37,6 +37,52 @@ impl TagWithUsage {
     pub fn get_times_used(&self) -> i64 { self.times_used }
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Region
+{
+    id: i64,
+    image_id: i64,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    note: String,
+    tags: HashSet<Tag>,
+}
+
+impl Region {
+    pub fn new(id: i64, image_id: i64, x: f64, y: f64, width: f64, height: f64, note: String, tags: HashSet<Tag>) -> Self {
+        Region { id, image_id, x, y, width, height, note, tags }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_x(&self) -> f64 { self.x }
+    pub fn get_y(&self) -> f64 { self.y }
+    pub fn get_width(&self) -> f64 { self.width }
+    pub fn get_height(&self) -> f64 { self.height }
+    pub fn get_note(&self) -> &String { &self.note }
+    pub fn get_tags(&self) -> &HashSet<Tag> { &self.tags }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ImageMetadata
+{
+    sources: Vec<String>,
+    artist: String,
+    license: String,
+}
+
+impl ImageMetadata {
+    pub fn new(sources: Vec<String>, artist: String, license: String) -> Self {
+        ImageMetadata { sources, artist, license }
+    }
+
+    pub fn get_sources(&self) -> &Vec<String> { &self.sources }
+    pub fn get_artist(&self) -> &String { &self.artist }
+    pub fn get_license(&self) -> &String { &self.license }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Image
 {
@@ -44,34 +90,61 @@ pub struct Image
     path: String,
     created_at_time : i64,
     tags: HashSet<Tag>,
+    #[serde(default)]
+    regions: Vec<Region>,
+    // ids of the regions that matched the search query this image was returned for.
+    #[serde(default)]
+    matched_regions: Vec<i64>,
+    #[serde(default)]
+    metadata: ImageMetadata,
+    // name of the root the image is in. the file is served at /roots/{root}/{path}.
+    #[serde(default)]
+    root: String,
+    // when the scanner last found the file gone, none while it's there.
+    #[serde(default)]
+    missing_since: Option<i64>,
 }
 
 impl Image {
     pub fn new(id: i64, path: String, created_at_time: i64, tags: HashSet<Tag>) -> Self {
-        Image { id, path, created_at_time, tags }
+        Image { id, path, created_at_time, tags, regions: vec![], matched_regions: vec![], metadata: ImageMetadata::default(), root: String::new(), missing_since: None }
     }
     pub fn new_no_tags(id: i64, path: String, created_at_time: i64) -> Self {
-        Image { id, path, created_at_time, tags: HashSet::new() }
+        Image { id, path, created_at_time, tags: HashSet::new(), regions: vec![], matched_regions: vec![], metadata: ImageMetadata::default(), root: String::new(), missing_since: None }
     }
 
     pub fn get_id(&self) -> i64 { self.id }
     pub fn get_path(&self) -> &String { &self.path }
     pub fn get_created_at_time(&self) -> i64 { self.created_at_time }
     pub fn get_tags(&self) -> &HashSet<Tag> { &self.tags }
+    pub fn get_regions(&self) -> &Vec<Region> { &self.regions }
+    pub fn get_matched_regions(&self) -> &Vec<i64> { &self.matched_regions }
+    pub fn get_metadata(&self) -> &ImageMetadata { &self.metadata }
+    pub fn get_root(&self) -> &String { &self.root }
+    pub fn get_missing_since(&self) -> Option<i64> { self.missing_since }
+
+    pub fn set_regions(&mut self, regions: Vec<Region>) { self.regions = regions; }
+    pub fn set_matched_regions(&mut self, ids: Vec<i64>) { self.matched_regions = ids; }
+    pub fn set_metadata(&mut self, metadata: ImageMetadata) { self.metadata = metadata; }
+    pub fn set_root(&mut self, root: String) { self.root = root; }
+    pub fn set_missing_since(&mut self, time: Option<i64>) { self.missing_since = time; }
 }
 
 #[derive(Serialize)]
 pub struct ErrorResponse {
     status: u32,
+    // machine readable, like "image_not_found".
+    code: String,
     message: String
 }
 
 impl ErrorResponse {
-    pub fn new(status: u32, message: String) -> Self {
-        ErrorResponse { status, message }
+    pub fn new(status: u32, code: String, message: String) -> Self {
+        ErrorResponse { status, code, message }
     }
 
     pub fn get_status(&self) -> u32 { self.status }
+    pub fn get_code(&self) -> &String { &self.code }
     pub fn get_message(&self) -> &String { &self.message }
 }
 
@@ -107,3 +180,833 @@ impl TagQueueSizeResponse {
         TagQueueSizeResponse { tag_queue_size }
     }
 }
+
+#[derive(Deserialize)]
+pub struct AddRegionRequest {
+    image_id: i64,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl AddRegionRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_x(&self) -> f64 { self.x }
+    pub fn get_y(&self) -> f64 { self.y }
+    pub fn get_width(&self) -> f64 { self.width }
+    pub fn get_height(&self) -> f64 { self.height }
+    pub fn get_note(&self) -> &String { &self.note }
+    pub fn get_tags(&self) -> &Vec<String> { &self.tags }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateRegionRequest {
+    region_id: i64,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    #[serde(default)]
+    note: String,
+}
+
+impl UpdateRegionRequest {
+    pub fn get_region_id(&self) -> i64 { self.region_id }
+    pub fn get_x(&self) -> f64 { self.x }
+    pub fn get_y(&self) -> f64 { self.y }
+    pub fn get_width(&self) -> f64 { self.width }
+    pub fn get_height(&self) -> f64 { self.height }
+    pub fn get_note(&self) -> &String { &self.note }
+}
+
+#[derive(Deserialize)]
+pub struct RemoveRegionRequest {
+    region_id: i64,
+}
+
+impl RemoveRegionRequest {
+    pub fn get_region_id(&self) -> i64 { self.region_id }
+}
+
+#[derive(Deserialize)]
+pub struct AddRegionTagsRequest {
+    region_id: i64,
+    tags: Vec<String>,
+}
+
+impl AddRegionTagsRequest {
+    pub fn get_region_id(&self) -> i64 { self.region_id }
+    pub fn get_tags(&self) -> &Vec<String> { &self.tags }
+}
+
+#[derive(Deserialize)]
+pub struct RemoveRegionTagsRequest {
+    region_id: i64,
+    tag_ids: Vec<i64>,
+}
+
+impl RemoveRegionTagsRequest {
+    pub fn get_region_id(&self) -> i64 { self.region_id }
+    pub fn get_tag_ids(&self) -> &Vec<i64> { &self.tag_ids }
+}
+
+// fields that are left out are not changed.
+#[derive(Deserialize)]
+pub struct SetMetadataRequest {
+    image_id: i64,
+    sources: Option<Vec<String>>,
+    artist: Option<String>,
+    license: Option<String>,
+}
+
+impl SetMetadataRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_sources(&self) -> Option<&Vec<String>> { self.sources.as_ref() }
+    pub fn get_artist(&self) -> Option<&str> { self.artist.as_ref().map(|e| &e[..]) }
+    pub fn get_license(&self) -> Option<&str> { self.license.as_ref().map(|e| &e[..]) }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedSearch {
+    id: i64,
+    name: String,
+    query: String,
+    sort_order: String,
+}
+
+impl SavedSearch {
+    pub fn new(id: i64, name: String, query: String, sort_order: String) -> Self {
+        SavedSearch { id, name, query, sort_order }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_query(&self) -> &String { &self.query }
+    pub fn get_sort_order(&self) -> &String { &self.sort_order }
+}
+
+#[derive(Serialize)]
+pub struct SearchPage {
+    total: usize,
+    offset: usize,
+    images: Vec<Image>,
+}
+
+impl SearchPage {
+    pub fn new(total: usize, offset: usize, images: Vec<Image>) -> Self {
+        SearchPage { total, offset, images }
+    }
+
+    pub fn get_total(&self) -> usize { self.total }
+    pub fn get_offset(&self) -> usize { self.offset }
+    pub fn get_images(&self) -> &Vec<Image> { &self.images }
+}
+
+fn default_sort_order() -> String { String::from("newest") }
+
+#[derive(Deserialize)]
+pub struct SaveSearchRequest {
+    name: String,
+    query: String,
+    #[serde(default = "default_sort_order")]
+    sort_order: String,
+}
+
+impl SaveSearchRequest {
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_query(&self) -> &String { &self.query }
+    pub fn get_sort_order(&self) -> &String { &self.sort_order }
+}
+
+#[derive(Deserialize)]
+pub struct RemoveSavedSearchRequest {
+    name: String,
+}
+
+impl RemoveSavedSearchRequest {
+    pub fn get_name(&self) -> &String { &self.name }
+}
+
+fn default_page_limit() -> usize { 50 }
+
+#[derive(Deserialize)]
+pub struct RunSavedSearchRequest {
+    name: String,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_page_limit")]
+    limit: usize,
+}
+
+impl RunSavedSearchRequest {
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_offset(&self) -> usize { self.offset }
+    pub fn get_limit(&self) -> usize { self.limit }
+}
+
+#[derive(Serialize)]
+pub struct JournalEntry {
+    id: i64,
+    created_at_time: i64,
+    operation: String,
+    undone: bool,
+    image_ids: Vec<i64>,
+    region_ids: Vec<i64>,
+    tag_names: Vec<String>,
+}
+
+impl JournalEntry {
+    pub fn new(id: i64, created_at_time: i64, operation: String, undone: bool,
+               image_ids: Vec<i64>, region_ids: Vec<i64>, tag_names: Vec<String>) -> Self {
+        JournalEntry { id, created_at_time, operation, undone, image_ids, region_ids, tag_names }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_created_at_time(&self) -> i64 { self.created_at_time }
+    pub fn get_operation(&self) -> &String { &self.operation }
+    pub fn is_undone(&self) -> bool { self.undone }
+    pub fn get_image_ids(&self) -> &Vec<i64> { &self.image_ids }
+    pub fn get_region_ids(&self) -> &Vec<i64> { &self.region_ids }
+    pub fn get_tag_names(&self) -> &Vec<String> { &self.tag_names }
+}
+
+#[derive(Deserialize)]
+pub struct RenameTagRequest {
+    tag_id: i64,
+    name: String,
+}
+
+impl RenameTagRequest {
+    pub fn get_tag_id(&self) -> i64 { self.tag_id }
+    pub fn get_name(&self) -> &String { &self.name }
+}
+
+#[derive(Deserialize)]
+pub struct MergeTagsRequest {
+    source_id: i64,
+    target_id: i64,
+}
+
+impl MergeTagsRequest {
+    pub fn get_source_id(&self) -> i64 { self.source_id }
+    pub fn get_target_id(&self) -> i64 { self.target_id }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedRegion {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl ExportedRegion {
+    pub fn new(x: f64, y: f64, width: f64, height: f64, note: String, tags: Vec<String>) -> Self {
+        ExportedRegion { x, y, width, height, note, tags }
+    }
+
+    pub fn get_x(&self) -> f64 { self.x }
+    pub fn get_y(&self) -> f64 { self.y }
+    pub fn get_width(&self) -> f64 { self.width }
+    pub fn get_height(&self) -> f64 { self.height }
+    pub fn get_note(&self) -> &String { &self.note }
+    pub fn get_tags(&self) -> &Vec<String> { &self.tags }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedImage {
+    path: String,
+    // md5 of the file contents, empty if unknown.
+    #[serde(default)]
+    hash: String,
+    #[serde(default)]
+    created_at_time: i64,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    metadata: ImageMetadata,
+    #[serde(default)]
+    regions: Vec<ExportedRegion>,
+}
+
+impl ExportedImage {
+    pub fn new(path: String, hash: String, created_at_time: i64, tags: Vec<String>,
+               metadata: ImageMetadata, regions: Vec<ExportedRegion>) -> Self {
+        ExportedImage { path, hash, created_at_time, tags, metadata, regions }
+    }
+
+    pub fn get_path(&self) -> &String { &self.path }
+    pub fn get_hash(&self) -> &String { &self.hash }
+    pub fn get_created_at_time(&self) -> i64 { self.created_at_time }
+    pub fn get_tags(&self) -> &Vec<String> { &self.tags }
+    pub fn get_metadata(&self) -> &ImageMetadata { &self.metadata }
+    pub fn get_regions(&self) -> &Vec<ExportedRegion> { &self.regions }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportDocument {
+    version: u32,
+    exported_at_time: i64,
+    images: Vec<ExportedImage>,
+}
+
+impl ExportDocument {
+    pub fn new(version: u32, exported_at_time: i64, images: Vec<ExportedImage>) -> Self {
+        ExportDocument { version, exported_at_time, images }
+    }
+
+    pub fn get_version(&self) -> u32 { self.version }
+    pub fn get_exported_at_time(&self) -> i64 { self.exported_at_time }
+    pub fn get_images(&self) -> &Vec<ExportedImage> { &self.images }
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    matched_by_hash: usize,
+    matched_by_path: usize,
+    // paths of the exported images that didn't match any image in the library.
+    unmatched: Vec<String>,
+    tags_added: usize,
+    regions_added: usize,
+}
+
+impl ImportReport {
+    pub fn new(matched_by_hash: usize, matched_by_path: usize, unmatched: Vec<String>,
+               tags_added: usize, regions_added: usize) -> Self {
+        ImportReport { matched_by_hash, matched_by_path, unmatched, tags_added, regions_added }
+    }
+
+    pub fn get_matched_by_hash(&self) -> usize { self.matched_by_hash }
+    pub fn get_matched_by_path(&self) -> usize { self.matched_by_path }
+    pub fn get_unmatched(&self) -> &Vec<String> { &self.unmatched }
+    pub fn get_tags_added(&self) -> usize { self.tags_added }
+    pub fn get_regions_added(&self) -> usize { self.regions_added }
+}
+
+#[derive(Serialize)]
+pub struct CountResponse {
+    count: usize,
+}
+
+impl CountResponse {
+    pub fn new(count: usize) -> Self {
+        CountResponse { count }
+    }
+
+    pub fn get_count(&self) -> usize { self.count }
+}
+
+// every field is optional, missing ones fall back to the server's defaults.
+#[derive(Deserialize)]
+pub struct CaptionSyncRequest {
+    #[serde(default)]
+    separator: Option<String>,
+    // "alphabetical" or "usage"
+    #[serde(default)]
+    order: Option<String>,
+    #[serde(default)]
+    strip_namespaces: Option<bool>,
+    // "prefer_file", "prefer_db", "merge" or "skip"
+    #[serde(default)]
+    conflict_policy: Option<String>,
+}
+
+impl CaptionSyncRequest {
+    pub fn get_separator(&self) -> Option<&String> { self.separator.as_ref() }
+    pub fn get_order(&self) -> Option<&String> { self.order.as_ref() }
+    pub fn get_strip_namespaces(&self) -> Option<bool> { self.strip_namespaces }
+    pub fn get_conflict_policy(&self) -> Option<&String> { self.conflict_policy.as_ref() }
+}
+
+#[derive(Serialize)]
+pub struct CaptionSyncReport {
+    imported: usize,
+    exported: usize,
+    // paths of the images that were skipped because both sides changed.
+    conflicts: Vec<String>,
+}
+
+impl CaptionSyncReport {
+    pub fn new(imported: usize, exported: usize, conflicts: Vec<String>) -> Self {
+        CaptionSyncReport { imported, exported, conflicts }
+    }
+
+    pub fn get_imported(&self) -> usize { self.imported }
+    pub fn get_exported(&self) -> usize { self.exported }
+    pub fn get_conflicts(&self) -> &Vec<String> { &self.conflicts }
+}
+
+// a post from a danbooru or gelbooru style metadata dump. danbooru splits the tags by category,
+// gelbooru only has them all in "tags".
+#[derive(Deserialize, Default)]
+pub struct BooruPost {
+    #[serde(default)]
+    md5: String,
+    #[serde(default)]
+    file_ext: String,
+    // gelbooru's file name
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    tag_string: String,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    tag_string_general: String,
+    #[serde(default)]
+    tag_string_artist: String,
+    #[serde(default)]
+    tag_string_character: String,
+    #[serde(default)]
+    tag_string_copyright: String,
+    #[serde(default)]
+    tag_string_meta: String,
+    #[serde(default)]
+    source: String,
+    #[serde(default)]
+    rating: String,
+}
+
+impl BooruPost {
+    pub fn get_md5(&self) -> &String { &self.md5 }
+    pub fn get_file_ext(&self) -> &String { &self.file_ext }
+    pub fn get_image(&self) -> &String { &self.image }
+    pub fn get_tag_string(&self) -> &String { &self.tag_string }
+    pub fn get_tags(&self) -> &String { &self.tags }
+    pub fn get_tag_string_general(&self) -> &String { &self.tag_string_general }
+    pub fn get_tag_string_artist(&self) -> &String { &self.tag_string_artist }
+    pub fn get_tag_string_character(&self) -> &String { &self.tag_string_character }
+    pub fn get_tag_string_copyright(&self) -> &String { &self.tag_string_copyright }
+    pub fn get_tag_string_meta(&self) -> &String { &self.tag_string_meta }
+    pub fn get_source(&self) -> &String { &self.source }
+    pub fn get_rating(&self) -> &String { &self.rating }
+}
+
+#[derive(Deserialize)]
+pub struct BooruImportRequest {
+    // when no posts are given, the .json sidecars in the image root are read instead.
+    #[serde(default)]
+    posts: Option<Vec<BooruPost>>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+impl BooruImportRequest {
+    pub fn get_posts(&self) -> Option<&Vec<BooruPost>> { self.posts.as_ref() }
+    pub fn get_dry_run(&self) -> bool { self.dry_run }
+}
+
+#[derive(Serialize)]
+pub struct BooruImportEntry {
+    path: String,
+    // "md5" or "filename"
+    matched_by: String,
+    // the tags and sources the image didn't have yet.
+    tags_added: Vec<String>,
+    sources_added: Vec<String>,
+}
+
+impl BooruImportEntry {
+    pub fn new(path: String, matched_by: String, tags_added: Vec<String>, sources_added: Vec<String>) -> Self {
+        BooruImportEntry { path, matched_by, tags_added, sources_added }
+    }
+
+    pub fn get_path(&self) -> &String { &self.path }
+    pub fn get_matched_by(&self) -> &String { &self.matched_by }
+    pub fn get_tags_added(&self) -> &Vec<String> { &self.tags_added }
+    pub fn get_sources_added(&self) -> &Vec<String> { &self.sources_added }
+}
+
+#[derive(Serialize)]
+pub struct BooruImportReport {
+    dry_run: bool,
+    images: Vec<BooruImportEntry>,
+    // md5s or file names of the posts that didn't match any image in the library.
+    unmatched: Vec<String>,
+}
+
+impl BooruImportReport {
+    pub fn new(dry_run: bool, images: Vec<BooruImportEntry>, unmatched: Vec<String>) -> Self {
+        BooruImportReport { dry_run, images, unmatched }
+    }
+
+    pub fn get_dry_run(&self) -> bool { self.dry_run }
+    pub fn get_images(&self) -> &Vec<BooruImportEntry> { &self.images }
+    pub fn get_unmatched(&self) -> &Vec<String> { &self.unmatched }
+}
+
+#[derive(Serialize)]
+pub struct Snapshot {
+    name: String,
+    size: i64,
+    created_at_time: i64,
+}
+
+impl Snapshot {
+    pub fn new(name: String, size: i64, created_at_time: i64) -> Self {
+        Snapshot { name, size, created_at_time }
+    }
+
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_size(&self) -> i64 { self.size }
+    pub fn get_created_at_time(&self) -> i64 { self.created_at_time }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreSnapshotRequest {
+    name: String,
+}
+
+impl RestoreSnapshotRequest {
+    pub fn get_name(&self) -> &String { &self.name }
+}
+
+#[derive(Serialize)]
+pub struct IntegrityReport {
+    // what sqlite's integrity_check found. when this isn't empty nothing is repaired.
+    sqlite_errors: Vec<String>,
+    // tag and image array tables with no image or tag behind them.
+    orphan_tables: Vec<String>,
+    // images and tags that had no array table.
+    missing_tables: Vec<String>,
+    // array table rows that point to a deleted image or tag.
+    dangling_memberships: usize,
+    // image and tag pairs that were only in one of the two array tables.
+    one_sided_memberships: usize,
+    unused_tags: Vec<String>,
+    // tag queue rows of deleted images.
+    orphan_queue_rows: usize,
+    // region, metadata, hash and caption rows of deleted images.
+    orphan_rows: usize,
+    repaired: bool,
+}
+
+impl IntegrityReport {
+    pub fn new(sqlite_errors: Vec<String>, orphan_tables: Vec<String>, missing_tables: Vec<String>,
+               dangling_memberships: usize, one_sided_memberships: usize, unused_tags: Vec<String>,
+               orphan_queue_rows: usize, orphan_rows: usize, repaired: bool) -> Self {
+        IntegrityReport {
+            sqlite_errors, orphan_tables, missing_tables,
+            dangling_memberships, one_sided_memberships, unused_tags,
+            orphan_queue_rows, orphan_rows, repaired
+        }
+    }
+
+    pub fn get_sqlite_errors(&self) -> &Vec<String> { &self.sqlite_errors }
+    pub fn get_orphan_tables(&self) -> &Vec<String> { &self.orphan_tables }
+    pub fn get_missing_tables(&self) -> &Vec<String> { &self.missing_tables }
+    pub fn get_dangling_memberships(&self) -> usize { self.dangling_memberships }
+    pub fn get_one_sided_memberships(&self) -> usize { self.one_sided_memberships }
+    pub fn get_unused_tags(&self) -> &Vec<String> { &self.unused_tags }
+    pub fn get_orphan_queue_rows(&self) -> usize { self.orphan_queue_rows }
+    pub fn get_orphan_rows(&self) -> usize { self.orphan_rows }
+    pub fn get_repaired(&self) -> bool { self.repaired }
+
+    pub fn is_consistent(&self) -> bool {
+        self.sqlite_errors.is_empty()
+            && self.orphan_tables.is_empty()
+            && self.missing_tables.is_empty()
+            && self.dangling_memberships == 0
+            && self.one_sided_memberships == 0
+            && self.unused_tags.is_empty()
+            && self.orphan_queue_rows == 0
+            && self.orphan_rows == 0
+    }
+}
+
+// a new file that could be where a missing image went.
+#[derive(Serialize)]
+pub struct RelinkCandidate {
+    image: Image,
+    // "hash" when the contents are the same, "name" when only the file name and size are.
+    matched_by: String,
+}
+
+impl RelinkCandidate {
+    pub fn new(image: Image, matched_by: String) -> Self {
+        RelinkCandidate { image, matched_by }
+    }
+
+    pub fn get_image(&self) -> &Image { &self.image }
+    pub fn get_matched_by(&self) -> &String { &self.matched_by }
+}
+
+#[derive(Serialize)]
+pub struct MissingImage {
+    image: Image,
+    // best matches first
+    candidates: Vec<RelinkCandidate>,
+}
+
+impl MissingImage {
+    pub fn new(image: Image, candidates: Vec<RelinkCandidate>) -> Self {
+        MissingImage { image, candidates }
+    }
+
+    pub fn get_image(&self) -> &Image { &self.image }
+    pub fn get_candidates(&self) -> &Vec<RelinkCandidate> { &self.candidates }
+}
+
+#[derive(Deserialize)]
+pub struct RelinkRequest {
+    missing_id: i64,
+    new_id: i64,
+}
+
+impl RelinkRequest {
+    pub fn get_missing_id(&self) -> i64 { self.missing_id }
+    pub fn get_new_id(&self) -> i64 { self.new_id }
+}
+
+#[derive(Deserialize)]
+pub struct TrashImageRequest {
+    image_id: i64,
+}
+
+impl TrashImageRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreImageRequest {
+    image_id: i64,
+}
+
+impl RestoreImageRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+}
+
+#[derive(Deserialize)]
+pub struct MoveImageRequest {
+    image_id: i64,
+    // the new file name, within the image's root.
+    path: String,
+}
+
+impl MoveImageRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_path(&self) -> &String { &self.path }
+}
+
+// an image of a gesture drawing session, shown for it's scheduled duration.
+#[derive(Serialize)]
+pub struct GestureSlot {
+    image: Image,
+    duration: i64,
+    // how long the image was up for, once it's done.
+    seconds_spent: Option<i64>,
+    skipped: bool,
+}
+
+impl GestureSlot {
+    pub fn new(image: Image, duration: i64, seconds_spent: Option<i64>, skipped: bool) -> Self {
+        GestureSlot { image, duration, seconds_spent, skipped }
+    }
+
+    pub fn get_image(&self) -> &Image { &self.image }
+    pub fn get_duration(&self) -> i64 { self.duration }
+    pub fn get_seconds_spent(&self) -> Option<i64> { self.seconds_spent }
+    pub fn is_skipped(&self) -> bool { self.skipped }
+}
+
+#[derive(Serialize)]
+pub struct GestureSession {
+    id: i64,
+    query: String,
+    schedule: String,
+    created_at_time: i64,
+    finished_at_time: Option<i64>,
+    paused: bool,
+    // the index of the current slot, the same as the number of slots once the session is finished.
+    position: usize,
+    // how long the current image has left.
+    remaining_seconds: i64,
+    slots: Vec<GestureSlot>,
+}
+
+impl GestureSession {
+    pub fn new(
+        id: i64,
+        query: String,
+        schedule: String,
+        created_at_time: i64,
+        finished_at_time: Option<i64>,
+        paused: bool,
+        position: usize,
+        remaining_seconds: i64,
+        slots: Vec<GestureSlot>) -> Self {
+
+        GestureSession { id, query, schedule, created_at_time, finished_at_time, paused, position, remaining_seconds, slots }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_query(&self) -> &String { &self.query }
+    pub fn get_schedule(&self) -> &String { &self.schedule }
+    pub fn get_created_at_time(&self) -> i64 { self.created_at_time }
+    pub fn get_finished_at_time(&self) -> Option<i64> { self.finished_at_time }
+    pub fn is_paused(&self) -> bool { self.paused }
+    pub fn get_position(&self) -> usize { self.position }
+    pub fn get_remaining_seconds(&self) -> i64 { self.remaining_seconds }
+    pub fn get_slots(&self) -> &Vec<GestureSlot> { &self.slots }
+    pub fn get_current(&self) -> Option<&GestureSlot> { self.slots.get(self.position) }
+    pub fn get_next(&self) -> Option<&GestureSlot> { self.slots.get(self.position + 1) }
+}
+
+#[derive(Deserialize)]
+pub struct CreateGestureSessionRequest {
+    query: String,
+    // like "10x30s, 5x2m, 1x10m".
+    schedule: String,
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+impl CreateGestureSessionRequest {
+    pub fn get_query(&self) -> &String { &self.query }
+    pub fn get_schedule(&self) -> &String { &self.schedule }
+    pub fn get_seed(&self) -> Option<u64> { self.seed }
+}
+
+#[derive(Deserialize)]
+pub struct GestureSessionRequest {
+    session_id: i64,
+}
+
+impl GestureSessionRequest {
+    pub fn get_session_id(&self) -> i64 { self.session_id }
+}
+
+#[derive(Serialize)]
+pub struct ImageStats {
+    image_id: i64,
+    view_count: i64,
+    last_viewed_at: Option<i64>,
+    draw_count: i64,
+    last_drawn_at: Option<i64>,
+}
+
+impl ImageStats {
+    pub fn new(image_id: i64, view_count: i64, last_viewed_at: Option<i64>, draw_count: i64, last_drawn_at: Option<i64>) -> Self {
+        ImageStats { image_id, view_count, last_viewed_at, draw_count, last_drawn_at }
+    }
+
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_view_count(&self) -> i64 { self.view_count }
+    pub fn get_last_viewed_at(&self) -> Option<i64> { self.last_viewed_at }
+    pub fn get_draw_count(&self) -> i64 { self.draw_count }
+    pub fn get_last_drawn_at(&self) -> Option<i64> { self.last_drawn_at }
+}
+
+// a time an image was drawn from, as kept in the practice log.
+#[derive(Serialize)]
+pub struct Drawing {
+    id: i64,
+    image_id: i64,
+    drawn_at_time: i64,
+    // how long the drawing took, if it was given.
+    seconds: Option<i64>,
+}
+
+impl Drawing {
+    pub fn new(id: i64, image_id: i64, drawn_at_time: i64, seconds: Option<i64>) -> Self {
+        Drawing { id, image_id, drawn_at_time, seconds }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_drawn_at_time(&self) -> i64 { self.drawn_at_time }
+    pub fn get_seconds(&self) -> Option<i64> { self.seconds }
+}
+
+#[derive(Deserialize)]
+pub struct RecordViewRequest {
+    image_id: i64,
+}
+
+impl RecordViewRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+}
+
+#[derive(Deserialize)]
+pub struct RecordDrawingRequest {
+    image_id: i64,
+    #[serde(default)]
+    seconds: Option<i64>,
+}
+
+impl RecordDrawingRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_seconds(&self) -> Option<i64> { self.seconds }
+}
+
+// a tag given along with another tag, or to the images a search found.
+#[derive(Serialize)]
+pub struct RelatedTag {
+    id: i64,
+    name: String,
+    // the number of images that have both.
+    count: i64,
+}
+
+impl RelatedTag {
+    pub fn new(id: i64, name: String, count: i64) -> Self {
+        RelatedTag { id, name, count }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_count(&self) -> i64 { self.count }
+}
+
+#[derive(Serialize)]
+pub struct TagSuggestion {
+    id: i64,
+    name: String,
+    // from 0 to 1.
+    confidence: f64,
+    // what suggested the tag: "duplicate", "similar_image", "similar_name", "file_name", "folder" or "recent".
+    sources: Vec<String>,
+}
+
+impl TagSuggestion {
+    pub fn new(id: i64, name: String, confidence: f64, sources: Vec<String>) -> Self {
+        TagSuggestion { id, name, confidence, sources }
+    }
+
+    pub fn get_id(&self) -> i64 { self.id }
+    pub fn get_name(&self) -> &String { &self.name }
+    pub fn get_confidence(&self) -> f64 { self.confidence }
+    pub fn get_sources(&self) -> &Vec<String> { &self.sources }
+}
+
+#[derive(Deserialize)]
+pub struct DeferImageRequest {
+    image_id: i64,
+    // unix time the image comes back to the tag queue at. a day from now when it's not given.
+    #[serde(default)]
+    until: Option<i64>,
+}
+
+impl DeferImageRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+    pub fn get_until(&self) -> Option<i64> { self.until }
+}
+
+// for marking an image in the tag queue for review, or as done.
+#[derive(Deserialize)]
+pub struct QueueImageRequest {
+    image_id: i64,
+}
+
+impl QueueImageRequest {
+    pub fn get_image_id(&self) -> i64 { self.image_id }
+}